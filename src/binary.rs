@@ -0,0 +1,305 @@
+use std::io::Write;
+
+use crate::{path_from_bytes, path_to_bytes, DirDiff, DirDiffFileContent};
+
+const MAGIC: &[u8; 4] = b"DDB1";
+
+fn status_code(diff_fragment: &DirDiff<std::path::PathBuf>) -> u8 {
+    // Return the code of the status of an entry. The codes are part of the wire format, so they
+    // must never change, only new ones be added
+
+    match diff_fragment {
+        DirDiff::Removed(_) => 0,
+        DirDiff::Added(_) => 1,
+        DirDiff::Similar(_, None) => 2,
+        DirDiff::Similar(_, Some(DirDiffFileContent::Unchanged)) => 3,
+        DirDiff::Similar(_, Some(DirDiffFileContent::Changed)) => 4,
+        DirDiff::Similar(_, Some(DirDiffFileContent::Special)) => 5,
+        DirDiff::Similar(_, Some(DirDiffFileContent::Inaccessible)) => 6,
+        DirDiff::Renamed(_, _) => 7,
+        DirDiff::RenamedChanged(_, _, _) => 8,
+        DirDiff::CaseChanged(_, _, _) => 9,
+    }
+}
+
+fn content_code(file_content_similarity: &Option<DirDiffFileContent>) -> u8 {
+    match file_content_similarity {
+        None => 0,
+        Some(DirDiffFileContent::Unchanged) => 1,
+        Some(DirDiffFileContent::Changed) => 2,
+        Some(DirDiffFileContent::Special) => 3,
+        Some(DirDiffFileContent::Inaccessible) => 4,
+    }
+}
+
+fn content_from_code(code: u8) -> Option<Option<DirDiffFileContent>> {
+    // Return the content of a content code, or None if the code is unknown
+
+    match code {
+        0 => Some(None),
+        1 => Some(Some(DirDiffFileContent::Unchanged)),
+        2 => Some(Some(DirDiffFileContent::Changed)),
+        3 => Some(Some(DirDiffFileContent::Special)),
+        4 => Some(Some(DirDiffFileContent::Inaccessible)),
+        _ => None,
+    }
+}
+
+fn push_path(frame: &mut Vec<u8>, path: &std::path::Path) {
+    // Append the length of the path and its bytes to a frame
+
+    let bytes = path_to_bytes(path);
+
+    frame.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&bytes);
+}
+
+fn entry_frame(diff_fragment: &DirDiff<std::path::PathBuf>) -> Vec<u8> {
+    // Return the payload of the frame of an entry, without its length
+
+    let mut frame = vec![status_code(diff_fragment)];
+
+    push_path(&mut frame, diff_fragment.path());
+
+    match diff_fragment {
+        DirDiff::Renamed(_, to) => push_path(&mut frame, to),
+        DirDiff::RenamedChanged(_, to, similarity) => {
+            push_path(&mut frame, to);
+            frame.push(*similarity);
+        }
+        DirDiff::CaseChanged(_, to, file_content_similarity) => {
+            push_path(&mut frame, to);
+            frame.push(content_code(file_content_similarity));
+        }
+        _ => {}
+    }
+
+    frame
+}
+
+pub fn print_binary(
+    out: &mut impl Write,
+    dir_diff: &[DirDiff<std::path::PathBuf>],
+    hide_similarities: bool,
+) -> std::io::Result<()> {
+    // Write the diff as a stream of length-prefixed frames, one per entry, for programs that
+    // read a lot of entries and don't want to parse text. The format is described in the README,
+    // which must be kept in sync with this function and read_binary
+
+    out.write_all(MAGIC)?;
+
+    for diff_fragment in dir_diff {
+        if hide_similarities && matches!(diff_fragment, DirDiff::Similar(..)) {
+            continue;
+        }
+
+        let frame = entry_frame(diff_fragment);
+
+        out.write_all(&(frame.len() as u32).to_le_bytes())?;
+        out.write_all(&frame)?;
+    }
+
+    out.write_all(&0u32.to_le_bytes())?;
+
+    Ok(())
+}
+
+fn take_path(frame: &[u8]) -> Option<(std::path::PathBuf, &[u8])> {
+    // Return the path at the start of a frame and the rest of the frame, or None if it is cut
+    // short
+
+    let (length, rest) = frame.split_first_chunk::<4>()?;
+    let length = u32::from_le_bytes(*length) as usize;
+
+    (rest.len() >= length).then(|| (path_from_bytes(&rest[..length]), &rest[length..]))
+}
+
+fn parse_frame(frame: &[u8]) -> Option<DirDiff<std::path::PathBuf>> {
+    // Return the entry of the payload of a frame, or None if it is invalid. Bytes after the known
+    // fields are ignored, as later versions may add fields
+
+    let (&status, rest) = frame.split_first()?;
+    let (path, rest) = take_path(rest)?;
+
+    Some(match status {
+        0 => DirDiff::Removed(path),
+        1 => DirDiff::Added(path),
+        // the similar statuses are in the order of the content codes
+        2..=6 => DirDiff::Similar(path, content_from_code(status - 2)?),
+        7 => DirDiff::Renamed(path, take_path(rest)?.0),
+        8 => {
+            let (to, rest) = take_path(rest)?;
+            DirDiff::RenamedChanged(path, to, *rest.first()?)
+        }
+        9 => {
+            let (to, rest) = take_path(rest)?;
+            DirDiff::CaseChanged(path, to, content_from_code(*rest.first()?)?)
+        }
+        _ => return None,
+    })
+}
+
+// the reference reader of the format, only used by the tests in this binary
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn read_binary(
+    input: &mut impl std::io::Read,
+) -> std::io::Result<Vec<DirDiff<std::path::PathBuf>>> {
+    // Read the entries of a stream written by print_binary. A stream without its end frame is
+    // an UnexpectedEof error, and an invalid one an InvalidData error
+
+    let invalid_data =
+        |message: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string());
+
+    let mut magic = [0; 4];
+    input.read_exact(&mut magic)?;
+
+    if &magic != MAGIC {
+        return Err(invalid_data("not a dirdiff binary stream"));
+    }
+
+    let mut dir_diff = Vec::new();
+
+    loop {
+        let mut length = [0; 4];
+        input.read_exact(&mut length)?;
+
+        let mut frame = vec![0; u32::from_le_bytes(length) as usize];
+
+        if frame.is_empty() {
+            return Ok(dir_diff);
+        }
+
+        input.read_exact(&mut frame)?;
+
+        dir_diff.push(parse_frame(&frame).ok_or_else(|| invalid_data("invalid frame"))?);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(
+        dir_diff: &[DirDiff<std::path::PathBuf>],
+        hide_similarities: bool,
+    ) -> Vec<String> {
+        let mut stream = Vec::new();
+
+        print_binary(&mut stream, dir_diff, hide_similarities).unwrap();
+
+        read_binary(&mut stream.as_slice())
+            .unwrap()
+            .iter()
+            .map(|diff_fragment| format!("{:?}", diff_fragment))
+            .collect()
+    }
+
+    fn every_status() -> Vec<DirDiff<std::path::PathBuf>> {
+        vec![
+            DirDiff::Removed("removed".into()),
+            DirDiff::Added("dir/added".into()),
+            DirDiff::Similar("similar".into(), None),
+            DirDiff::Similar("unchanged".into(), Some(DirDiffFileContent::Unchanged)),
+            DirDiff::Similar("changed".into(), Some(DirDiffFileContent::Changed)),
+            DirDiff::Similar("special".into(), Some(DirDiffFileContent::Special)),
+            DirDiff::Similar(
+                "inaccessible".into(),
+                Some(DirDiffFileContent::Inaccessible),
+            ),
+            DirDiff::Renamed("from".into(), "to".into()),
+            DirDiff::RenamedChanged("from".into(), "to".into(), 87),
+            DirDiff::CaseChanged("Readme.md".into(), "readme.md".into(), None),
+            DirDiff::CaseChanged(
+                "Readme.md".into(),
+                "README.md".into(),
+                Some(DirDiffFileContent::Changed),
+            ),
+            DirDiff::Added("".into()),
+        ]
+    }
+
+    #[test]
+    fn round_trips_every_status() {
+        let dir_diff = every_status();
+
+        let expected: Vec<String> = dir_diff
+            .iter()
+            .map(|diff_fragment| format!("{:?}", diff_fragment))
+            .collect();
+
+        assert_eq!(round_trip(&dir_diff, false), expected);
+    }
+
+    #[test]
+    fn writes_the_documented_frames() {
+        let mut stream = Vec::new();
+
+        print_binary(
+            &mut stream,
+            &[DirDiff::RenamedChanged("a".into(), "bc".into(), 87)],
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(
+            stream,
+            [
+                b"DDB1".as_slice(),
+                &[13, 0, 0, 0],
+                &[8],
+                &[1, 0, 0, 0, b'a'],
+                &[2, 0, 0, 0, b'b', b'c'],
+                &[87],
+                &[0, 0, 0, 0],
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn leaves_out_similar_entries_when_hiding_them() {
+        let kept: Vec<String> = every_status()
+            .iter()
+            .filter(|diff_fragment| !matches!(diff_fragment, DirDiff::Similar(..)))
+            .map(|diff_fragment| format!("{:?}", diff_fragment))
+            .collect();
+
+        assert_eq!(round_trip(&every_status(), true), kept);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_paths_that_are_not_utf8() {
+        let path = path_from_bytes(b"caf\xe9");
+
+        assert_eq!(
+            round_trip(&[DirDiff::Removed(path.clone())], false),
+            [format!("{:?}", DirDiff::Removed(path))]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_streams() {
+        let mut stream = Vec::new();
+        print_binary(&mut stream, &every_status(), false).unwrap();
+
+        // without the end frame
+        let cut_stream = &stream[..stream.len() - 4];
+        assert_eq!(
+            read_binary(&mut &cut_stream[..]).unwrap_err().kind(),
+            std::io::ErrorKind::UnexpectedEof
+        );
+
+        assert_eq!(
+            read_binary(&mut b"JSON".as_slice()).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidData
+        );
+
+        // a path longer than its frame
+        let bad_frame = [b"DDB1".as_slice(), &[5, 0, 0, 0, 0, 9, 0, 0, 0]].concat();
+        assert_eq!(
+            read_binary(&mut bad_frame.as_slice()).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidData
+        );
+    }
+}
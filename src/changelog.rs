@@ -0,0 +1,83 @@
+use std::io::Write;
+
+use crate::{DirDiff, DirDiffFileContent};
+
+pub fn print_changelog(
+    out: &mut impl Write,
+    dir_diff: &[DirDiff<std::path::PathBuf>],
+) -> std::io::Result<()> {
+    // Print the differences grouped by kind, a line per kind listing its paths ("Added: a, b"), to
+    // paste in commit messages or release notes. Kinds without paths are left out
+
+    let mut added = Vec::new();
+    let mut removed = Vec::new();
+    let mut changed = Vec::new();
+    let mut renamed = Vec::new();
+
+    for diff_fragment in dir_diff {
+        match diff_fragment {
+            DirDiff::Added(path) => added.push(path.display().to_string()),
+            DirDiff::Removed(path) => removed.push(path.display().to_string()),
+            DirDiff::Similar(path, Some(DirDiffFileContent::Changed)) => {
+                changed.push(path.display().to_string())
+            }
+            DirDiff::Renamed(from, to)
+            | DirDiff::RenamedChanged(from, to, _)
+            | DirDiff::CaseChanged(from, to, _) => {
+                renamed.push(format!("{} -> {}", from.display(), to.display()))
+            }
+            DirDiff::Similar(_, _) => {}
+        }
+    }
+
+    let groups = [
+        ("Added", added),
+        ("Removed", removed),
+        ("Changed", changed),
+        ("Renamed", renamed),
+    ];
+
+    if groups.iter().all(|(_, paths)| paths.is_empty()) {
+        return writeln!(out, "No changes");
+    }
+
+    for (kind, paths) in groups {
+        if !paths.is_empty() {
+            writeln!(out, "{}: {}", kind, paths.join(", "))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn changelog(dir_diff: &[DirDiff<std::path::PathBuf>]) -> String {
+        let mut out = Vec::new();
+        print_changelog(&mut out, dir_diff).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn groups_the_paths_by_kind_of_difference() {
+        let dir_diff = [
+            DirDiff::Added("a".into()),
+            DirDiff::Similar("b".into(), Some(DirDiffFileContent::Changed)),
+            DirDiff::Removed("c".into()),
+            DirDiff::Added("docs/d".into()),
+            DirDiff::Similar("docs".into(), None),
+            DirDiff::Similar("e".into(), Some(DirDiffFileContent::Unchanged)),
+            DirDiff::RenamedChanged("f".into(), "g".into(), 80),
+        ];
+
+        // in the order of the diff within each kind, kinds without paths left out
+        assert_eq!(
+            changelog(&dir_diff),
+            "Added: a, docs/d\nRemoved: c\nChanged: b\nRenamed: f -> g\n"
+        );
+        assert_eq!(changelog(&dir_diff[..2]), "Added: a\nChanged: b\n");
+        assert_eq!(changelog(&dir_diff[4..6]), "No changes\n");
+    }
+}
@@ -0,0 +1,1233 @@
+use crate::{find_target_dir, DirDiff, DirDiffError, DirDiffFileContent};
+
+pub trait ContentComparator: Sync {
+    // Return whether two regular files have the same content
+    fn compare(
+        &self,
+        source_file_path: &std::path::Path,
+        target_file_path: &std::path::Path,
+    ) -> std::io::Result<DirDiffFileContent>;
+}
+
+pub fn read_chunk(reader: &mut impl std::io::Read, buffer: &mut [u8]) -> std::io::Result<usize> {
+    // Fill the buffer as much as possible, since a single read can return less than was asked
+    // before the end of the file. Return the number of bytes read
+
+    let mut length = 0;
+
+    while length < buffer.len() {
+        match reader.read(&mut buffer[length..]) {
+            Ok(0) => break,
+            Ok(read_length) => length += read_length,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(length)
+}
+
+pub fn streaming_equal(
+    source_file_path: &std::path::Path,
+    target_file_path: &std::path::Path,
+    buffer_size: usize,
+) -> std::io::Result<bool> {
+    // Return whether both files have the same content, reading them in chunks of buffer_size bytes
+    // and stopping at the first chunk that differs, so big files are never fully in memory.
+    // A file written to while it is read gives no consistent content to compare, so it is
+    // different, whatever the reads happened to see
+
+    let source_file = std::fs::File::open(source_file_path)?;
+    let target_file = std::fs::File::open(target_file_path)?;
+
+    unchanged_readers_equal(
+        (&source_file, &mut &source_file),
+        (&target_file, &mut &target_file),
+        buffer_size,
+    )
+}
+
+fn unchanged_readers_equal(
+    (source_file, source): (&std::fs::File, &mut impl std::io::Read),
+    (target_file, target): (&std::fs::File, &mut impl std::io::Read),
+    buffer_size: usize,
+) -> std::io::Result<bool> {
+    // Return whether the readers of both files give the same bytes, or false if one of the files
+    // changed size or modification time while it was read
+
+    let source_state = file_state(source_file)?;
+    let target_state = file_state(target_file)?;
+
+    if source_state.0 != target_state.0 {
+        return Ok(false);
+    }
+
+    let equal = readers_equal(source, target, buffer_size)?;
+
+    if file_state(source_file)? != source_state || file_state(target_file)? != target_state {
+        return Ok(false);
+    }
+
+    Ok(equal)
+}
+
+fn readers_equal(
+    source: &mut impl std::io::Read,
+    target: &mut impl std::io::Read,
+    buffer_size: usize,
+) -> std::io::Result<bool> {
+    // Return whether both readers give the same bytes, reading them in chunks of buffer_size bytes
+    // and stopping at the first chunk that differs
+
+    let mut source_buffer = vec![0; buffer_size];
+    let mut target_buffer = vec![0; buffer_size];
+
+    loop {
+        let source_length = read_chunk(source, &mut source_buffer)?;
+        let target_length = read_chunk(target, &mut target_buffer)?;
+
+        if source_buffer[..source_length] != target_buffer[..target_length] {
+            return Ok(false);
+        }
+
+        if source_length == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+pub fn differs_by_final_newline(
+    source_file_path: &std::path::Path,
+    target_file_path: &std::path::Path,
+) -> std::io::Result<bool> {
+    // Return whether one file is the other followed by a newline (\n or \r\n). Only files whose
+    // sizes differ by one or two bytes are read
+
+    let source_file_size = std::fs::metadata(source_file_path)?.len();
+    let target_file_size = std::fs::metadata(target_file_path)?.len();
+
+    if !matches!(source_file_size.abs_diff(target_file_size), 1 | 2) {
+        return Ok(false);
+    }
+
+    let source_content = std::fs::read(source_file_path)?;
+    let target_content = std::fs::read(target_file_path)?;
+
+    let (shorter, longer) = match source_content.len() < target_content.len() {
+        true => (source_content, target_content),
+        false => (target_content, source_content),
+    };
+
+    Ok(longer
+        .strip_prefix(shorter.as_slice())
+        .is_some_and(|end| end == b"\n" || end == b"\r\n"))
+}
+
+pub fn creation_times_differ(
+    source_file_path: &std::path::Path,
+    target_file_path: &std::path::Path,
+) -> bool {
+    // Return whether both files were created at different times. Creation times are not available
+    // on every platform and file system, and files without one are not considered different
+
+    let created = |path: &std::path::Path| std::fs::metadata(path).and_then(|m| m.created());
+
+    match (created(source_file_path), created(target_file_path)) {
+        (Ok(source_created), Ok(target_created)) => source_created != target_created,
+        _ => false,
+    }
+}
+
+pub fn find_creation_time_changes(
+    dir_diff: &[DirDiff<std::path::PathBuf>],
+    source_dir: &std::path::Path,
+    target_dirs: &[std::path::PathBuf],
+) -> std::collections::HashSet<std::path::PathBuf> {
+    // Return the changed files whose creation times differ
+
+    dir_diff
+        .iter()
+        .filter_map(|diff_fragment| match diff_fragment {
+            DirDiff::Similar(path, Some(DirDiffFileContent::Changed)) => Some(path),
+            _ => None,
+        })
+        .filter(|path| {
+            creation_times_differ(
+                &source_dir.join(path),
+                &find_target_dir(target_dirs, path).join(path),
+            )
+        })
+        .cloned()
+        .collect()
+}
+
+pub fn find_final_newline_changes(
+    dir_diff: &[DirDiff<std::path::PathBuf>],
+    source_dir: &std::path::Path,
+    target_dirs: &[std::path::PathBuf],
+) -> Result<std::collections::HashSet<std::path::PathBuf>, DirDiffError> {
+    // Return the changed files that only differ by a newline at the end of one of them
+
+    let mut final_newline_changes = std::collections::HashSet::new();
+
+    for diff_fragment in dir_diff {
+        let path = match diff_fragment {
+            DirDiff::Similar(path, Some(DirDiffFileContent::Changed)) => path,
+            _ => continue,
+        };
+
+        let source_file_path = source_dir.join(path);
+        let target_file_path = find_target_dir(target_dirs, path).join(path);
+
+        if !source_file_path.is_file() || !target_file_path.is_file() {
+            continue;
+        }
+
+        if differs_by_final_newline(&source_file_path, &target_file_path).map_err(|source| {
+            DirDiffError::Compare {
+                path: path.clone(),
+                source,
+            }
+        })? {
+            final_newline_changes.insert(path.clone());
+        }
+    }
+
+    Ok(final_newline_changes)
+}
+
+// compares the content of both files chunk by chunk
+pub struct ByteComparator {
+    pub buffer_size: usize, // size of the chunks read from each file
+}
+
+impl ContentComparator for ByteComparator {
+    fn compare(
+        &self,
+        source_file_path: &std::path::Path,
+        target_file_path: &std::path::Path,
+    ) -> std::io::Result<DirDiffFileContent> {
+        let source_file_size = std::fs::metadata(source_file_path)?.len();
+        let target_file_size = std::fs::metadata(target_file_path)?.len();
+
+        // files of different sizes can't have the same content, and two empty files always do
+        if source_file_size != target_file_size {
+            return Ok(DirDiffFileContent::Changed);
+        } else if source_file_size == 0 {
+            return Ok(DirDiffFileContent::Unchanged);
+        }
+
+        if streaming_equal(source_file_path, target_file_path, self.buffer_size)? {
+            Ok(DirDiffFileContent::Unchanged)
+        } else {
+            Ok(DirDiffFileContent::Changed)
+        }
+    }
+}
+
+// compares the content of both files mapped in memory
+pub struct MmapComparator {
+    pub buffer_size: usize, // size of the chunks read from files that can't be mapped
+}
+
+fn file_state(file: &std::fs::File) -> std::io::Result<(u64, Option<std::time::SystemTime>)> {
+    // Return the size and modification time of an open file, to notice if it changes
+    let metadata = file.metadata()?;
+    Ok((metadata.len(), metadata.modified().ok()))
+}
+
+fn mapped_equal(
+    source_file_path: &std::path::Path,
+    target_file_path: &std::path::Path,
+) -> std::io::Result<Option<bool>> {
+    // Return whether both files have the same content by comparing their mapped bytes, or None if
+    // one of them couldn't be mapped or changed while it was compared
+
+    let source_file = std::fs::File::open(source_file_path)?;
+    let target_file = std::fs::File::open(target_file_path)?;
+
+    let source_state = file_state(&source_file)?;
+    let target_state = file_state(&target_file)?;
+
+    // SAFETY: another process can modify or truncate the files while they are mapped, which is
+    // why the files are compared again (by reading them) if their size or modification time
+    // changed. Reading past the end of a truncated mapping can still kill the process, which is
+    // the price of --mmap on files that are being written
+    let source_map = match unsafe { memmap2::Mmap::map(&source_file) } {
+        Ok(source_map) => source_map,
+        Err(_) => return Ok(None),
+    };
+    let target_map = match unsafe { memmap2::Mmap::map(&target_file) } {
+        Ok(target_map) => target_map,
+        Err(_) => return Ok(None),
+    };
+
+    let equal = source_map[..] == target_map[..];
+
+    if file_state(&source_file)? != source_state || file_state(&target_file)? != target_state {
+        return Ok(None);
+    }
+
+    Ok(Some(equal))
+}
+
+impl ContentComparator for MmapComparator {
+    fn compare(
+        &self,
+        source_file_path: &std::path::Path,
+        target_file_path: &std::path::Path,
+    ) -> std::io::Result<DirDiffFileContent> {
+        let source_file_size = std::fs::metadata(source_file_path)?.len();
+        let target_file_size = std::fs::metadata(target_file_path)?.len();
+
+        // files of different sizes can't have the same content, and empty files can't be mapped
+        if source_file_size != target_file_size {
+            return Ok(DirDiffFileContent::Changed);
+        } else if source_file_size == 0 {
+            return Ok(DirDiffFileContent::Unchanged);
+        }
+
+        let equal = match mapped_equal(source_file_path, target_file_path)? {
+            Some(equal) => equal,
+            None => streaming_equal(source_file_path, target_file_path, self.buffer_size)?,
+        };
+
+        if equal {
+            Ok(DirDiffFileContent::Unchanged)
+        } else {
+            Ok(DirDiffFileContent::Changed)
+        }
+    }
+}
+
+// compares the size and the start and end of both files, a fast but approximate comparison
+pub struct SampleComparator {
+    pub sample_size: usize, // number of bytes compared at the start and at the end
+}
+
+fn read_samples(file_path: &std::path::Path, sample_size: usize) -> std::io::Result<Vec<u8>> {
+    // Return the first and last sample_size bytes of a file (all of it if it is smaller)
+    use std::io::Seek;
+
+    let mut file = std::fs::File::open(file_path)?;
+    let file_size = file.metadata()?.len();
+
+    let mut samples = vec![0; sample_size.min(file_size as usize)];
+    let length = read_chunk(&mut file, &mut samples)?;
+    samples.truncate(length);
+
+    // the end overlaps the start in files smaller than two samples
+    let end_start = file_size
+        .saturating_sub(sample_size as u64)
+        .max(length as u64);
+
+    if end_start < file_size {
+        file.seek(std::io::SeekFrom::Start(end_start))?;
+
+        let mut end_sample = vec![0; (file_size - end_start) as usize];
+        let length = read_chunk(&mut file, &mut end_sample)?;
+        samples.extend_from_slice(&end_sample[..length]);
+    }
+
+    Ok(samples)
+}
+
+impl ContentComparator for SampleComparator {
+    fn compare(
+        &self,
+        source_file_path: &std::path::Path,
+        target_file_path: &std::path::Path,
+    ) -> std::io::Result<DirDiffFileContent> {
+        let source_file_size = std::fs::metadata(source_file_path)?.len();
+        let target_file_size = std::fs::metadata(target_file_path)?.len();
+
+        if source_file_size != target_file_size {
+            return Ok(DirDiffFileContent::Changed);
+        }
+
+        if read_samples(source_file_path, self.sample_size)?
+            == read_samples(target_file_path, self.sample_size)?
+        {
+            Ok(DirDiffFileContent::Unchanged)
+        } else {
+            Ok(DirDiffFileContent::Changed)
+        }
+    }
+}
+
+pub struct SameFileComparator; // for files known to be the same file, without reading them
+
+impl ContentComparator for SameFileComparator {
+    fn compare(
+        &self,
+        _source_file_path: &std::path::Path,
+        _target_file_path: &std::path::Path,
+    ) -> std::io::Result<DirDiffFileContent> {
+        Ok(DirDiffFileContent::Unchanged)
+    }
+}
+
+// compares text files after normalizing them, and binary files byte by byte
+pub struct TextComparator {
+    pub ignore_case: bool, // text differing only in letter case is the same
+    pub ignored_lines: Vec<regex::Regex>, // lines matching one of these are left out
+    pub ignore_final_newline: bool, // a newline at the end of the text is left out
+    pub head_lines: Option<usize>, // only this many lines at the start of the files are compared
+}
+
+fn read_text(content: Vec<u8>) -> Result<String, Vec<u8>> {
+    // Return the content as text, or give it back if it is binary (contains a NUL byte or is not
+    // UTF-8)
+
+    if content.contains(&0) {
+        return Err(content);
+    }
+
+    String::from_utf8(content).map_err(|e| e.into_bytes())
+}
+
+fn read_head_lines(path: &std::path::Path, line_count: usize) -> std::io::Result<Vec<u8>> {
+    // Return the first lines of a file, with their line endings, without reading the rest. The
+    // whole file is returned if it has fewer lines
+    use std::io::BufRead;
+
+    let mut reader = std::io::BufReader::new(std::fs::File::open(path)?);
+    let mut content = Vec::new();
+
+    for _ in 0..line_count {
+        if reader.read_until(b'\n', &mut content)? == 0 {
+            break;
+        }
+    }
+
+    Ok(content)
+}
+
+impl TextComparator {
+    fn read(&self, path: &std::path::Path) -> std::io::Result<Vec<u8>> {
+        match self.head_lines {
+            Some(line_count) => read_head_lines(path, line_count),
+            None => std::fs::read(path),
+        }
+    }
+
+    fn normalize(&self, text: String) -> String {
+        // Return the text without the ignored lines (and final newline), in lower case if case is
+        // ignored. Other line endings are kept, so \n and \r\n still differ
+
+        let mut text = if self.ignored_lines.is_empty() {
+            text
+        } else {
+            text.split_inclusive('\n')
+                .filter(|line| {
+                    let line = line.trim_end_matches(['\n', '\r']);
+                    !self.ignored_lines.iter().any(|regex| regex.is_match(line))
+                })
+                .collect()
+        };
+
+        if self.ignore_final_newline {
+            let text_length = text.strip_suffix('\n').map_or(text.len(), |text| {
+                text.strip_suffix('\r').unwrap_or(text).len()
+            });
+            text.truncate(text_length);
+        }
+
+        if self.ignore_case {
+            text.to_lowercase()
+        } else {
+            text
+        }
+    }
+}
+
+impl ContentComparator for TextComparator {
+    fn compare(
+        &self,
+        source_file_path: &std::path::Path,
+        target_file_path: &std::path::Path,
+    ) -> std::io::Result<DirDiffFileContent> {
+        // normalizing can change the length of the text, so the sizes can't be compared first
+        let source_file_content = read_text(self.read(source_file_path)?);
+        let target_file_content = read_text(self.read(target_file_path)?);
+
+        let unchanged = match (source_file_content, target_file_content) {
+            (Ok(source_text), Ok(target_text)) => {
+                self.normalize(source_text) == self.normalize(target_text)
+            }
+            // binary files are compared byte by byte, whole since they have no lines
+            (Err(_), Err(_)) if self.head_lines.is_some() => {
+                std::fs::read(source_file_path)? == std::fs::read(target_file_path)?
+            }
+            (Err(source_bytes), Err(target_bytes)) => source_bytes == target_bytes,
+            _ => false,
+        };
+
+        if unchanged {
+            Ok(DirDiffFileContent::Unchanged)
+        } else {
+            Ok(DirDiffFileContent::Changed)
+        }
+    }
+}
+
+pub struct JsonComparator {
+    pub comparator: Box<dyn ContentComparator>, // compares the files that are not valid JSON
+}
+
+fn is_json_file(path: &std::path::Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"))
+}
+
+impl ContentComparator for JsonComparator {
+    fn compare(
+        &self,
+        source_file_path: &std::path::Path,
+        target_file_path: &std::path::Path,
+    ) -> std::io::Result<DirDiffFileContent> {
+        // Compare .json files by their parsed values, so key order and whitespace don't matter
+
+        if !is_json_file(source_file_path) || !is_json_file(target_file_path) {
+            return self.comparator.compare(source_file_path, target_file_path);
+        }
+
+        let source_value =
+            serde_json::from_slice::<serde_json::Value>(&std::fs::read(source_file_path)?);
+        let target_value =
+            serde_json::from_slice::<serde_json::Value>(&std::fs::read(target_file_path)?);
+
+        match (source_value, target_value) {
+            (Ok(source_value), Ok(target_value)) if source_value == target_value => {
+                Ok(DirDiffFileContent::Unchanged)
+            }
+            (Ok(_), Ok(_)) => Ok(DirDiffFileContent::Changed),
+            // malformed JSON is compared like other files
+            _ => self.comparator.compare(source_file_path, target_file_path),
+        }
+    }
+}
+
+pub struct ArchiveComparator {
+    pub comparator: Box<dyn ContentComparator>, // compares the files that are not valid archives
+    pub buffer_size: usize,                     // bytes read at once from each entry
+}
+
+// extensions of the archives in the zip format
+const ARCHIVE_EXTENSIONS: &[&str] = &["zip", "jar", "war", "ear", "apk", "aar", "whl"];
+
+fn is_archive_file(path: &std::path::Path) -> bool {
+    path.extension().is_some_and(|extension| {
+        ARCHIVE_EXTENSIONS
+            .iter()
+            .any(|archive_extension| extension.eq_ignore_ascii_case(archive_extension))
+    })
+}
+
+fn archive_entries_equal(
+    source_archive: &mut zip::ZipArchive<std::fs::File>,
+    target_archive: &mut zip::ZipArchive<std::fs::File>,
+    buffer_size: usize,
+) -> zip::result::ZipResult<bool> {
+    // Return whether both archives have the same entries with the same decompressed content. The
+    // order of the entries, their compression and their timestamps don't matter
+
+    let source_names: std::collections::BTreeSet<String> =
+        source_archive.file_names().map(String::from).collect();
+    let target_names: std::collections::BTreeSet<String> =
+        target_archive.file_names().map(String::from).collect();
+
+    if source_names != target_names {
+        return Ok(false);
+    }
+
+    for name in &source_names {
+        let mut source_entry = source_archive.by_name(name)?;
+        let mut target_entry = target_archive.by_name(name)?;
+
+        if source_entry.is_dir() != target_entry.is_dir() {
+            return Ok(false);
+        }
+
+        if source_entry.size() != target_entry.size()
+            || !readers_equal(&mut source_entry, &mut target_entry, buffer_size)?
+        {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+impl ContentComparator for ArchiveComparator {
+    fn compare(
+        &self,
+        source_file_path: &std::path::Path,
+        target_file_path: &std::path::Path,
+    ) -> std::io::Result<DirDiffFileContent> {
+        // Compare zip archives (and jars, which are zips) by their entries instead of their bytes,
+        // so archives packed again with the same content are unchanged
+
+        if !is_archive_file(source_file_path) || !is_archive_file(target_file_path) {
+            return self.comparator.compare(source_file_path, target_file_path);
+        }
+
+        let source_archive = zip::ZipArchive::new(std::fs::File::open(source_file_path)?);
+        let target_archive = zip::ZipArchive::new(std::fs::File::open(target_file_path)?);
+
+        let (mut source_archive, mut target_archive) = match (source_archive, target_archive) {
+            (Ok(source_archive), Ok(target_archive)) => (source_archive, target_archive),
+            // files that aren't valid archives are compared like other files
+            _ => return self.comparator.compare(source_file_path, target_file_path),
+        };
+
+        match archive_entries_equal(&mut source_archive, &mut target_archive, self.buffer_size) {
+            Ok(true) => Ok(DirDiffFileContent::Unchanged),
+            Ok(false) => Ok(DirDiffFileContent::Changed),
+            Err(zip::result::ZipError::Io(e)) => Err(e),
+            // corrupt or unsupported entries (encrypted ones, unknown compression methods)
+            Err(_) => self.comparator.compare(source_file_path, target_file_path),
+        }
+    }
+}
+
+pub struct RuleComparator {
+    pub source_dir: std::path::PathBuf, // the relative paths of the files are matched by the rules
+    pub rules: Vec<(glob::Pattern, Box<dyn ContentComparator>)>, // the first match compares
+    pub comparator: Box<dyn ContentComparator>, // compares the files no rule matches
+}
+
+impl ContentComparator for RuleComparator {
+    fn compare(
+        &self,
+        source_file_path: &std::path::Path,
+        target_file_path: &std::path::Path,
+    ) -> std::io::Result<DirDiffFileContent> {
+        // Compare the files with the comparator of the first rule matching their relative path,
+        // like --exclude patterns
+
+        let relative_path = source_file_path
+            .strip_prefix(&self.source_dir)
+            .unwrap_or(source_file_path);
+
+        let comparator = self
+            .rules
+            .iter()
+            .find(|(pattern, _)| pattern.matches_path(relative_path))
+            .map_or(&self.comparator, |(_, comparator)| comparator);
+
+        comparator.compare(source_file_path, target_file_path)
+    }
+}
+
+// longest wait between two attempts of RetryComparator
+const MAX_RETRY_BACKOFF: std::time::Duration = std::time::Duration::from_secs(5);
+
+fn is_transient(error: &std::io::Error) -> bool {
+    // Return whether reading again may succeed. Missing files and denied permissions won't change
+    // by waiting, and an interrupted read must stop at once
+
+    !matches!(
+        error.kind(),
+        std::io::ErrorKind::NotFound
+            | std::io::ErrorKind::PermissionDenied
+            | std::io::ErrorKind::Interrupted
+    )
+}
+
+pub struct RetryComparator {
+    pub comparator: Box<dyn ContentComparator>, // the comparator doing the actual reads
+    pub retries: u32,                           // number of attempts after the first failure
+}
+
+impl ContentComparator for RetryComparator {
+    fn compare(
+        &self,
+        source_file_path: &std::path::Path,
+        target_file_path: &std::path::Path,
+    ) -> std::io::Result<DirDiffFileContent> {
+        // Retry comparisons failing with transient errors, waiting twice as long after every
+        // failure (starting at 100ms, up to MAX_RETRY_BACKOFF), since reads on network file
+        // systems can fail transiently
+
+        let mut backoff = std::time::Duration::from_millis(100);
+        let mut attempt = 0;
+
+        loop {
+            match self.comparator.compare(source_file_path, target_file_path) {
+                Err(e) if attempt < self.retries && is_transient(&e) => {
+                    std::thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_RETRY_BACKOFF);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // fails with the given error kinds, in order, then finds the files unchanged
+    struct FailingComparator {
+        errors: std::sync::Mutex<Vec<std::io::ErrorKind>>,
+        calls: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl ContentComparator for FailingComparator {
+        fn compare(
+            &self,
+            _source_file_path: &std::path::Path,
+            _target_file_path: &std::path::Path,
+        ) -> std::io::Result<DirDiffFileContent> {
+            self.calls
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
+            match self.errors.lock().unwrap().pop() {
+                Some(kind) => Err(std::io::Error::from(kind)),
+                None => Ok(DirDiffFileContent::Unchanged),
+            }
+        }
+    }
+
+    fn retry(
+        errors: &[std::io::ErrorKind],
+        retries: u32,
+    ) -> (std::io::Result<DirDiffFileContent>, usize) {
+        // Return the result of a RetryComparator around a comparator failing with errors, and the
+        // number of comparisons it made
+
+        let calls = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+
+        let retry_comparator = RetryComparator {
+            comparator: Box::new(FailingComparator {
+                errors: std::sync::Mutex::new(errors.iter().rev().copied().collect()),
+                calls: calls.clone(),
+            }),
+            retries,
+        };
+
+        let result = retry_comparator.compare("a".as_ref(), "b".as_ref());
+
+        (result, calls.load(std::sync::atomic::Ordering::Relaxed))
+    }
+
+    // reads a file, and calls a function on the first read, to change a file in the middle of a
+    // comparison
+    struct ChangingReader<'a, F: FnMut()> {
+        file: &'a std::fs::File,
+        change: Option<F>,
+    }
+
+    impl<F: FnMut()> std::io::Read for ChangingReader<'_, F> {
+        fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+            let length = self.file.read(buffer)?;
+
+            if let Some(mut change) = self.change.take() {
+                change();
+            }
+
+            Ok(length)
+        }
+    }
+
+    fn equal_while_changing(content: &[u8], change: impl FnMut(&std::path::Path)) -> bool {
+        // Compare two files with the same content in small chunks, calling change with the path
+        // of the source file right after its first chunk is read
+
+        let dir = tempfile::tempdir().unwrap();
+        let source_file_path = dir.path().join("source");
+        let target_file_path = dir.path().join("target");
+
+        std::fs::write(&source_file_path, content).unwrap();
+        std::fs::write(&target_file_path, content).unwrap();
+
+        let source_file = std::fs::File::open(&source_file_path).unwrap();
+        let target_file = std::fs::File::open(&target_file_path).unwrap();
+
+        let mut change = change;
+        let mut source = ChangingReader {
+            file: &source_file,
+            change: Some(|| change(&source_file_path)),
+        };
+
+        unchanged_readers_equal(
+            (&source_file, &mut source),
+            (&target_file, &mut &target_file),
+            4,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn unchanged_files_are_equal() {
+        assert!(equal_while_changing(b"0123456789", |_| {}));
+    }
+
+    #[test]
+    fn file_appended_to_during_the_comparison_differs() {
+        assert!(!equal_while_changing(b"0123456789", |path| {
+            use std::io::Write;
+
+            let mut file = std::fs::OpenOptions::new().append(true).open(path).unwrap();
+            file.write_all(b"appended").unwrap();
+        }));
+    }
+
+    #[test]
+    fn file_rewritten_during_the_comparison_differs() {
+        // the rewritten bytes were already read, so only the modification time shows the change
+        assert!(!equal_while_changing(b"0123456789", |path| {
+            use std::io::{Seek, Write};
+
+            let mut file = std::fs::OpenOptions::new().write(true).open(path).unwrap();
+            file.seek(std::io::SeekFrom::Start(0)).unwrap();
+            file.write_all(b"X").unwrap();
+            file.set_modified(std::time::UNIX_EPOCH).unwrap();
+        }));
+    }
+
+    #[test]
+    fn retries_a_transient_failure() {
+        let (result, calls) = retry(&[std::io::ErrorKind::TimedOut], 2);
+
+        assert!(matches!(result, Ok(DirDiffFileContent::Unchanged)));
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn gives_up_after_the_retries() {
+        let (result, calls) = retry(&[std::io::ErrorKind::TimedOut; 3], 1);
+
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn does_not_retry_permanent_failures() {
+        for kind in [
+            std::io::ErrorKind::NotFound,
+            std::io::ErrorKind::PermissionDenied,
+            std::io::ErrorKind::Interrupted,
+        ] {
+            let (result, calls) = retry(&[kind], 3);
+
+            assert_eq!(result.unwrap_err().kind(), kind);
+            assert_eq!(calls, 1);
+        }
+    }
+
+    fn compare_text(
+        comparator: &TextComparator,
+        source_content: &[u8],
+        target_content: &[u8],
+    ) -> DirDiffFileContent {
+        // Return the result of the comparator on two files with the given contents
+
+        let dir = tempfile::tempdir().unwrap();
+        let source_file_path = dir.path().join("source");
+        let target_file_path = dir.path().join("target");
+
+        std::fs::write(&source_file_path, source_content).unwrap();
+        std::fs::write(&target_file_path, target_content).unwrap();
+
+        comparator
+            .compare(&source_file_path, &target_file_path)
+            .unwrap()
+    }
+
+    #[test]
+    fn content_case_is_only_ignored_in_text_when_asked() {
+        let comparator = |ignore_case| TextComparator {
+            ignore_case,
+            ignored_lines: Vec::new(),
+            ignore_final_newline: false,
+            head_lines: None,
+        };
+
+        let cases = [
+            (
+                false,
+                "Hello World\n".as_bytes(),
+                "hello world\n".as_bytes(),
+                "Changed",
+            ),
+            (true, b"Hello World\n", b"hello world\n", "Unchanged"),
+            (true, b"Hello World\n", b"hello there\n", "Changed"),
+            // binary files are compared byte by byte
+            (true, b"A\0", b"a\0", "Changed"),
+        ];
+
+        for (ignore_case, source_content, target_content, expected) in cases {
+            assert_eq!(
+                format!(
+                    "{:?}",
+                    compare_text(&comparator(ignore_case), source_content, target_content)
+                ),
+                expected
+            );
+        }
+    }
+
+    // counts the bytes read from a reader
+    struct CountingReader<R: std::io::Read> {
+        reader: R,
+        read_length: usize,
+    }
+
+    impl<R: std::io::Read> std::io::Read for CountingReader<R> {
+        fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+            let length = self.reader.read(buffer)?;
+            self.read_length += length;
+            Ok(length)
+        }
+    }
+
+    #[test]
+    fn streaming_stops_at_the_first_differing_chunk() {
+        let source_content = vec![b'a'; 1 << 20];
+        let mut target_content = source_content.clone();
+        target_content[10] = b'b';
+
+        let mut source = CountingReader {
+            reader: source_content.as_slice(),
+            read_length: 0,
+        };
+        let mut target = CountingReader {
+            reader: target_content.as_slice(),
+            read_length: 0,
+        };
+
+        assert!(!readers_equal(&mut source, &mut target, 4096).unwrap());
+        assert_eq!((source.read_length, target.read_length), (4096, 4096));
+    }
+
+    #[test]
+    fn streaming_compares_large_files_to_the_last_byte() {
+        let dir = tempfile::tempdir().unwrap();
+        let source_file_path = dir.path().join("source");
+        let target_file_path = dir.path().join("target");
+
+        // not a multiple of the buffer size
+        let content: Vec<u8> = (0..(3 << 20) + 7).map(|i| (i % 251) as u8).collect();
+
+        std::fs::write(&source_file_path, &content).unwrap();
+        std::fs::write(&target_file_path, &content).unwrap();
+
+        assert!(streaming_equal(&source_file_path, &target_file_path, 65536).unwrap());
+
+        let mut changed_content = content;
+        *changed_content.last_mut().unwrap() ^= 1;
+        std::fs::write(&target_file_path, &changed_content).unwrap();
+
+        assert!(!streaming_equal(&source_file_path, &target_file_path, 65536).unwrap());
+    }
+
+    #[test]
+    fn lines_matching_the_ignored_regexes_are_left_out() {
+        let comparator = |ignored_lines: &[&str]| TextComparator {
+            ignore_case: false,
+            ignored_lines: ignored_lines
+                .iter()
+                .map(|regex| regex::Regex::new(regex).unwrap())
+                .collect(),
+            ignore_final_newline: false,
+            head_lines: None,
+        };
+
+        let source_content = b"name: app\nBuild: 2024-01-01T10:00:00\nId: 1\n";
+        let target_content = b"name: app\nBuild: 2024-06-30T18:42:07\nId: 2\n";
+
+        let cases: [(&[&str], &str); 3] = [
+            (&[], "Changed"),
+            (&["^Build: "], "Changed"),
+            (&["^Build: ", "^Id: "], "Unchanged"),
+        ];
+
+        for (ignored_lines, expected) in cases {
+            assert_eq!(
+                format!(
+                    "{:?}",
+                    compare_text(&comparator(ignored_lines), source_content, target_content)
+                ),
+                expected,
+                "{:?}",
+                ignored_lines
+            );
+        }
+
+        // the other lines still count
+        assert_eq!(
+            format!(
+                "{:?}",
+                compare_text(
+                    &comparator(&["^Build: "]),
+                    b"Build: 1\nname: app\n",
+                    b"Build: 2\nname: other\n"
+                )
+            ),
+            "Changed"
+        );
+    }
+
+    #[test]
+    fn large_files_are_compared_through_their_mappings() {
+        let dir = tempfile::tempdir().unwrap();
+        let source_file_path = dir.path().join("source");
+        let target_file_path = dir.path().join("target");
+
+        let content: Vec<u8> = (0..8 * 1024 * 1024)
+            .map(|index| (index % 251) as u8)
+            .collect();
+
+        let comparator = MmapComparator { buffer_size: 4096 };
+
+        let compare = |target_content: &[u8]| {
+            std::fs::write(&source_file_path, &content).unwrap();
+            std::fs::write(&target_file_path, target_content).unwrap();
+
+            // the files are compared mapped, not with the fallback
+            assert!(mapped_equal(&source_file_path, &target_file_path)
+                .unwrap()
+                .is_some());
+
+            format!(
+                "{:?}",
+                comparator
+                    .compare(&source_file_path, &target_file_path)
+                    .unwrap()
+            )
+        };
+
+        assert_eq!(compare(&content), "Unchanged");
+
+        let mut changed_content = content.clone();
+        *changed_content.last_mut().unwrap() ^= 1;
+        assert_eq!(compare(&changed_content), "Changed");
+
+        changed_content = content.clone();
+        changed_content[content.len() / 2] ^= 1;
+        assert_eq!(compare(&changed_content), "Changed");
+
+        // empty files can't be mapped
+        std::fs::write(&source_file_path, "").unwrap();
+        std::fs::write(&target_file_path, "").unwrap();
+        assert_eq!(
+            format!(
+                "{:?}",
+                comparator
+                    .compare(&source_file_path, &target_file_path)
+                    .unwrap()
+            ),
+            "Unchanged"
+        );
+    }
+
+    #[test]
+    fn json_files_are_compared_by_their_values() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let comparator = JsonComparator {
+            comparator: Box::new(ByteComparator { buffer_size: 4096 }),
+        };
+
+        let compare = |file_name: &str, source_content: &str, target_content: &str| {
+            let source_file_path = dir.path().join(format!("source-{}", file_name));
+            let target_file_path = dir.path().join(format!("target-{}", file_name));
+
+            std::fs::write(&source_file_path, source_content).unwrap();
+            std::fs::write(&target_file_path, target_content).unwrap();
+
+            format!(
+                "{:?}",
+                comparator
+                    .compare(&source_file_path, &target_file_path)
+                    .unwrap()
+            )
+        };
+
+        let source_json = r#"{"name": "app", "tags": ["a", "b"], "nested": {"x": 1, "y": 2}}"#;
+        let reordered_json = r#"{
+            "nested": {"y": 2, "x": 1},
+            "tags": ["a", "b"],
+            "name": "app"
+        }"#;
+
+        assert_eq!(compare("a.json", source_json, reordered_json), "Unchanged");
+        // the order of arrays still matters
+        assert_eq!(
+            compare(
+                "b.json",
+                r#"{"tags": ["a", "b"]}"#,
+                r#"{"tags": ["b", "a"]}"#
+            ),
+            "Changed"
+        );
+        assert_eq!(compare("c.json", r#"{"x": 1}"#, r#"{"x": 2}"#), "Changed");
+        // other files and malformed JSON are compared byte by byte
+        assert_eq!(compare("d.txt", source_json, reordered_json), "Changed");
+        assert_eq!(compare("e.json", "{", "{ "), "Changed");
+        assert_eq!(compare("f.json", "{", "{"), "Unchanged");
+    }
+
+    #[test]
+    fn sampling_misses_differences_in_the_middle_of_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let source_file_path = dir.path().join("source");
+        let target_file_path = dir.path().join("target");
+
+        let content = vec![b'x'; 1000];
+
+        let compare = |comparator: &dyn ContentComparator, changed_at: usize| {
+            let mut target_content = content.clone();
+            target_content[changed_at] = b'y';
+
+            std::fs::write(&source_file_path, &content).unwrap();
+            std::fs::write(&target_file_path, &target_content).unwrap();
+
+            format!(
+                "{:?}",
+                comparator
+                    .compare(&source_file_path, &target_file_path)
+                    .unwrap()
+            )
+        };
+
+        let sample_comparator = SampleComparator { sample_size: 100 };
+        let byte_comparator = ByteComparator { buffer_size: 4096 };
+
+        // the approximation --sample warns about: only the samples are compared
+        assert_eq!(compare(&sample_comparator, 500), "Unchanged");
+        assert_eq!(compare(&byte_comparator, 500), "Changed");
+
+        assert_eq!(compare(&sample_comparator, 99), "Changed");
+        assert_eq!(compare(&sample_comparator, 900), "Changed");
+        assert_eq!(compare(&sample_comparator, 100), "Unchanged");
+        assert_eq!(compare(&sample_comparator, 899), "Unchanged");
+
+        // files of different sizes are changed whatever their samples
+        std::fs::write(&target_file_path, [&content[..], b"x"].concat()).unwrap();
+        assert_eq!(
+            format!(
+                "{:?}",
+                sample_comparator
+                    .compare(&source_file_path, &target_file_path)
+                    .unwrap()
+            ),
+            "Changed"
+        );
+    }
+
+    #[test]
+    fn only_the_first_lines_are_compared_with_head_lines() {
+        let comparator = |head_lines| TextComparator {
+            ignore_case: false,
+            ignored_lines: Vec::new(),
+            ignore_final_newline: false,
+            head_lines,
+        };
+
+        let cases = [
+            (
+                None,
+                "header\nline 1\n".as_bytes(),
+                "header\nline 2\n".as_bytes(),
+                "Changed",
+            ),
+            (
+                Some(1),
+                b"header\nline 1\n",
+                b"header\nline 2\n",
+                "Unchanged",
+            ),
+            (Some(2), b"header\nline 1\n", b"header\nline 2\n", "Changed"),
+            (Some(1), b"header 1\nline\n", b"header 2\nline\n", "Changed"),
+            // shorter files are compared whole
+            (Some(5), b"header\nline\n", b"header\nline\n", "Unchanged"),
+            (Some(5), b"header\n", b"header\nline\n", "Changed"),
+            // binary files are compared whole, since they have no lines
+            (Some(1), b"\0\n1", b"\0\n2", "Changed"),
+        ];
+
+        for (head_lines, source_content, target_content, expected) in cases {
+            assert_eq!(
+                format!(
+                    "{:?}",
+                    compare_text(&comparator(head_lines), source_content, target_content)
+                ),
+                expected,
+                "{:?} {:?}",
+                head_lines,
+                source_content
+            );
+        }
+    }
+
+    #[test]
+    fn archives_with_the_same_entries_are_unchanged_whatever_their_compression() {
+        use std::io::Write;
+
+        let dir = tempfile::tempdir().unwrap();
+
+        let write_zip =
+            |file_name: &str, method: zip::CompressionMethod, entries: &[(&str, &str)]| {
+                let file_path = dir.path().join(file_name);
+                let mut writer = zip::ZipWriter::new(std::fs::File::create(&file_path).unwrap());
+                let options = zip::write::SimpleFileOptions::default().compression_method(method);
+
+                for (name, content) in entries {
+                    writer.start_file(*name, options).unwrap();
+                    writer.write_all(content.repeat(100).as_bytes()).unwrap();
+                }
+
+                writer.finish().unwrap();
+                file_path
+            };
+
+        let entries = [("a.txt", "a"), ("dir/b.txt", "b")];
+
+        let stored = write_zip("stored.zip", zip::CompressionMethod::Stored, &entries);
+        let deflated = write_zip(
+            "deflated.zip",
+            zip::CompressionMethod::Deflated,
+            &[entries[1], entries[0]],
+        );
+        let changed = write_zip(
+            "changed.jar",
+            zip::CompressionMethod::Deflated,
+            &[("a.txt", "a"), ("dir/b.txt", "c")],
+        );
+
+        // the archives themselves differ
+        assert_ne!(
+            std::fs::read(&stored).unwrap(),
+            std::fs::read(&deflated).unwrap()
+        );
+
+        let byte_comparator = ByteComparator { buffer_size: 4096 };
+        let archive_comparator = ArchiveComparator {
+            comparator: Box::new(ByteComparator { buffer_size: 4096 }),
+            buffer_size: 4096,
+        };
+
+        let compare = |comparator: &dyn ContentComparator,
+                       source_file_path: &std::path::Path,
+                       target_file_path: &std::path::Path| {
+            format!(
+                "{:?}",
+                comparator
+                    .compare(source_file_path, target_file_path)
+                    .unwrap()
+            )
+        };
+
+        assert_eq!(compare(&byte_comparator, &stored, &deflated), "Changed");
+        assert_eq!(
+            compare(&archive_comparator, &stored, &deflated),
+            "Unchanged"
+        );
+        assert_eq!(compare(&archive_comparator, &stored, &changed), "Changed");
+    }
+}
@@ -0,0 +1,126 @@
+use std::io::Write;
+
+use crate::{find_target_dir, hash, DirDiff, DirDiffError};
+
+pub struct DuplicateGroup {
+    added: bool,                    // whether the files are added or removed
+    paths: Vec<std::path::PathBuf>, // files with identical content, in diff order
+}
+
+pub fn find_duplicate_groups(
+    dir_diff: &[DirDiff<std::path::PathBuf>],
+    source_dir: &std::path::Path,
+    target_dirs: &[std::path::PathBuf],
+    buffer_size: usize,
+) -> Result<Vec<DuplicateGroup>, DirDiffError> {
+    // Group the added files (and separately the removed files) that have the same content
+
+    let mut removed_by_hash: Vec<(String, Vec<std::path::PathBuf>)> = Vec::new();
+    let mut added_by_hash: Vec<(String, Vec<std::path::PathBuf>)> = Vec::new();
+
+    for diff_fragment in dir_diff {
+        let (file_path, groups) = match diff_fragment {
+            DirDiff::Removed(path) => (source_dir.join(path), &mut removed_by_hash),
+            DirDiff::Added(path) => (
+                find_target_dir(target_dirs, path).join(path),
+                &mut added_by_hash,
+            ),
+            // renamed files are not only on one side anymore
+            DirDiff::Similar(_, _)
+            | DirDiff::Renamed(_, _)
+            | DirDiff::RenamedChanged(_, _, _)
+            | DirDiff::CaseChanged(_, _, _) => continue,
+        };
+
+        if !file_path.is_file() {
+            continue;
+        }
+
+        let file_hash =
+            hash::hash_file(&file_path, buffer_size).map_err(|source| DirDiffError::Read {
+                path: file_path.clone(),
+                source,
+            })?;
+
+        // keep the groups in the order of their first file
+        match groups
+            .iter_mut()
+            .find(|(group_hash, _)| *group_hash == file_hash)
+        {
+            Some((_, paths)) => paths.push(diff_fragment.path().clone()),
+            None => groups.push((file_hash, vec![diff_fragment.path().clone()])),
+        }
+    }
+
+    let into_duplicate_groups = |groups: Vec<(String, Vec<std::path::PathBuf>)>, added| {
+        groups
+            .into_iter()
+            .filter(|(_, paths)| paths.len() > 1)
+            .map(move |(_, paths)| DuplicateGroup { added, paths })
+    };
+
+    Ok(into_duplicate_groups(removed_by_hash, false)
+        .chain(into_duplicate_groups(added_by_hash, true))
+        .collect())
+}
+
+pub fn print_duplicate_groups(
+    out: &mut impl Write,
+    duplicate_groups: &[DuplicateGroup],
+) -> std::io::Result<()> {
+    for group in duplicate_groups {
+        writeln!(
+            out,
+            "{} identical {} files:",
+            group.paths.len(),
+            if group.added { "added" } else { "removed" }
+        )?;
+
+        for path in &group.paths {
+            writeln!(out, "  {}", path.display())?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_identical_added_files() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        for (path, content) in [("a", "boilerplate"), ("b", "other"), ("c", "boilerplate")] {
+            std::fs::write(target_dir.path().join(path), content).unwrap();
+        }
+        std::fs::create_dir(target_dir.path().join("dir")).unwrap();
+        std::fs::write(target_dir.path().join("dir/d"), "boilerplate").unwrap();
+        // a removed file with the same content is not in the group of the added files
+        std::fs::write(source_dir.path().join("e"), "boilerplate").unwrap();
+
+        let dir_diff = ["a", "b", "c", "dir", "dir/d"]
+            .map(|path| DirDiff::Added(path.into()))
+            .into_iter()
+            .chain([DirDiff::Removed("e".into())])
+            .collect::<Vec<_>>();
+
+        let duplicate_groups = find_duplicate_groups(
+            &dir_diff,
+            source_dir.path(),
+            &[target_dir.path().to_path_buf()],
+            4096,
+        )
+        .unwrap();
+
+        let mut out = Vec::new();
+        print_duplicate_groups(&mut out, &duplicate_groups).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "3 identical added files:\n  a\n  c\n  dir/d\n"
+        );
+    }
+}
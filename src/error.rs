@@ -0,0 +1,257 @@
+#[derive(Debug)]
+pub enum DirDiffError {
+    MissingDirectory(std::path::PathBuf), // the path does not exist
+    NotADirectory(std::path::PathBuf),    // the path exists but is not a directory
+    CannotStrip(std::path::PathBuf),      // the directory doesn't only have a single directory
+    InvalidPattern(glob::PatternError),   // a glob pattern given as an argument is invalid
+
+    // a directory argument is a symlink to a path that does not exist
+    BrokenSymlink {
+        path: std::path::PathBuf,
+        target: std::path::PathBuf,
+    },
+
+    // a directory argument is a symlink to a file
+    SymlinkNotADirectory {
+        path: std::path::PathBuf,
+        target: std::path::PathBuf,
+    },
+
+    // a directory argument refers to an environment variable that is not set
+    Expansion {
+        path: std::path::PathBuf,
+        message: String,
+    },
+
+    // a directory could not be listed
+    Walk {
+        path: std::path::PathBuf,
+        source: std::io::Error,
+    },
+
+    // a file could not be read for comparison
+    Read {
+        path: std::path::PathBuf,
+        source: std::io::Error,
+    },
+
+    // two files with the same relative path could not be compared
+    Compare {
+        path: std::path::PathBuf,
+        source: std::io::Error,
+    },
+
+    // a path in the target directory could not be modified while applying the diff
+    Write {
+        path: std::path::PathBuf,
+        source: std::io::Error,
+    },
+
+    // the --external-diff command could not be started
+    ExternalDiff {
+        command: String,
+        source: std::io::Error,
+    },
+
+    // the --on-changed-run command could not be started
+    OnChangedRun {
+        command: String,
+        source: std::io::Error,
+    },
+
+    // the --on-changed-run command exited with an error for some files
+    CommandFailed {
+        command: String,
+        failure_count: usize,
+        run_count: usize,
+    },
+
+    WalkTimeout(std::path::PathBuf), // listing the directory took longer than --max-walk-time
+
+    // a directory listing has more entries than allowed by --max-entries
+    TooManyEntries {
+        path: std::path::PathBuf,
+        max_entries: usize,
+    },
+
+    Output(std::io::Error), // the diff could not be written to stdout
+
+    #[cfg(feature = "git")]
+    Git(git2::Error), // the git revision could not be read
+
+    // a manifest or directory index could not be downloaded
+    #[cfg(feature = "http")]
+    Http {
+        url: String,
+        source: reqwest::Error,
+    },
+
+    #[cfg(feature = "http")]
+    InvalidUrl(String), // a URL given as manifest, or linked from a directory index, is invalid
+
+    // a line of a manifest is not in the form path<TAB>hash
+    InvalidManifestLine {
+        path: std::path::PathBuf,
+        line: usize,
+    },
+
+    InvalidBatchRecord(usize), // a record of the batch input has no tab between the directories
+    RewriteCollision(std::path::PathBuf), // several paths of a listing are rewritten to this one
+    InvalidListedPath(std::path::PathBuf), // a --paths-from path is absolute or goes up with ..
+    MultipleTargets,           // an action that needs a single target directory was given several
+    IncompatibleFormat(&'static str), // the output format can't be used with this option
+    Aborted,                   // the user did not confirm an action
+    Interrupted,               // Ctrl-C was pressed
+}
+
+impl std::fmt::Display for DirDiffError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DirDiffError::MissingDirectory(path) => write!(f, "{} does not exist", path.display()),
+            DirDiffError::NotADirectory(path) => {
+                write!(f, "{} is not a directory", path.display())
+            }
+            DirDiffError::BrokenSymlink { path, target } => write!(
+                f,
+                "{} is a symlink to {}, which does not exist",
+                path.display(),
+                target.display()
+            ),
+            DirDiffError::SymlinkNotADirectory { path, target } => write!(
+                f,
+                "{} is a symlink to {}, which is not a directory",
+                path.display(),
+                target.display()
+            ),
+            DirDiffError::CannotStrip(path) => write!(
+                f,
+                "unable to strip {}: it must only contain a single directory",
+                path.display()
+            ),
+            DirDiffError::InvalidPattern(e) => write!(f, "invalid pattern: {}", e),
+            DirDiffError::Expansion { path, message } => {
+                write!(f, "unable to expand {}: {}", path.display(), message)
+            }
+            DirDiffError::Walk { path, source } => {
+                write!(f, "unable to list {}: {}", path.display(), source)
+            }
+            DirDiffError::Read { path, source } => {
+                write!(f, "unable to read {}: {}", path.display(), source)
+            }
+            DirDiffError::Compare { path, source } => {
+                write!(f, "unable to compare {}: {}", path.display(), source)
+            }
+            DirDiffError::Write { path, source } => {
+                write!(f, "unable to write {}: {}", path.display(), source)
+            }
+            DirDiffError::ExternalDiff { command, source } => {
+                write!(f, "unable to run {}: {}", command, source)
+            }
+            DirDiffError::OnChangedRun { command, source } => write!(
+                f,
+                "unable to run {} (--on-changed-run): {}",
+                command, source
+            ),
+            DirDiffError::CommandFailed {
+                command,
+                failure_count,
+                run_count,
+            } => write!(
+                f,
+                "{} failed on {} of {} files",
+                command, failure_count, run_count
+            ),
+            DirDiffError::WalkTimeout(path) => write!(
+                f,
+                "listing {} took longer than --max-walk-time",
+                path.display()
+            ),
+            DirDiffError::TooManyEntries { path, max_entries } => write!(
+                f,
+                "{} has more than {} entries (see --max-entries)",
+                path.display(),
+                max_entries
+            ),
+            DirDiffError::Output(e) => write!(f, "unable to write output: {}", e),
+            #[cfg(feature = "git")]
+            DirDiffError::Git(e) => write!(f, "git: {}", e.message()),
+            #[cfg(feature = "http")]
+            DirDiffError::Http { url, source } => write!(f, "unable to fetch {}: {}", url, source),
+            #[cfg(feature = "http")]
+            DirDiffError::InvalidUrl(url) => write!(f, "invalid URL: {}", url),
+            DirDiffError::InvalidManifestLine { path, line } => write!(
+                f,
+                "line {} of {} is not in the form path<TAB>hash",
+                line,
+                path.display()
+            ),
+            DirDiffError::InvalidBatchRecord(number) => write!(
+                f,
+                "batch record {} is not in the form source<TAB>target",
+                number
+            ),
+            DirDiffError::InvalidListedPath(path) => write!(
+                f,
+                "{} is not a path inside the compared directories",
+                path.display()
+            ),
+            DirDiffError::RewriteCollision(path) => write!(
+                f,
+                "several paths of the same directory are rewritten to {}",
+                path.display()
+            ),
+            DirDiffError::MultipleTargets => {
+                write!(f, "this option needs exactly one target directory")
+            }
+            DirDiffError::IncompatibleFormat(option) => {
+                write!(f, "this output format can't be used with {}", option)
+            }
+            DirDiffError::Aborted => write!(f, "aborted, nothing was changed"),
+            DirDiffError::Interrupted => write!(f, "interrupted"),
+        }
+    }
+}
+
+impl std::error::Error for DirDiffError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DirDiffError::InvalidPattern(e) => Some(e),
+            DirDiffError::Output(e) => Some(e),
+            #[cfg(feature = "git")]
+            DirDiffError::Git(e) => Some(e),
+            #[cfg(feature = "http")]
+            DirDiffError::Http { source, .. } => Some(source),
+            DirDiffError::Walk { source, .. }
+            | DirDiffError::Read { source, .. }
+            | DirDiffError::Compare { source, .. }
+            | DirDiffError::Write { source, .. }
+            | DirDiffError::ExternalDiff { source, .. }
+            | DirDiffError::OnChangedRun { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<glob::PatternError> for DirDiffError {
+    fn from(e: glob::PatternError) -> Self {
+        DirDiffError::InvalidPattern(e)
+    }
+}
+
+// only for the errors writing the output, and the interrupt::check ones going through them to
+// stop printing. Errors reading or comparing files are mapped to Read or Compare where they happen
+impl From<std::io::Error> for DirDiffError {
+    fn from(e: std::io::Error) -> Self {
+        match crate::interrupt::is_ctrl_c(&e) {
+            true => DirDiffError::Interrupted,
+            false => DirDiffError::Output(e),
+        }
+    }
+}
+
+#[cfg(feature = "git")]
+impl From<git2::Error> for DirDiffError {
+    fn from(e: git2::Error) -> Self {
+        DirDiffError::Git(e)
+    }
+}
@@ -0,0 +1,251 @@
+use crate::{find_target_dir, DirDiff, DirDiffError, DirDiffFileContent};
+
+pub fn find_changed_files(
+    dir_diff: &[DirDiff<std::path::PathBuf>],
+    source_dir: &std::path::Path,
+    target_dirs: &[std::path::PathBuf],
+) -> Vec<(std::path::PathBuf, std::path::PathBuf)> {
+    // Return the source and target paths of the changed files. Files replaced by a directory (or
+    // the other way around) can't be opened by a diff tool
+
+    dir_diff
+        .iter()
+        .filter_map(|diff_fragment| match diff_fragment {
+            DirDiff::Similar(path, Some(DirDiffFileContent::Changed)) => Some((
+                source_dir.join(path),
+                find_target_dir(target_dirs, path).join(path),
+            )),
+            _ => None,
+        })
+        .filter(|(source_file_path, target_file_path)| {
+            source_file_path.is_file() && target_file_path.is_file()
+        })
+        .collect()
+}
+
+fn shell_command(command_line: &str) -> std::process::Command {
+    // Return the command of a command line given as an option, to which more arguments can be
+    // added. The command line can have arguments, so the shell parses it
+
+    #[cfg(unix)]
+    {
+        let mut command = std::process::Command::new("sh");
+        command
+            .arg("-c")
+            .arg(format!("{} \"$@\"", command_line))
+            .arg("sh");
+        command
+    }
+
+    #[cfg(not(unix))]
+    {
+        let mut words = command_line.split_whitespace();
+        let mut command = std::process::Command::new(words.next().unwrap_or_default());
+        command.args(words);
+        command
+    }
+}
+
+pub fn run_external_diff(
+    diff_command: &str,
+    changed_files: &[(std::path::PathBuf, std::path::PathBuf)],
+) -> Result<(), DirDiffError> {
+    // Run the diff tool with the source and target paths of each changed file as its last two
+    // arguments, one file after the other. Diff tools exit with an error when files differ, so
+    // their exit status is ignored, unless the shell couldn't find the command
+
+    for (source_file_path, target_file_path) in changed_files {
+        crate::interrupt::check()?;
+
+        let mut command = shell_command(diff_command);
+
+        let external_diff_error = |source| DirDiffError::ExternalDiff {
+            command: diff_command.to_string(),
+            source,
+        };
+
+        let status = command
+            .arg(source_file_path)
+            .arg(target_file_path)
+            .status()
+            .map_err(external_diff_error)?;
+
+        if cfg!(unix) && status.code() == Some(127) {
+            return Err(external_diff_error(std::io::ErrorKind::NotFound.into()));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn find_changed_and_added_files(
+    dir_diff: &[DirDiff<std::path::PathBuf>],
+    target_dirs: &[std::path::PathBuf],
+) -> Vec<(std::path::PathBuf, std::path::PathBuf)> {
+    // Return the relative path and target directory of the added and changed files. Directories
+    // are left out, their files have their own entries
+
+    dir_diff
+        .iter()
+        .filter_map(|diff_fragment| match diff_fragment {
+            DirDiff::Added(path) | DirDiff::Similar(path, Some(DirDiffFileContent::Changed)) => {
+                Some((path.clone(), find_target_dir(target_dirs, path).clone()))
+            }
+            _ => None,
+        })
+        .filter(|(path, target_dir)| target_dir.join(path).is_file())
+        .collect()
+}
+
+pub fn run_on_changed_command(
+    command_line: &str,
+    changed_files: &[(std::path::PathBuf, std::path::PathBuf)],
+) -> Result<(), DirDiffError> {
+    // Run the command once for each added or changed file, in its target directory and with its
+    // relative path as last argument. A failure doesn't stop the other runs, but is reported and
+    // makes the whole hook fail at the end
+
+    let mut failure_count = 0;
+
+    for (path, target_dir) in changed_files {
+        crate::interrupt::check()?;
+
+        let command_error = |source| DirDiffError::OnChangedRun {
+            command: command_line.to_string(),
+            source,
+        };
+
+        let status = shell_command(command_line)
+            .arg(path)
+            .current_dir(target_dir)
+            .status()
+            .map_err(command_error)?;
+
+        // the other runs would fail the same way
+        if cfg!(unix) && status.code() == Some(127) {
+            return Err(command_error(std::io::ErrorKind::NotFound.into()));
+        }
+
+        if !status.success() {
+            eprintln!("{} failed on {} ({})", command_line, path.display(), status);
+            failure_count += 1;
+        }
+    }
+
+    match failure_count {
+        0 => Ok(()),
+        _ => Err(DirDiffError::CommandFailed {
+            command: command_line.to_string(),
+            failure_count,
+            run_count: changed_files.len(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn external_diff_runs_on_the_two_paths_of_each_changed_file() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+        let log_dir = tempfile::tempdir().unwrap();
+        let log_path = log_dir.path().join("log");
+
+        for path in ["changed", "other", "unchanged"] {
+            std::fs::write(source_dir.path().join(path), path).unwrap();
+            std::fs::write(target_dir.path().join(path), path).unwrap();
+        }
+
+        let dir_diff = [
+            DirDiff::Similar("changed".into(), Some(DirDiffFileContent::Changed)),
+            DirDiff::Added("new".into()),
+            DirDiff::Similar("other".into(), Some(DirDiffFileContent::Changed)),
+            DirDiff::Similar("unchanged".into(), Some(DirDiffFileContent::Unchanged)),
+        ];
+
+        let changed_files = find_changed_files(
+            &dir_diff,
+            source_dir.path(),
+            &[target_dir.path().to_path_buf()],
+        );
+
+        // a diff tool that logs its arguments and exits like diff does on different files
+        let diff_command = format!(
+            "log() {{ printf '%s %s\\n' \"$@\" >> '{}'; return 1; }}; log",
+            log_path.display()
+        );
+
+        run_external_diff(&diff_command, &changed_files).unwrap();
+
+        let line = |path: &str| {
+            format!(
+                "{} {}\n",
+                source_dir.path().join(path).display(),
+                target_dir.path().join(path).display()
+            )
+        };
+
+        assert_eq!(
+            std::fs::read_to_string(&log_path).unwrap(),
+            line("changed") + &line("other")
+        );
+
+        assert!(matches!(
+            run_external_diff("dirdiff-missing-diff-tool", &changed_files),
+            Err(DirDiffError::ExternalDiff { .. })
+        ));
+    }
+    #[cfg(unix)]
+    #[test]
+    fn on_changed_run_runs_once_per_added_and_changed_file() {
+        let target_dir = tempfile::tempdir().unwrap();
+        let log_dir = tempfile::tempdir().unwrap();
+        let log_path = log_dir.path().join("log");
+
+        std::fs::create_dir(target_dir.path().join("sub")).unwrap();
+
+        for path in ["changed", "sub/new", "unchanged"] {
+            std::fs::write(target_dir.path().join(path), path).unwrap();
+        }
+
+        let dir_diff = [
+            DirDiff::Similar("changed".into(), Some(DirDiffFileContent::Changed)),
+            DirDiff::Removed("removed".into()),
+            DirDiff::Added("sub".into()),
+            DirDiff::Added("sub/new".into()),
+            DirDiff::Similar("unchanged".into(), Some(DirDiffFileContent::Unchanged)),
+        ];
+
+        let changed_files =
+            find_changed_and_added_files(&dir_diff, &[target_dir.path().to_path_buf()]);
+
+        // a hook that logs its argument and fails on the new file
+        let hook_command = format!(
+            "log() {{ echo \"$@\" >> '{}'; [ \"$1\" = changed ]; }}; log",
+            log_path.display()
+        );
+
+        let error = run_on_changed_command(&hook_command, &changed_files).unwrap_err();
+
+        assert_eq!(
+            std::fs::read_to_string(&log_path).unwrap(),
+            "changed\nsub/new\n"
+        );
+        assert!(matches!(
+            error,
+            DirDiffError::CommandFailed {
+                failure_count: 1,
+                run_count: 2,
+                ..
+            }
+        ));
+
+        let error = run_on_changed_command("dirdiff-missing-hook", &changed_files).unwrap_err();
+
+        assert!(matches!(error, DirDiffError::OnChangedRun { .. }));
+        assert!(error.to_string().contains("--on-changed-run"));
+    }
+}
@@ -0,0 +1,185 @@
+use crate::{find_target_dir, hash, DirDiff, DirDiffError, DirDiffFileContent};
+
+fn group_by_file_name(
+    dir_listing: &[std::path::PathBuf],
+    dir_path_of: impl Fn(&std::path::Path) -> std::path::PathBuf,
+) -> std::collections::BTreeMap<std::ffi::OsString, Vec<std::path::PathBuf>> {
+    // Return the absolute paths of the regular files of a listing, by file name. dir_path_of
+    // returns the directory a path of the listing is in
+
+    let mut files_by_name = std::collections::BTreeMap::new();
+
+    for path in dir_listing {
+        let file_path = dir_path_of(path).join(path);
+
+        if let (true, Some(file_name)) = (file_path.is_file(), path.file_name()) {
+            files_by_name
+                .entry(file_name.to_os_string())
+                .or_insert_with(Vec::new)
+                .push(file_path);
+        }
+    }
+
+    files_by_name
+}
+
+pub fn get_flat_dir_diff(
+    source_dir_listing: &[std::path::PathBuf],
+    target_dir_listing: &[std::path::PathBuf],
+    source_dir: &std::path::Path,
+    target_dirs: &[std::path::PathBuf],
+    compare_file_content: bool,
+    buffer_size: usize,
+) -> Result<Vec<DirDiff<std::path::PathBuf>>, DirDiffError> {
+    // Return the diff between the multisets of file names of both directories, wherever the files
+    // are. A name that is n times in one directory and m times in the other is similar min(n, m)
+    // times, and removed or added for the others. When comparing file content, the files with the
+    // same name and content are paired first (unchanged), then the others (changed). The paths of
+    // the diff are the file names, sorted
+
+    let source_files = group_by_file_name(source_dir_listing, |_| source_dir.to_path_buf());
+    let target_files = group_by_file_name(target_dir_listing, |path| {
+        find_target_dir(target_dirs, path).clone()
+    });
+
+    let file_names: std::collections::BTreeSet<&std::ffi::OsString> =
+        source_files.keys().chain(target_files.keys()).collect();
+
+    let hash = |file_path: &std::path::PathBuf| {
+        crate::interrupt::check()?;
+
+        hash::hash_file(file_path, buffer_size).map_err(|source| DirDiffError::Read {
+            path: file_path.clone(),
+            source,
+        })
+    };
+
+    let mut diff_output = Vec::new();
+
+    for file_name in file_names {
+        let path = std::path::PathBuf::from(file_name);
+
+        let source_count = source_files.get(file_name).map_or(0, Vec::len);
+        let target_count = target_files.get(file_name).map_or(0, Vec::len);
+
+        let mut unchanged_count = 0;
+
+        if compare_file_content && source_count > 0 && target_count > 0 {
+            let source_hashes = source_files[file_name]
+                .iter()
+                .map(hash)
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut target_hashes = target_files[file_name]
+                .iter()
+                .map(hash)
+                .collect::<Result<Vec<_>, _>>()?;
+
+            for source_hash in &source_hashes {
+                if let Some(index) = target_hashes.iter().position(|h| h == source_hash) {
+                    target_hashes.swap_remove(index);
+                    unchanged_count += 1;
+                }
+            }
+        }
+
+        let similar_count = source_count.min(target_count);
+
+        for _ in 0..unchanged_count {
+            diff_output.push(DirDiff::Similar(
+                path.clone(),
+                Some(DirDiffFileContent::Unchanged),
+            ));
+        }
+
+        for _ in unchanged_count..similar_count {
+            diff_output.push(DirDiff::Similar(
+                path.clone(),
+                compare_file_content.then_some(DirDiffFileContent::Changed),
+            ));
+        }
+
+        for _ in similar_count..source_count {
+            diff_output.push(DirDiff::Removed(path.clone()));
+        }
+
+        for _ in similar_count..target_count {
+            diff_output.push(DirDiff::Added(path.clone()));
+        }
+    }
+
+    Ok(diff_output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_files_in_other_directories_have_no_difference() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        let files = [
+            (source_dir.path(), "a/photo.jpg", "photo"),
+            (source_dir.path(), "a/notes.txt", "notes"),
+            (source_dir.path(), "b/notes.txt", "other notes"),
+            (target_dir.path(), "2024/photo.jpg", "photo"),
+            (target_dir.path(), "notes.txt", "other notes"),
+            (target_dir.path(), "x/y/notes.txt", "notes"),
+        ];
+
+        for (dir, path, content) in files {
+            let file_path = dir.join(path);
+            std::fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+            std::fs::write(file_path, content).unwrap();
+        }
+
+        let listing = |paths: &[&str]| -> Vec<std::path::PathBuf> {
+            paths.iter().map(std::path::PathBuf::from).collect()
+        };
+        let source_listing = listing(&["a", "a/notes.txt", "a/photo.jpg", "b", "b/notes.txt"]);
+        let target_listing = listing(&[
+            "2024",
+            "2024/photo.jpg",
+            "notes.txt",
+            "x",
+            "x/y",
+            "x/y/notes.txt",
+        ]);
+
+        let flat_diff = |source_listing: &[std::path::PathBuf]| -> Vec<String> {
+            get_flat_dir_diff(
+                source_listing,
+                &target_listing,
+                source_dir.path(),
+                &[target_dir.path().to_path_buf()],
+                true,
+                4096,
+            )
+            .unwrap()
+            .iter()
+            .map(|diff_fragment| format!("{:?}", diff_fragment))
+            .collect()
+        };
+
+        // both notes.txt are paired with the one of the same content, wherever it is
+        assert_eq!(
+            flat_diff(&source_listing),
+            [
+                r#"Similar("notes.txt", Some(Unchanged))"#,
+                r#"Similar("notes.txt", Some(Unchanged))"#,
+                r#"Similar("photo.jpg", Some(Unchanged))"#,
+            ]
+        );
+
+        // a duplicate missing from the source is an added file
+        assert_eq!(
+            flat_diff(&source_listing[..3]),
+            [
+                r#"Similar("notes.txt", Some(Unchanged))"#,
+                r#"Added("notes.txt")"#,
+                r#"Similar("photo.jpg", Some(Unchanged))"#,
+            ]
+        );
+    }
+}
@@ -0,0 +1,90 @@
+#[cfg(unix)]
+use std::os::unix::{ffi::OsStrExt, fs::PermissionsExt};
+
+use crate::DirDiffError;
+
+pub struct RevisionTree {
+    pub path: std::path::PathBuf, // temporary directory holding the files of the revision
+}
+
+impl Drop for RevisionTree {
+    fn drop(&mut self) {
+        // the temporary directory is only useful while diffing
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+fn write_tree(
+    repo: &git2::Repository,
+    tree: &git2::Tree,
+    dir_path: &std::path::Path,
+) -> Result<(), DirDiffError> {
+    // Write every entry of a git tree to dir_path, recursively
+
+    for entry in tree.iter() {
+        let entry_path = dir_path.join(String::from_utf8_lossy(entry.name_bytes()).as_ref());
+
+        let write_error = |source| DirDiffError::Write {
+            path: entry_path.clone(),
+            source,
+        };
+
+        match entry.kind() {
+            Some(git2::ObjectType::Tree) => {
+                std::fs::create_dir(&entry_path).map_err(write_error)?;
+
+                let subtree = entry.to_object(repo)?.peel_to_tree()?;
+                write_tree(repo, &subtree, &entry_path)?;
+            }
+            Some(git2::ObjectType::Blob) => {
+                let blob = entry.to_object(repo)?.peel_to_blob()?;
+
+                // symlinks are stored as blobs containing the link target
+                #[cfg(unix)]
+                if entry.filemode() == 0o120000 {
+                    let link_target = std::ffi::OsStr::from_bytes(blob.content());
+                    std::os::unix::fs::symlink(link_target, &entry_path).map_err(write_error)?;
+                    continue;
+                }
+
+                std::fs::write(&entry_path, blob.content()).map_err(write_error)?;
+
+                #[cfg(unix)]
+                if entry.filemode() == 0o100755 {
+                    std::fs::set_permissions(&entry_path, std::fs::Permissions::from_mode(0o755))
+                        .map_err(write_error)?;
+                }
+            }
+            _ => {
+                // submodules are commits, only keep their directory
+                std::fs::create_dir(&entry_path).map_err(write_error)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn checkout_revision(
+    repo_dir: &std::path::Path,
+    revision: &str,
+) -> Result<RevisionTree, DirDiffError> {
+    // Write the files of the repository at a revision (anything `git rev-parse` understands) to a
+    // temporary directory, without touching the working directory
+
+    let repo = git2::Repository::open(repo_dir)?;
+    let tree = repo.revparse_single(revision)?.peel_to_tree()?;
+
+    let revision_tree = RevisionTree {
+        path: std::env::temp_dir().join(format!("dirdiff-{}-{}", std::process::id(), tree.id())),
+    };
+
+    std::fs::create_dir(&revision_tree.path).map_err(|source| DirDiffError::Write {
+        path: revision_tree.path.clone(),
+        source,
+    })?;
+
+    write_tree(&repo, &tree, &revision_tree.path)?;
+
+    Ok(revision_tree)
+}
@@ -0,0 +1,105 @@
+use std::io::Write;
+
+use crate::{DirDiff, DirDiffFileContent};
+
+fn escape_data(text: &str) -> String {
+    // Escape the message of a workflow command
+    text.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn escape_property(text: &str) -> String {
+    // Escape a property (like file=...) of a workflow command, which also can't contain : or ,
+    escape_data(text).replace(':', "%3A").replace(',', "%2C")
+}
+
+pub fn print_github_annotations(
+    out: &mut impl Write,
+    dir_diff: &[DirDiff<std::path::PathBuf>],
+) -> std::io::Result<()> {
+    // Print a GitHub Actions warning annotation for every removed, added, changed or renamed
+    // path
+
+    for diff_fragment in dir_diff {
+        let path = diff_fragment.path().to_string_lossy();
+
+        let (title, message) = match diff_fragment {
+            DirDiff::Removed(_) => ("Removed", format!("{} is only in the source", path)),
+            DirDiff::Added(_) => ("Added", format!("{} is only in the target", path)),
+            DirDiff::Similar(_, Some(DirDiffFileContent::Changed)) => (
+                "Changed",
+                format!("{} differs between source and target", path),
+            ),
+            DirDiff::Renamed(_, to) => (
+                "Renamed",
+                format!("{} was moved to {}", path, to.to_string_lossy()),
+            ),
+            DirDiff::RenamedChanged(_, to, similarity) => (
+                "Renamed",
+                format!(
+                    "{} was moved to {} and changed ({}% similar)",
+                    path,
+                    to.to_string_lossy(),
+                    similarity
+                ),
+            ),
+            DirDiff::CaseChanged(_, to, content) => (
+                "Case changed",
+                match content {
+                    Some(DirDiffFileContent::Changed) => format!(
+                        "{} was renamed to {} and changed",
+                        path,
+                        to.to_string_lossy()
+                    ),
+                    _ => format!("{} was renamed to {}", path, to.to_string_lossy()),
+                },
+            ),
+            DirDiff::Similar(_, _) => continue,
+        };
+
+        writeln!(
+            out,
+            "::warning file={},title={}::{}",
+            escape_property(&path),
+            title,
+            escape_data(&message)
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn annotates_each_status() {
+        let dir_diff = [
+            DirDiff::Removed("removed".into()),
+            DirDiff::Added("dir/added".into()),
+            DirDiff::Similar("changed".into(), Some(DirDiffFileContent::Changed)),
+            DirDiff::Similar("unchanged".into(), Some(DirDiffFileContent::Unchanged)),
+            DirDiff::Similar("dir".into(), None),
+            DirDiff::Renamed("from".into(), "to".into()),
+            DirDiff::RenamedChanged("old".into(), "new".into(), 87),
+            DirDiff::CaseChanged("Readme".into(), "README".into(), None),
+            DirDiff::Added("a,b:c%".into()),
+        ];
+
+        let mut out = Vec::new();
+        print_github_annotations(&mut out, &dir_diff).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "::warning file=removed,title=Removed::removed is only in the source\n\
+             ::warning file=dir/added,title=Added::dir/added is only in the target\n\
+             ::warning file=changed,title=Changed::changed differs between source and target\n\
+             ::warning file=from,title=Renamed::from was moved to to\n\
+             ::warning file=old,title=Renamed::old was moved to new and changed (87%25 similar)\n\
+             ::warning file=Readme,title=Case changed::Readme was renamed to README\n\
+             ::warning file=a%2Cb%3Ac%25,title=Added::a,b:c%25 is only in the target\n"
+        );
+    }
+}
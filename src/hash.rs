@@ -0,0 +1,303 @@
+use sha2::Digest;
+use std::io::Write;
+
+use crate::{
+    compare_paths, find_target_dir, path_from_bytes, DirDiff, DirDiffError, DirDiffFileContent,
+    PathCase,
+};
+
+// source and target hash of compared files, by path
+pub type FileHashes = std::collections::HashMap<std::path::PathBuf, (String, String)>;
+
+pub fn hash_file(path: &std::path::Path, buffer_size: usize) -> std::io::Result<String> {
+    // Return the SHA-256 of a file's content as a hex string
+
+    hash_reader(&mut std::fs::File::open(path)?, buffer_size)
+}
+
+pub fn hash_reader(reader: &mut impl std::io::Read, buffer_size: usize) -> std::io::Result<String> {
+    // Return the SHA-256 of everything the reader reads as a hex string, reading it in chunks of
+    // buffer_size bytes so it is never all in memory
+
+    let mut hasher = sha2::Sha256::new();
+
+    let mut buffer = vec![0; buffer_size];
+
+    loop {
+        match crate::compare::read_chunk(reader, &mut buffer)? {
+            0 => break,
+            length => hasher.update(&buffer[..length]),
+        }
+    }
+
+    Ok(hex_digest(hasher))
+}
+
+pub fn hash_bytes(bytes: &[u8]) -> String {
+    // Return the SHA-256 of bytes already in memory as a hex string
+
+    hex_digest(sha2::Sha256::new_with_prefix(bytes))
+}
+
+fn hex_digest(hasher: sha2::Sha256) -> String {
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+pub fn print_manifest(
+    out: &mut impl Write,
+    dir_listing: &[std::path::PathBuf],
+    dir_path: &std::path::Path,
+    buffer_size: usize,
+) -> Result<(), DirDiffError> {
+    // Print a `path<TAB>hash` line for every regular file of the (sorted) listing. Directories and
+    // special files have no content to hash, so they are left out
+
+    for path in dir_listing {
+        let file_path = dir_path.join(path);
+
+        if !file_path.is_file() {
+            continue;
+        }
+
+        let file_hash =
+            hash_file(&file_path, buffer_size).map_err(|source| DirDiffError::Read {
+                path: file_path.clone(),
+                source,
+            })?;
+
+        writeln!(out, "{}\t{}", path.display(), file_hash)?;
+    }
+
+    Ok(())
+}
+
+pub fn read_manifest(
+    manifest_path: &std::path::Path,
+) -> Result<Vec<(std::path::PathBuf, String)>, DirDiffError> {
+    // Read the path and hash of every file of a manifest printed by --manifest
+
+    let content = std::fs::read(manifest_path).map_err(|source| DirDiffError::Read {
+        path: manifest_path.to_path_buf(),
+        source,
+    })?;
+
+    parse_manifest(&content, manifest_path)
+}
+
+pub fn parse_manifest(
+    content: &[u8],
+    manifest_path: &std::path::Path,
+) -> Result<Vec<(std::path::PathBuf, String)>, DirDiffError> {
+    // Return the path and hash of every line of a manifest. Empty lines are skipped. The path of
+    // the manifest is only used in errors
+
+    content
+        .split(|byte| *byte == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(index, line)| {
+            // paths can contain tabs, hashes can't
+            match line.iter().rposition(|byte| *byte == b'\t') {
+                Some(tab_index) => Ok((
+                    path_from_bytes(&line[..tab_index]),
+                    String::from_utf8_lossy(&line[tab_index + 1..]).to_lowercase(),
+                )),
+                None => Err(DirDiffError::InvalidManifestLine {
+                    path: manifest_path.to_path_buf(),
+                    line: index + 1,
+                }),
+            }
+        })
+        .collect()
+}
+
+pub fn diff_against_manifest(
+    mut manifest: Vec<(std::path::PathBuf, String)>,
+    dir_listing: &[std::path::PathBuf],
+    dir_path: &std::path::Path,
+    path_case: PathCase,
+    buffer_size: usize,
+) -> Result<Vec<DirDiff<std::path::PathBuf>>, DirDiffError> {
+    // Return the diff between the files of a manifest (the source) and the regular files of the
+    // (sorted) listing of a directory (the target), comparing their hashes. Directories are not in
+    // manifests, so they are left out
+
+    manifest.sort_by(|(a, _), (b, _)| compare_paths(a, b, path_case));
+
+    let dir_files: Vec<&std::path::PathBuf> = dir_listing
+        .iter()
+        .filter(|path| dir_path.join(path).is_file())
+        .collect();
+
+    let mut manifest_entries = manifest.into_iter().peekable();
+    let mut dir_files = dir_files.into_iter().peekable();
+
+    let mut diff_output = Vec::new();
+
+    loop {
+        crate::interrupt::check()?;
+
+        let ordering = match (manifest_entries.peek(), dir_files.peek()) {
+            (Some((manifest_path, _)), Some(dir_file)) => {
+                compare_paths(manifest_path, dir_file, path_case)
+            }
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => break,
+        };
+
+        match ordering {
+            std::cmp::Ordering::Less => {
+                let (manifest_path, _) = manifest_entries.next().unwrap();
+                diff_output.push(DirDiff::Removed(manifest_path));
+            }
+            std::cmp::Ordering::Greater => {
+                diff_output.push(DirDiff::Added(dir_files.next().unwrap().clone()));
+            }
+            std::cmp::Ordering::Equal => {
+                let (_, manifest_hash) = manifest_entries.next().unwrap();
+                let dir_file = dir_files.next().unwrap();
+
+                let file_path = dir_path.join(dir_file);
+
+                let file_hash =
+                    hash_file(&file_path, buffer_size).map_err(|source| DirDiffError::Read {
+                        path: file_path.clone(),
+                        source,
+                    })?;
+
+                diff_output.push(DirDiff::Similar(
+                    dir_file.clone(),
+                    Some(match file_hash == manifest_hash {
+                        true => DirDiffFileContent::Unchanged,
+                        false => DirDiffFileContent::Changed,
+                    }),
+                ));
+            }
+        }
+    }
+
+    Ok(diff_output)
+}
+
+pub fn find_file_hashes(
+    dir_diff: &[DirDiff<std::path::PathBuf>],
+    source_dir: &std::path::Path,
+    target_dirs: &[std::path::PathBuf],
+    buffer_size: usize,
+) -> Result<FileHashes, DirDiffError> {
+    // Return the hashes of both sides of every changed or unchanged file. Files replaced by a
+    // directory (or the other way around) have nothing to hash
+
+    let mut file_hashes = FileHashes::new();
+
+    for diff_fragment in dir_diff {
+        let path = match diff_fragment {
+            DirDiff::Similar(
+                path,
+                Some(DirDiffFileContent::Changed | DirDiffFileContent::Unchanged),
+            ) => path,
+            _ => continue,
+        };
+
+        let source_file_path = source_dir.join(path);
+        let target_file_path = find_target_dir(target_dirs, path).join(path);
+
+        if !source_file_path.is_file() || !target_file_path.is_file() {
+            continue;
+        }
+
+        let hash = |file_path: &std::path::Path| {
+            hash_file(file_path, buffer_size).map_err(|source| DirDiffError::Read {
+                path: file_path.to_path_buf(),
+                source,
+            })
+        };
+
+        file_hashes.insert(
+            path.clone(),
+            (hash(&source_file_path)?, hash(&target_file_path)?),
+        );
+    }
+
+    Ok(file_hashes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_lists_the_hash_of_each_file() {
+        let dir = tempfile::tempdir().unwrap();
+
+        std::fs::create_dir(dir.path().join("dir")).unwrap();
+        std::fs::write(dir.path().join("dir/empty"), "").unwrap();
+        std::fs::write(dir.path().join("hello"), "hello\n").unwrap();
+
+        let listing = ["dir", "dir/empty", "hello"].map(std::path::PathBuf::from);
+
+        let manifest = || {
+            let mut out = Vec::new();
+            print_manifest(&mut out, &listing, dir.path(), 4).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        // the same hashes as sha256sum, even with a buffer smaller than the files
+        assert_eq!(
+            manifest(),
+            "dir/empty\te3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855\n\
+             hello\t5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03\n"
+        );
+        assert_eq!(manifest(), manifest());
+    }
+
+    #[test]
+    fn directory_is_diffed_against_the_manifest_hashes() {
+        let dir = tempfile::tempdir().unwrap();
+
+        std::fs::create_dir(dir.path().join("dir")).unwrap();
+        for path in ["altered", "dir/kept", "removed"] {
+            std::fs::write(dir.path().join(path), path).unwrap();
+        }
+
+        let listing = ["altered", "dir", "dir/kept", "removed"].map(std::path::PathBuf::from);
+
+        let mut manifest = Vec::new();
+        print_manifest(&mut manifest, &listing, dir.path(), 4096).unwrap();
+
+        // the directory drifts from the saved manifest
+        std::fs::write(dir.path().join("altered"), "altered later").unwrap();
+        std::fs::remove_file(dir.path().join("removed")).unwrap();
+        std::fs::write(dir.path().join("added"), "added").unwrap();
+
+        let listing = ["added", "altered", "dir", "dir/kept"].map(std::path::PathBuf::from);
+
+        let dir_diff = diff_against_manifest(
+            parse_manifest(&manifest, "manifest".as_ref()).unwrap(),
+            &listing,
+            dir.path(),
+            PathCase::Sensitive,
+            4096,
+        )
+        .unwrap();
+
+        assert_eq!(
+            dir_diff
+                .iter()
+                .map(|diff_fragment| format!("{:?}", diff_fragment))
+                .collect::<Vec<_>>(),
+            [
+                r#"Added("added")"#,
+                r#"Similar("altered", Some(Changed))"#,
+                r#"Similar("dir/kept", Some(Unchanged))"#,
+                r#"Removed("removed")"#,
+            ]
+        );
+    }
+}
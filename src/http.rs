@@ -0,0 +1,245 @@
+use crate::{hash, interrupt, DirDiffError};
+
+// a manifest: the relative path and hash of every file
+type Manifest = Vec<(std::path::PathBuf, String)>;
+
+pub fn is_url(path: &std::path::Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| path.starts_with("http://") || path.starts_with("https://"))
+}
+
+fn http_error(url: &reqwest::Url) -> impl FnOnce(reqwest::Error) -> DirDiffError + '_ {
+    move |source| DirDiffError::Http {
+        url: url.to_string(),
+        source: source.without_url(),
+    }
+}
+
+fn get(
+    client: &reqwest::blocking::Client,
+    url: &reqwest::Url,
+) -> Result<reqwest::blocking::Response, DirDiffError> {
+    client
+        .get(url.clone())
+        .send()
+        .and_then(|response| response.error_for_status())
+        .map_err(http_error(url))
+}
+
+fn percent_decode(text: &str) -> String {
+    // Return the text with its %XX escapes decoded, as found in the links of directory indexes
+
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        let escaped_byte = match bytes[index] {
+            b'%' => text
+                .get(index + 1..index + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+
+        match escaped_byte {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn index_links(page: &str) -> Vec<String> {
+    // Return the links of a directory index page to the entries of the directory, in order and
+    // without duplicates. Links to parents, other sites, queries (sorting the index) and anchors
+    // are left out
+
+    let link_pattern = regex::Regex::new(r#"(?i)href\s*=\s*"([^"]*)""#).unwrap();
+
+    let mut links: Vec<String> = Vec::new();
+
+    for captures in link_pattern.captures_iter(page) {
+        let link = &captures[1];
+
+        let is_entry = !link.is_empty()
+            && !link.starts_with(['/', '?', '#'])
+            && !matches!(link.trim_end_matches('/'), "." | "..")
+            && !link.starts_with("./")
+            && !link.starts_with("../")
+            && !link.contains("://")
+            && !link.contains(['?', '#']);
+
+        if is_entry && !links.iter().any(|known_link| known_link == link) {
+            links.push(link.to_string());
+        }
+    }
+
+    links
+}
+
+fn crawl_index(
+    client: &reqwest::blocking::Client,
+    dir_url: &reqwest::Url,
+    dir_path: &std::path::Path,
+    buffer_size: usize,
+    manifest: &mut Manifest,
+) -> Result<(), DirDiffError> {
+    // Add every file of the directory index at dir_url, recursively, to the manifest, hashing
+    // their content as they are downloaded. Links ending with / are subdirectories
+
+    let page = get(client, dir_url)?.text().map_err(http_error(dir_url))?;
+
+    for link in index_links(&page) {
+        interrupt::check()?;
+
+        let entry_url = dir_url
+            .join(&link)
+            .map_err(|_| DirDiffError::InvalidUrl(link.clone()))?;
+        let entry_path = dir_path.join(percent_decode(link.trim_end_matches('/')));
+
+        if link.ends_with('/') {
+            crawl_index(client, &entry_url, &entry_path, buffer_size, manifest)?;
+        } else {
+            let mut response = get(client, &entry_url)?;
+
+            let file_hash = hash::hash_reader(&mut response, buffer_size).map_err(|source| {
+                DirDiffError::Read {
+                    path: std::path::PathBuf::from(entry_url.as_str()),
+                    source,
+                }
+            })?;
+
+            manifest.push((entry_path, file_hash));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn fetch_manifest(url: &std::path::Path, buffer_size: usize) -> Result<Manifest, DirDiffError> {
+    // Return the manifest at the URL, printed by --manifest and served over HTTP. A URL ending
+    // with / is a directory index instead (like the ones of Apache, nginx or `python -m
+    // http.server`): it is crawled and every file it lists is downloaded to build the manifest
+
+    let url_str = url.to_string_lossy();
+    let url =
+        reqwest::Url::parse(&url_str).map_err(|_| DirDiffError::InvalidUrl(url_str.to_string()))?;
+
+    let client = reqwest::blocking::Client::new();
+
+    if url.path().ends_with('/') {
+        let mut manifest = Manifest::new();
+        crawl_index(
+            &client,
+            &url,
+            std::path::Path::new(""),
+            buffer_size,
+            &mut manifest,
+        )?;
+
+        return Ok(manifest);
+    }
+
+    let content = get(&client, &url)?.bytes().map_err(http_error(&url))?;
+
+    hash::parse_manifest(&content, std::path::Path::new(url.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn serve(pages: &'static [(&'static str, &'static str)]) -> String {
+        // Serve the pages, by path, on a local port in the background, and return the URL of the
+        // server. Other paths are not found
+
+        use std::io::{BufRead, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let server_url = format!("http://{}", listener.local_addr().unwrap());
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = std::io::BufReader::new(&stream);
+
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+
+                // the headers are not needed
+                let mut header = String::new();
+                while reader.read_line(&mut header).unwrap() > 2 {
+                    header.clear();
+                }
+
+                let path = request_line.split(' ').nth(1).unwrap_or_default();
+
+                let (status, body) = match pages.iter().find(|(page_path, _)| *page_path == path) {
+                    Some((_, body)) => ("200 OK", *body),
+                    None => ("404 Not Found", ""),
+                };
+
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+            }
+        });
+
+        server_url
+    }
+
+    fn manifest(url: &str) -> Result<Vec<String>, DirDiffError> {
+        Ok(fetch_manifest(url.as_ref(), 4096)?
+            .iter()
+            .map(|(path, file_hash)| format!("{} {}", path.display(), file_hash))
+            .collect())
+    }
+
+    #[test]
+    fn fetches_manifests_and_crawls_directory_indexes() {
+        static PAGES: &[(&str, &str)] = &[
+            ("/manifest", "a.txt\tABC123\nsub/b c.txt\tdef456\n"),
+            (
+                "/site/",
+                r#"<a href="../">Parent</a> <a href="?C=N;O=D">Name</a>
+                   <a href="a.txt">a.txt</a> <a href="sub/">sub/</a>
+                   <a href="https://example.com/">elsewhere</a>"#,
+            ),
+            ("/site/a.txt", "a"),
+            ("/site/sub/", r#"<a href="b%20c.txt">b c.txt</a>"#),
+            ("/site/sub/b%20c.txt", "b c"),
+        ];
+
+        let server_url = serve(PAGES);
+
+        // hashes are compared in lower case
+        assert_eq!(
+            manifest(&format!("{}/manifest", server_url)).unwrap(),
+            ["a.txt abc123", "sub/b c.txt def456"]
+        );
+
+        assert_eq!(
+            manifest(&format!("{}/site/", server_url)).unwrap(),
+            [
+                format!("a.txt {}", hash::hash_bytes(b"a")),
+                format!("sub/b c.txt {}", hash::hash_bytes(b"b c")),
+            ]
+        );
+
+        assert!(matches!(
+            manifest(&format!("{}/missing", server_url)),
+            Err(DirDiffError::Http { .. })
+        ));
+    }
+}
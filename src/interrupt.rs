@@ -0,0 +1,64 @@
+static INTERRUPTED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+// the inner error of the errors returned by check, to tell them from other interrupted reads
+#[derive(Debug)]
+struct CtrlC;
+
+impl std::fmt::Display for CtrlC {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Ctrl-C was pressed")
+    }
+}
+
+impl std::error::Error for CtrlC {}
+
+pub fn install_handler() {
+    // Make Ctrl-C stop the diff at the next check instead of killing the process, so the lines
+    // already printed are flushed and no line is left half written (with its color still on).
+    // A second Ctrl-C exits right away, in case the diff is stuck on a slow read
+
+    let _ = ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, std::sync::atomic::Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+    });
+}
+
+pub fn check() -> std::io::Result<()> {
+    // Return an Interrupted error once Ctrl-C was pressed
+
+    match INTERRUPTED.load(std::sync::atomic::Ordering::SeqCst) {
+        true => Err(std::io::Error::new(std::io::ErrorKind::Interrupted, CtrlC)),
+        false => Ok(()),
+    }
+}
+
+pub fn is_ctrl_c(error: &std::io::Error) -> bool {
+    // Return whether the error was returned by check, and not by an interrupted system call
+
+    error.get_ref().is_some_and(|inner| inner.is::<CtrlC>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_check_errors_are_ctrl_c() {
+        let check_error = std::io::Error::new(std::io::ErrorKind::Interrupted, CtrlC);
+
+        assert!(is_ctrl_c(&check_error));
+        assert!(matches!(
+            crate::DirDiffError::from(check_error),
+            crate::DirDiffError::Interrupted
+        ));
+
+        let system_error = std::io::Error::from(std::io::ErrorKind::Interrupted);
+
+        assert!(!is_ctrl_c(&system_error));
+        assert!(matches!(
+            crate::DirDiffError::from(system_error),
+            crate::DirDiffError::Output(_)
+        ));
+    }
+}
@@ -0,0 +1,254 @@
+use std::io::Write;
+
+use crate::{hash, DiffCounts, DirDiff, DirDiffError, DirDiffFileContent};
+
+pub fn summary_json(counts: &DiffCounts) -> serde_json::Value {
+    serde_json::json!({
+        "removed": counts.removed,
+        "added": counts.added,
+        "similar": counts.similar,
+        "changed": counts.changed,
+        "unchanged": counts.unchanged,
+        "special": counts.special,
+        "inaccessible": counts.inaccessible,
+        "renamed": counts.renamed,
+        "renamed_changed": counts.renamed_changed,
+        "case_changed": counts.case_changed,
+    })
+}
+
+pub fn write_summary_file(
+    path: &std::path::Path,
+    dir_diff: &[DirDiff<std::path::PathBuf>],
+) -> Result<(), DirDiffError> {
+    // Write the JSON summary to a file, separately from the normal output
+
+    let summary = summary_json(&DiffCounts::from_dir_diff(dir_diff));
+
+    std::fs::write(path, format!("{}\n", summary)).map_err(|source| DirDiffError::Write {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+pub fn print_json(
+    out: &mut impl Write,
+    dir_diff: &[DirDiff<std::path::PathBuf>],
+    hide_similarities: bool,
+    file_hashes: &hash::FileHashes,
+    path_notes: &std::collections::HashMap<std::path::PathBuf, String>,
+) -> std::io::Result<()> {
+    // Print the diff as a JSON object. Hiding similarities only removes them from the entries, the
+    // summary always has every count so consumers don't need to know which options were given.
+    // Entries in file_hashes get a hash field, or source_hash and target_hash if they differ.
+    // Entries in path_notes get a note
+
+    let counts = DiffCounts::from_dir_diff(dir_diff);
+
+    let entries: Vec<serde_json::Value> = dir_diff
+        .iter()
+        .filter(|diff_fragment| {
+            !hide_similarities || !matches!(diff_fragment, DirDiff::Similar(..))
+        })
+        .map(|diff_fragment| {
+            let mut entry = serde_json::json!({
+                "path": diff_fragment.path().to_string_lossy(),
+                "status": diff_fragment.status().name(),
+            });
+
+            // the path of renamed files is their source path
+            match diff_fragment {
+                DirDiff::Renamed(_, to) => {
+                    entry["to"] = serde_json::json!(to.to_string_lossy());
+                }
+                DirDiff::RenamedChanged(_, to, similarity) => {
+                    entry["to"] = serde_json::json!(to.to_string_lossy());
+                    entry["similarity"] = serde_json::json!(similarity);
+                }
+                DirDiff::CaseChanged(_, to, content) => {
+                    entry["to"] = serde_json::json!(to.to_string_lossy());
+
+                    if let Some(content) = content {
+                        entry["content_changed"] =
+                            serde_json::json!(matches!(content, DirDiffFileContent::Changed));
+                    }
+                }
+                _ => {}
+            }
+
+            match file_hashes.get(diff_fragment.path()) {
+                Some((source_hash, target_hash)) if source_hash == target_hash => {
+                    entry["hash"] = serde_json::json!(source_hash);
+                }
+                Some((source_hash, target_hash)) => {
+                    entry["source_hash"] = serde_json::json!(source_hash);
+                    entry["target_hash"] = serde_json::json!(target_hash);
+                }
+                None => {}
+            }
+
+            if let Some(note) = path_notes.get(diff_fragment.path()) {
+                entry["note"] = serde_json::json!(note);
+            }
+
+            entry
+        })
+        .collect();
+
+    let output = serde_json::json!({
+        "entries": entries,
+        "summary": summary_json(&counts),
+    });
+
+    writeln!(out, "{}", output)
+}
+
+fn insert_tree_entry(
+    tree: &mut serde_json::Map<String, serde_json::Value>,
+    path: &std::path::Path,
+    status: &str,
+) {
+    // Insert the status of a path in the nested tree, creating the nodes of its parent directories
+    // if they are not in the tree yet (they may have been hidden)
+
+    let mut children = tree;
+    let mut components = path.components().peekable();
+
+    while let Some(component) = components.next() {
+        let node = children
+            .entry(component.as_os_str().to_string_lossy())
+            .or_insert_with(|| serde_json::json!({}));
+
+        if components.peek().is_none() {
+            node["status"] = serde_json::json!(status);
+            return;
+        }
+
+        children = node
+            .as_object_mut()
+            .unwrap()
+            .entry("children")
+            .or_insert_with(|| serde_json::json!({}))
+            .as_object_mut()
+            .unwrap();
+    }
+}
+
+pub fn print_json_tree(
+    out: &mut impl Write,
+    dir_diff: &[DirDiff<std::path::PathBuf>],
+    hide_similarities: bool,
+) -> std::io::Result<()> {
+    // Print the diff as nested JSON objects mirroring the directory hierarchy: each path component
+    // maps to an object with the status of the path and its children, if it has any
+
+    let counts = DiffCounts::from_dir_diff(dir_diff);
+
+    let mut tree = serde_json::Map::new();
+
+    for diff_fragment in dir_diff {
+        if hide_similarities && matches!(diff_fragment, DirDiff::Similar(..)) {
+            continue;
+        }
+
+        insert_tree_entry(
+            &mut tree,
+            diff_fragment.path(),
+            diff_fragment.status().name(),
+        );
+    }
+
+    let output = serde_json::json!({
+        "tree": tree,
+        "summary": summary_json(&counts),
+    });
+
+    writeln!(out, "{}", output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quiet_output_keeps_every_count() {
+        let dir_diff = [
+            DirDiff::Removed("removed".into()),
+            DirDiff::Added("added".into()),
+            DirDiff::Similar("dir".into(), None),
+            DirDiff::Similar("changed".into(), Some(DirDiffFileContent::Changed)),
+            DirDiff::Similar("unchanged".into(), Some(DirDiffFileContent::Unchanged)),
+        ];
+
+        let print = |hide_similarities: bool| {
+            let mut out = Vec::new();
+
+            print_json(
+                &mut out,
+                &dir_diff,
+                hide_similarities,
+                &hash::FileHashes::new(),
+                &std::collections::HashMap::new(),
+            )
+            .unwrap();
+
+            serde_json::from_slice::<serde_json::Value>(&out).unwrap()
+        };
+
+        let quiet_output = print(true);
+
+        // only the entries are hidden
+        assert_eq!(quiet_output["entries"].as_array().unwrap().len(), 2);
+        assert_eq!(quiet_output["summary"], print(false)["summary"]);
+
+        for (status, count) in [
+            ("removed", 1),
+            ("added", 1),
+            ("similar", 1),
+            ("changed", 1),
+            ("unchanged", 1),
+        ] {
+            assert_eq!(quiet_output["summary"][status], count, "{}", status);
+        }
+    }
+
+    #[test]
+    fn nests_entries_by_directory() {
+        let dir_diff = [
+            DirDiff::Similar("dir".into(), None),
+            DirDiff::Added("dir/added".into()),
+            DirDiff::Similar("dir/changed".into(), Some(DirDiffFileContent::Changed)),
+            DirDiff::Removed("top".into()),
+        ];
+
+        let mut out = Vec::new();
+        print_json_tree(&mut out, &dir_diff, false).unwrap();
+
+        let output: serde_json::Value = serde_json::from_slice(&out).unwrap();
+
+        assert_eq!(
+            output["tree"],
+            serde_json::json!({
+                "dir": {
+                    "status": "similar",
+                    "children": {
+                        "added": {"status": "added"},
+                        "changed": {"status": "changed"},
+                    },
+                },
+                "top": {"status": "removed"},
+            })
+        );
+
+        // the hidden directory is still the parent of its children
+        let mut out = Vec::new();
+        print_json_tree(&mut out, &dir_diff, true).unwrap();
+
+        let output: serde_json::Value = serde_json::from_slice(&out).unwrap();
+
+        assert_eq!(
+            output["tree"]["dir"],
+            serde_json::json!({"children": {"added": {"status": "added"}}})
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use std::io::Write;
+
+use crate::{DiffCounts, DirDiff, DirDiffFileContent};
+
+fn escape_xml(text: &str) -> String {
+    // Escape the text of an attribute or element
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+pub fn print_junit(
+    out: &mut impl Write,
+    dir_diff: &[DirDiff<std::path::PathBuf>],
+) -> std::io::Result<()> {
+    // Print the diff as a JUnit XML test suite with a test case per path: removed, added, changed
+    // and renamed paths fail, special and inaccessible files are skipped, and the other paths pass
+
+    let counts = DiffCounts::from_dir_diff(dir_diff);
+
+    let failures = counts.removed
+        + counts.added
+        + counts.changed
+        + counts.renamed
+        + counts.renamed_changed
+        + counts.case_changed;
+
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<testsuite name="dirdiff" tests="{}" failures="{}" errors="0" skipped="{}">"#,
+        dir_diff.len(),
+        failures,
+        counts.special + counts.inaccessible
+    )?;
+
+    for diff_fragment in dir_diff {
+        let path = diff_fragment.path().to_string_lossy();
+
+        let failure_message = match diff_fragment {
+            DirDiff::Removed(_) => Some(format!("{} is only in the source", path)),
+            DirDiff::Added(_) => Some(format!("{} is only in the target", path)),
+            DirDiff::Similar(_, Some(DirDiffFileContent::Changed)) => {
+                Some(format!("{} differs between source and target", path))
+            }
+            DirDiff::Renamed(_, to) => Some(format!("{} was moved to {}", path, to.display())),
+            DirDiff::RenamedChanged(_, to, similarity) => Some(format!(
+                "{} was moved to {} and changed ({}% similar)",
+                path,
+                to.display(),
+                similarity
+            )),
+            DirDiff::CaseChanged(_, to, Some(DirDiffFileContent::Changed)) => Some(format!(
+                "{} was renamed to {} and changed",
+                path,
+                to.display()
+            )),
+            DirDiff::CaseChanged(_, to, _) => {
+                Some(format!("{} was renamed to {}", path, to.display()))
+            }
+            DirDiff::Similar(_, _) => None,
+        };
+
+        write!(
+            out,
+            r#"  <testcase classname="dirdiff" name="{}""#,
+            escape_xml(&path)
+        )?;
+
+        match (failure_message, diff_fragment) {
+            (Some(message), _) => {
+                writeln!(out, ">")?;
+                writeln!(
+                    out,
+                    r#"    <failure type="{}" message="{}"/>"#,
+                    diff_fragment.status().name(),
+                    escape_xml(&message)
+                )?;
+                writeln!(out, "  </testcase>")?;
+            }
+            (None, DirDiff::Similar(_, Some(DirDiffFileContent::Special))) => {
+                writeln!(out, ">")?;
+                writeln!(
+                    out,
+                    r#"    <skipped message="special files are not compared"/>"#
+                )?;
+                writeln!(out, "  </testcase>")?;
+            }
+            (None, DirDiff::Similar(_, Some(DirDiffFileContent::Inaccessible))) => {
+                writeln!(out, ">")?;
+                writeln!(out, r#"    <skipped message="the path can't be read"/>"#)?;
+                writeln!(out, "  </testcase>")?;
+            }
+            (None, _) => writeln!(out, "/>")?,
+        }
+    }
+
+    writeln!(out, "</testsuite>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_a_test_case_per_path_with_the_failure_counts() {
+        let dir_diff = [
+            DirDiff::Removed("a&b".into()),
+            DirDiff::Added("<new>".into()),
+            DirDiff::Similar("diff".into(), Some(DirDiffFileContent::Changed)),
+            DirDiff::Similar("dir".into(), None),
+            DirDiff::Similar("fifo".into(), Some(DirDiffFileContent::Special)),
+            DirDiff::Similar("unchanged".into(), Some(DirDiffFileContent::Unchanged)),
+        ];
+
+        let mut out = Vec::new();
+        print_junit(&mut out, &dir_diff).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            [
+                r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+                r#"<testsuite name="dirdiff" tests="6" failures="3" errors="0" skipped="1">"#,
+                r#"  <testcase classname="dirdiff" name="a&amp;b">"#,
+                r#"    <failure type="removed" message="a&amp;b is only in the source"/>"#,
+                r#"  </testcase>"#,
+                r#"  <testcase classname="dirdiff" name="&lt;new&gt;">"#,
+                r#"    <failure type="added" message="&lt;new&gt; is only in the target"/>"#,
+                r#"  </testcase>"#,
+                r#"  <testcase classname="dirdiff" name="diff">"#,
+                r#"    <failure type="changed" message="diff differs between source and target"/>"#,
+                r#"  </testcase>"#,
+                r#"  <testcase classname="dirdiff" name="dir"/>"#,
+                r#"  <testcase classname="dirdiff" name="fifo">"#,
+                r#"    <skipped message="special files are not compared"/>"#,
+                r#"  </testcase>"#,
+                r#"  <testcase classname="dirdiff" name="unchanged"/>"#,
+                r#"</testsuite>"#,
+                "",
+            ]
+            .join("\n")
+        );
+    }
+}
@@ -1,321 +1,5661 @@
 use clap::Parser;
-use colored::Colorize;
-use glob::glob;
+use std::io::Write;
 
-#[derive(Parser)]
+mod binary;
+mod changelog;
+mod compare;
+mod dedup;
+mod error;
+mod external;
+mod flatten;
+#[cfg(feature = "git")]
+mod git;
+mod github;
+mod hash;
+#[cfg(feature = "http")]
+mod http;
+mod interrupt;
+mod json;
+mod junit;
+mod pager;
+mod patch;
+mod rename;
+mod state;
+mod sync;
+mod theme;
+mod tree;
+#[cfg(feature = "tui")]
+mod tui;
+mod walk;
+
+use compare::ContentComparator;
+use error::DirDiffError;
+use theme::ColorTheme;
+use walk::{explain_walk, walk_dir, WalkOptions};
+
+// options reading files after the diff, by its paths, which are not the real ones once rewritten
+const REWRITE_CONFLICTS: &[&str] = &[
+    "apply",
+    "show-hashes",
+    "dedup-report",
+    "detect-renames",
+    "external-diff",
+    "on-changed-run",
+    "print-matching-pairs",
+    "flatten",
+    "compare-to-manifest",
+    "manifest",
+];
+
+// options changing the diff once it is complete, or printing it differently
+const STREAMING_CONFLICTS: &[&str] = &[
+    "prune-empty",
+    "report-identical",
+    "natural-sort",
+    "locale-sort",
+    "detect-renames",
+    "relative-to",
+    "posix-paths",
+    "apply",
+    "flatten",
+    "compare-to-manifest",
+    "manifest",
+    "changed-files-only",
+    "show-hashes",
+    "print-matching-pairs",
+    "repeat",
+    "stable-output",
+    "counts",
+];
+
+// options that need the content of files or the diff itself
+const COUNT_ONLY_CONFLICTS: &[&str] = &[
+    "files",
+    "format",
+    "apply",
+    "manifest",
+    "compare-to-manifest",
+    "changed-files-only",
+    "detect-renames",
+    "dedup-report",
+    "flatten",
+    "repeat",
+];
+
+#[derive(Clone, Parser)]
 struct CliArgs {
+    #[clap(parse(from_os_str), required_unless_present_any = &["batch", "list-formats"])]
+    source_dir: Option<std::path::PathBuf>, // only missing in batch mode
     #[clap(parse(from_os_str))]
-    source_dir: std::path::PathBuf,
-    #[clap(parse(from_os_str))]
-    target_dir: std::path::PathBuf,
+    #[cfg_attr(
+        not(feature = "git"),
+        clap(
+            required_unless_present_any = &["batch", "manifest", "compare-to-manifest", "list-formats"]
+        )
+    )]
+    #[cfg_attr(
+        feature = "git",
+        clap(required_unless_present_any = &[
+            "since",
+            "batch",
+            "manifest",
+            "compare-to-manifest",
+            "list-formats"
+        ])
+    )]
+    target_dirs: Vec<std::path::PathBuf>, // a path is similar if it is in any of these
+
+    #[clap(long, conflicts_with_all = &["source-dir", "target-dirs", "apply"])]
+    batch: bool, // read `source<TAB>target` pairs from stdin and compare each of them
+    #[clap(long, requires = "batch")]
+    null_input: bool, // the batch pairs are separated by NUL instead of newline characters
+    #[clap(long, conflicts_with_all = &["target-dirs", "apply"])]
+    manifest: bool, // print the path and SHA-256 of every file in the source directory instead
+    #[clap(
+        long,
+        parse(from_os_str),
+        conflicts_with_all = &[
+            "target-dirs",
+            "manifest",
+            "apply",
+            "relative-to",
+            "print-matching-pairs",
+            "show-hashes",
+            "dedup-report",
+            "detect-renames",
+        ]
+    )]
+    compare_to_manifest: Option<std::path::PathBuf>, // diff the directory against a saved --manifest
+    #[clap(
+        long,
+        conflicts_with_all = &[
+            "compare-to-manifest",
+            "apply",
+            "relative-to",
+            "print-matching-pairs",
+            "show-hashes",
+            "dedup-report",
+            "detect-renames",
+        ]
+    )]
+    flatten: bool, // compare the file names (and contents with -f) wherever the files are
+    #[clap(long)]
+    expand_paths: bool, // expand `~` and `$VAR` in the directories (also the batch ones)
     #[clap(short, long)]
     quiet: bool, // don't show similarities (and changed/unchanged files if -f option is given)
+    #[clap(long, conflicts_with_all = &["manifest", "compare-to-manifest", "apply"])]
+    explain_filters: bool, // print which paths the filters drop and why, instead of the diff
     #[clap(short, long)]
-    depth: Option<u8>,
+    depth: Option<u8>, // only compare the paths at this depth
+    #[clap(long)]
+    dir_content: bool, // directories whose children have different names are changed
+    #[clap(long)]
+    include: Vec<String>, // glob patterns of the only paths to compare
+    #[clap(long)]
+    exclude: Vec<String>, // glob patterns of paths (and their content) not to compare
+    #[clap(long)]
+    exclude_dir: Vec<String>, // glob patterns of directory names not to enter (like node_modules)
+    #[clap(
+        long,
+        conflicts_with_all = &["depth", "include", "exclude", "exclude-dir", "skip-hidden", "explain-filters"]
+    )]
+    paths_from: Option<std::path::PathBuf>, // only compare the relative paths listed in this file
+    #[clap(long)]
+    skip_hidden: bool, // don't compare hidden paths (starting with a dot)
+    #[clap(long)]
+    no_follow_symlinks: bool, // don't compare the content of symlinks to directories
     #[clap(long)]
     no_color: bool,
+    #[clap(long, value_enum, default_value = "default")]
+    color_theme: ColorTheme, // colors and symbols used for each status
     #[clap(short, long)]
     files: bool, // whether to compare file contents
+    #[clap(long)]
+    natural_sort: bool, // print the paths with numbers sorted by value (file2 before file10)
+    #[clap(long, conflicts_with = "natural-sort")]
+    locale_sort: bool, // print paths in the collation order of $LC_COLLATE (Unicode root or Arabic)
+    #[clap(long)]
+    external_diff: Option<String>, // open changed files in this diff tool at the end (implies -f)
+    #[clap(long)]
+    on_changed_run: Option<String>, // run this on each added and changed file (implies -f)
+    #[clap(long)]
+    pager: bool, // show the output in $PAGER (or less) when it goes to a terminal
+    #[clap(long)]
+    no_summary: bool, // don't print the summary line after the diff
+    #[clap(long)]
+    counts: bool, // print the number of files and directories on each side before the diff (text)
+    #[clap(long)]
+    dir_summary: bool, // also print the number of differences in each top-level directory
+    #[clap(long, value_enum, default_value = "text")]
+    format: OutputFormat,
+    #[clap(long)]
+    ascii_tree: bool, // draw the branches of the tree format with ASCII instead of box-drawing
+    #[clap(long, default_value_t = 4, parse(try_from_str = parse_indent_width))]
+    tree_indent: usize, // width of each level of the tree format, branch included
+    #[clap(long, exclusive = true)]
+    list_formats: bool, // print the values of --format with a description of each, then exit
+    #[clap(long, value_enum, default_value = DEFAULT_PATH_CASE)]
+    path_case: PathCase, // whether paths differing only in case are the same path
+    #[clap(long, default_value_t = 3)]
+    context: usize, // number of unchanged lines around each hunk of the patch format
+    #[clap(long, value_enum, default_value = "myers")]
+    diff_algorithm: patch::DiffAlgorithm, // how lines are matched in the patch and diffstat formats
+    #[clap(long, parse(from_os_str))]
+    relative_to: Option<std::path::PathBuf>, // report paths relative to this directory
+    #[clap(long)]
+    posix_paths: bool, // print paths with / as separator, even on windows
+    #[clap(long, conflicts_with_all = &["locale-sort", "path-case"])]
+    stable_output: bool, // the same output bytes on every run and machine, for golden files
+    // the paths left out by the filters would not be mirrored
+    #[clap(
+        long,
+        conflicts_with_all = &["depth", "include", "exclude", "exclude-dir", "skip-hidden", "paths-from"]
+    )]
+    apply: bool, // make the target directory mirror the source directory (implies -f)
+    #[clap(short, long, requires = "apply")]
+    yes: bool, // don't ask for confirmation before applying
+    #[clap(long, requires = "apply")]
+    dry_run: bool, // only print what applying would do
+    #[clap(long)]
+    ignore_errors_in: Vec<String>, // glob patterns of paths whose errors should not be printed
+    #[clap(long, value_enum)]
+    fail_on: Vec<DiffStatus>, // exit with code 1 if any path has one of these statuses
+    #[clap(long, use_value_delimiter = true, parse(try_from_str = parse_exit_code))]
+    exit_code: Vec<(DiffStatus, i32)>, // status=code pairs, exit with the highest matching code
+    #[clap(long)]
+    perms: bool, // also consider files with different permissions as changed
+    #[clap(long, requires = "perms", parse(try_from_str = parse_octal))]
+    perm_mask: Option<u32>, // only compare these permission bits (default 7777)
+    #[cfg(unix)]
+    #[clap(long)]
+    show_perms: bool, // print the octal mode of similar paths in both directories after them
+    #[cfg(unix)]
+    #[clap(long)]
+    sparse_aware: bool, // add a note to similar files using a different amount of disk space
+    #[clap(long)]
+    btime: bool, // also consider files with different creation times as changed
+    #[clap(long)]
+    timing: bool, // print how long the walk, sort, compare and output phases took to stderr
+    #[clap(long, hide = true, default_value_t = 1, parse(try_from_str = parse_repeat_count))]
+    repeat: usize, // compare this many times and print the compare time statistics, for profiling
+    #[clap(long, default_value_t = 1)]
+    threads_io: usize, // number of threads reading files to compare their content
+    #[clap(long, conflicts_with_all = STREAMING_CONFLICTS)]
+    content_threads_chunked: bool, // print each path once the files up to it are compared (text)
+    #[clap(long)]
+    compare_symlink_as_content: bool, // symlinks with the same target are unchanged (with -f)
+    #[clap(long)]
+    ignore_content_case: bool, // text files differing only in letter case are unchanged (with -f)
+    #[clap(long, parse(try_from_str = regex::Regex::new))]
+    ignore_lines_matching: Vec<regex::Regex>, // leave out matching lines of text files (with -f)
+    #[clap(long)]
+    ignore_final_newline: bool, // text files differing only by a final newline are unchanged (-f)
+    #[clap(long)]
+    head_lines: Option<usize>, // only compare the first lines of text files (with -f)
+    #[clap(long, parse(try_from_str = parse_content_rule))]
+    rule: Vec<ContentRule>, // `glob:mode` compares matching files with another mode (with -f)
+    #[clap(
+        long,
+        conflicts_with_all = &[
+            "ignore-content-case",
+            "ignore-lines-matching",
+            "ignore-final-newline",
+            "head-lines",
+        ]
+    )]
+    mmap: bool, // compare the content of files by mapping them in memory (with -f)
+    #[clap(
+        long,
+        parse(try_from_str = parse_size),
+        requires = "files",
+        conflicts_with_all = &[
+            "ignore-content-case",
+            "ignore-lines-matching",
+            "ignore-final-newline",
+            "head-lines",
+            "mmap",
+        ]
+    )]
+    sample: Option<usize>, // only compare the size and this many bytes at the start and end of files
+    #[clap(long)]
+    json_aware: bool, // .json files with equal values are unchanged, whatever their formatting (-f)
+    #[clap(long)]
+    dive_archives: bool, // zips and jars are compared by their entries, not their raw bytes (-f)
+    #[clap(long, default_value = "64K", parse(try_from_str = parse_buffer_size))]
+    buffer_size: usize, // bytes read at once from each file when comparing or hashing it
+    #[clap(long, default_value_t = 0)]
+    read_retries: u32, // retry comparing the content of files this many times if reading fails
+    #[clap(long, requires = "files")]
+    state_file: Option<std::path::PathBuf>, // reuse results stored here for unchanged files (-f)
+    #[clap(long, default_value_t = 0)]
+    strip_components: usize, // compare the single directory this many levels below each directory
+    #[clap(
+        long,
+        parse(try_from_str = parse_path_rewrite),
+        conflicts_with_all = REWRITE_CONFLICTS
+    )]
+    rewrite_source: Vec<PathRewrite>, // `regex=replacement` applied to source paths before the merge
+    #[clap(
+        long,
+        parse(try_from_str = parse_path_rewrite),
+        conflicts_with_all = REWRITE_CONFLICTS
+    )]
+    rewrite_target: Vec<PathRewrite>, // `regex=replacement` applied to target paths before the merge
+    #[clap(long, conflicts_with_all = &["relative-to", "print-matching-pairs"])]
+    ignore_missing_dir: bool, // compare a directory that doesn't exist as an empty directory
+    #[clap(long, parse(try_from_str = parse_duration))]
+    max_walk_time: Option<std::time::Duration>, // give up listing the directories after this time
+    #[clap(long)]
+    max_entries: Option<usize>, // abort if a directory listing has more entries than this
+    #[clap(long)]
+    one_file_system: bool, // don't list the content of mounted file systems (unix only)
+    #[clap(long, conflicts_with = "relative-to")]
+    print_matching_pairs: bool, // print the absolute source and target paths of similar entries
+    #[clap(long, parse(from_os_str))]
+    summary_file: Option<std::path::PathBuf>, // also write the summary as JSON to this file
+    #[clap(long, requires = "files", conflicts_with = "relative-to")]
+    show_hashes: bool, // print the SHA-256 of changed and unchanged files after their path
+    #[clap(long)]
+    dedup_report: bool, // list added (and removed) files that have identical content
+    #[clap(long)]
+    detect_renames: bool, // report removed files that were moved to an added path as renamed
+    #[clap(long, conflicts_with = "path-case")]
+    detect_case_renames: bool, // pair removed and added paths differing only in case as renamed
+    #[clap(long, default_value_t = 50, requires = "detect-renames", parse(try_from_str = parse_percentage))]
+    rename_threshold: u8, // how similar (in %) a moved file must be to count as renamed and changed
+    #[clap(long)]
+    prune_empty: bool, // hide similar paths whose subtree has no differences
+    #[clap(long, conflicts_with_all = &["quiet", "prune-empty", "apply"])]
+    report_identical: bool, // only show the paths that are the same in both directories
+    #[clap(long, conflicts_with_all = &["format", "relative-to", "apply", "manifest"])]
+    changed_files_only: bool, // only print the paths of added and changed files (implies -f)
+    #[clap(long, requires = "changed-files-only")]
+    print0: bool, // separate the --changed-files-only paths by NUL instead of newline characters
+    #[clap(long, conflicts_with_all = COUNT_ONLY_CONFLICTS)]
+    count_only: bool, // only count the removed and added paths, without building the diff
+    #[cfg(feature = "tui")]
+    #[clap(
+        long,
+        conflicts_with_all = &[
+            "format",
+            "changed-files-only",
+            "apply",
+            "pager",
+            "content-threads-chunked",
+            "counts",
+            "relative-to",
+            "flatten",
+            "rewrite-source",
+            "rewrite-target",
+        ]
+    )]
+    tui: bool, // browse the diff in the terminal, with the content diff of each file
+    #[cfg(feature = "git")]
+    #[clap(long, conflicts_with_all = &["target-dirs", "compare-to-manifest"])]
+    since: Option<String>, // compare the source directory (a git working tree) to this revision
+}
+
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+enum OutputFormat {
+    Text,
+    Patch,
+    Json,
+    Github,
+    Diffstat,
+    JsonTree,
+    Junit,
+    Tree,
+    Changelog,
+    Binary,
+}
+
+impl OutputFormat {
+    fn description(&self) -> &'static str {
+        match self {
+            OutputFormat::Text => "one line per path, followed by a summary",
+            OutputFormat::Patch => {
+                "a patch that transforms the source into the target (implies -f)"
+            }
+            OutputFormat::Json => {
+                "an object with every path and its status, and the count of each status"
+            }
+            OutputFormat::Github => {
+                "GitHub Actions annotations for removed, added and changed paths"
+            }
+            OutputFormat::Diffstat => {
+                "the number of inserted and deleted lines of each file (implies -f)"
+            }
+            OutputFormat::JsonTree => {
+                "like json, but the entries are nested objects mirroring the directory hierarchy"
+            }
+            OutputFormat::Junit => {
+                "a JUnit XML test suite, with a failed test case per differing path"
+            }
+            OutputFormat::Changelog => {
+                "a line per kind of difference listing its paths, for commit messages"
+            }
+            OutputFormat::Tree => {
+                "the paths as a tree like the tree command, followed by a summary"
+            }
+            OutputFormat::Binary => {
+                "length-prefixed binary frames, one per path, for programs (see the README)"
+            }
+        }
+    }
+}
+
+fn print_formats(out: &mut impl std::io::Write) -> std::io::Result<()> {
+    // Print the name of every output format and what it prints, the name first so completion
+    // scripts can take the first word of each line
+
+    for format in <OutputFormat as clap::ValueEnum>::value_variants() {
+        let name = clap::ValueEnum::to_possible_value(format)
+            .unwrap()
+            .get_name();
+
+        writeln!(out, "{:<10}{}", name, format.description())?;
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum PathCase {
+    Sensitive,
+    Insensitive,
 }
 
+// windows file systems are case-insensitive by default
+const DEFAULT_PATH_CASE: &str = if cfg!(windows) {
+    "insensitive"
+} else {
+    "sensitive"
+};
+
 #[derive(Debug)]
-enum DirDiff<T> {
+pub enum DirDiff<T> {
     Removed(T), // path is only in source
     Added(T),   // path is only in target
 
+    Renamed(T, T), // file only in source (first path) has the same content as one only in target
+    RenamedChanged(T, T, u8), // like Renamed, but the content is only this percentage similar
+
+    // path only in source and path only in target differing only by the case of their letters,
+    // with the comparison of their content like Similar
+    CaseChanged(T, T, Option<DirDiffFileContent>),
+
     Similar(T, Option<DirDiffFileContent>),
     // path is both source and target; if Option is None, then either the path points to a directory
     // or file content checking is disabled
 }
 
 #[derive(Debug)]
-enum DirDiffFileContent {
-    Unchanged, // file content are the same
-    Changed,   // file content are different
+pub enum DirDiffFileContent {
+    Unchanged,    // file content are the same
+    Changed,      // file content are different
+    Special,      // FIFO, socket or device file; the content is not compared
+    Inaccessible, // the file, or its directory on one side, can't be read (permission denied)
+}
+
+pub struct DiffOptions {
+    pub compare_file_content: bool,
+    pub comparator: Box<dyn ContentComparator>, // used when compare_file_content is set
+    pub io_threads: usize,                      // number of files compared at the same time
+    pub permission_mask: Option<u32>,           // compare the permission bits in this mask, if any
+    pub compare_creation_times: bool,           // files created at different times are changed
+    pub compare_dir_children: bool, // directories with different children names are changed
+    pub path_case: PathCase,        // how listings are sorted and paths matched
+    pub compare_symlink_targets: bool, // compare where symlinks point instead of their content
+    pub source_paths: RewrittenPaths, // real paths of the rewritten paths of the source listing
+    pub target_paths: RewrittenPaths, // real paths of the rewritten paths of the target listing
+}
+
+// real relative path of a file, by its rewritten path (only for paths that were rewritten)
+pub type RewrittenPaths = std::collections::HashMap<std::path::PathBuf, std::path::PathBuf>;
+
+fn real_path<'a>(
+    rewritten_paths: &'a RewrittenPaths,
+    path: &'a std::path::Path,
+) -> &'a std::path::Path {
+    rewritten_paths
+        .get(path)
+        .map(|real_path| real_path.as_path())
+        .unwrap_or(path)
+}
+
+// a transformation of the diff (filtering, renaming...) before it is printed
+pub type DiffPass =
+    Box<dyn Fn(Vec<DirDiff<std::path::PathBuf>>) -> Vec<DirDiff<std::path::PathBuf>> + Sync>;
+
+pub fn apply_passes(
+    dir_diff: Vec<DirDiff<std::path::PathBuf>>,
+    passes: &[DiffPass],
+) -> Vec<DirDiff<std::path::PathBuf>> {
+    // Run the passes in order on a complete diff. They are not part of DiffOptions, since they
+    // also run on the diffs that don't come from get_dir_diff (flattened, against a manifest),
+    // after renames are detected
+    passes
+        .iter()
+        .fold(dir_diff, |dir_diff, pass| pass(dir_diff))
+}
+
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+enum DiffStatus {
+    Removed,
+    Added,
+    Similar, // in both directories, content not compared
+    Unchanged,
+    Changed,
+    Special,
+    Inaccessible,
+    Renamed,
+    RenamedChanged,
+    CaseChanged,
+}
+
+#[derive(Default)]
+struct DiffCounts {
+    removed: usize,
+    added: usize,
+    similar: usize,
+    changed: usize,
+    unchanged: usize,
+    special: usize,
+    inaccessible: usize,
+    renamed: usize,
+    renamed_changed: usize,
+    case_changed: usize,
 }
 
-fn check_cli_args(args: &CliArgs) -> Result<(), Box<dyn std::error::Error>> {
-    // check if paths exist
-    if !args.source_dir.exists() {
-        return Err(format!("{} does not exist", args.source_dir.display()).into());
+impl DiffCounts {
+    fn from_dir_diff<T>(dir_diff: &[DirDiff<T>]) -> DiffCounts {
+        // Count the paths of each status
+
+        let mut counts = DiffCounts::default();
+
+        for diff_fragment in dir_diff {
+            counts.add(diff_fragment.status());
+        }
+
+        counts
     }
 
-    if !args.target_dir.exists() {
-        return Err(format!("{} does not exist", args.target_dir.display()).into());
+    fn add(&mut self, status: DiffStatus) {
+        match status {
+            DiffStatus::Removed => self.removed += 1,
+            DiffStatus::Added => self.added += 1,
+            DiffStatus::Similar => self.similar += 1,
+            DiffStatus::Unchanged => self.unchanged += 1,
+            DiffStatus::Changed => self.changed += 1,
+            DiffStatus::Special => self.special += 1,
+            DiffStatus::Inaccessible => self.inaccessible += 1,
+            DiffStatus::Renamed => self.renamed += 1,
+            DiffStatus::RenamedChanged => self.renamed_changed += 1,
+            DiffStatus::CaseChanged => self.case_changed += 1,
+        }
     }
+}
 
-    // check if paths are directories
-    if !args.source_dir.is_dir() {
-        return Err(format!("{} is not a directory", args.source_dir.display()).into());
+impl DiffStatus {
+    fn name(&self) -> &'static str {
+        // same name as the command line value
+        clap::ValueEnum::to_possible_value(self).unwrap().get_name()
     }
+}
 
-    if !args.target_dir.is_dir() {
-        return Err(format!("{} is not a directory", args.target_dir.display()).into());
+impl<T> DirDiff<T> {
+    fn path(&self) -> &T {
+        // renamed files are at the place of their source path
+        match self {
+            DirDiff::Removed(path)
+            | DirDiff::Added(path)
+            | DirDiff::Similar(path, _)
+            | DirDiff::Renamed(path, _)
+            | DirDiff::RenamedChanged(path, _, _)
+            | DirDiff::CaseChanged(path, _, _) => path,
+        }
     }
 
-    Ok(())
+    fn status(&self) -> DiffStatus {
+        match self {
+            DirDiff::Removed(_) => DiffStatus::Removed,
+            DirDiff::Added(_) => DiffStatus::Added,
+            DirDiff::Similar(_, None) => DiffStatus::Similar,
+            DirDiff::Similar(_, Some(DirDiffFileContent::Unchanged)) => DiffStatus::Unchanged,
+            DirDiff::Similar(_, Some(DirDiffFileContent::Changed)) => DiffStatus::Changed,
+            DirDiff::Similar(_, Some(DirDiffFileContent::Special)) => DiffStatus::Special,
+            DirDiff::Similar(_, Some(DirDiffFileContent::Inaccessible)) => DiffStatus::Inaccessible,
+            DirDiff::Renamed(_, _) => DiffStatus::Renamed,
+            DirDiff::RenamedChanged(_, _, _) => DiffStatus::RenamedChanged,
+            DirDiff::CaseChanged(_, _, _) => DiffStatus::CaseChanged,
+        }
+    }
 }
 
-fn glob_pattern_from_path_buf(path_buf: &std::path::PathBuf, depth: Option<u8>) -> String {
-    // Return a glob pattern for every file in a directory (recursively) from a PathBuf, assumed to
-    // be a directory
+fn parse_exit_code(value: &str) -> Result<(DiffStatus, i32), String> {
+    // Parse a `status=code` pair. Processes can only exit with codes from 0 to 255, higher ones
+    // would be truncated
 
-    match depth {
-        None => format!("{}{}", path_buf.as_path().to_str().unwrap(), "/**/*"),
-        Some(d) => {
-            let mut output = String::from(path_buf.as_path().to_str().unwrap());
+    let (status, code) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected status=code, got {}", value))?;
 
-            for _ in 0..d {
-                output.push_str("/*");
-            }
+    let status = <DiffStatus as clap::ValueEnum>::from_str(status, true)?;
+    let code = code
+        .parse::<u8>()
+        .map_err(|_| format!("exit codes go from 0 to 255, got {}", code))?;
 
-            output
-        }
+    Ok((status, i32::from(code)))
+}
+
+fn gated_exit_code(
+    statuses: impl Iterator<Item = DiffStatus>,
+    exit_codes: &[(DiffStatus, i32)],
+    fail_on: &[DiffStatus],
+) -> i32 {
+    // Return the exit code of the statuses of the paths: the highest code given to one of them
+    // with --exit-code, or 1 if one of them is gated on with --fail-on, and 0 otherwise
+
+    statuses
+        .flat_map(|status| {
+            exit_codes
+                .iter()
+                .filter(move |(mapped_status, _)| *mapped_status == status)
+                .map(|(_, code)| *code)
+                .chain(fail_on.contains(&status).then_some(1))
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+fn parse_duration(value: &str) -> Result<std::time::Duration, String> {
+    // Parse a duration such as 500ms, 10s, 2m or 1h. A number without unit is in seconds
+
+    let (number, seconds_per_unit) = if let Some(number) = value.strip_suffix("ms") {
+        (number, 0.001)
+    } else if let Some(number) = value.strip_suffix('s') {
+        (number, 1.0)
+    } else if let Some(number) = value.strip_suffix('m') {
+        (number, 60.0)
+    } else if let Some(number) = value.strip_suffix('h') {
+        (number, 3600.0)
+    } else {
+        (value, 1.0)
+    };
+
+    let number = number.parse::<f64>().map_err(|e| e.to_string())?;
+
+    std::time::Duration::try_from_secs_f64(number * seconds_per_unit).map_err(|e| e.to_string())
+}
+
+fn parse_percentage(value: &str) -> Result<u8, String> {
+    // Parse a percentage between 0 and 100, with or without a % sign
+
+    let percentage = value
+        .trim_end_matches('%')
+        .parse::<u8>()
+        .map_err(|e| e.to_string())?;
+
+    match percentage {
+        0..=100 => Ok(percentage),
+        _ => Err(format!("{} is more than 100%", value)),
     }
 }
 
-fn get_dir_listing(dir_path: &std::path::PathBuf, depth: Option<u8>) -> Vec<std::path::PathBuf> {
-    // Return a full recursive directory listing
+#[derive(Clone)]
+pub struct PathRewrite {
+    pattern: regex::Regex, // the first match in a relative path is replaced
+    replacement: String,   // can refer to the groups of the pattern ($1, ${name})
+}
 
-    let absolute_dir_path = std::fs::canonicalize(dir_path).unwrap();
+fn parse_path_rewrite(value: &str) -> Result<PathRewrite, String> {
+    // Parse a `regex=replacement` rewrite rule. The regex ends at the first `=`
 
-    let glob_pattern = glob_pattern_from_path_buf(&absolute_dir_path, depth);
+    let (pattern, replacement) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected regex=replacement, got {}", value))?;
 
-    let maybe_paths = glob(&glob_pattern).expect("Failed to read glob pattern");
+    Ok(PathRewrite {
+        pattern: regex::Regex::new(pattern).map_err(|e| e.to_string())?,
+        replacement: replacement.to_string(),
+    })
+}
 
-    let mut paths: Vec<std::path::PathBuf> = Vec::new();
+// the ways of comparing the content of files that can be combined, globally or by --rule
+#[derive(Clone, Default)]
+struct ContentModes {
+    ignore_case: bool,
+    ignored_lines: Vec<regex::Regex>,
+    ignore_final_newline: bool,
+    head_lines: Option<usize>,
+    json_aware: bool,
+    dive_archives: bool,
+}
 
-    // remove the parent directory from the paths, so the diffs don't show everything as different
-    for maybe_path in maybe_paths {
-        let path = match maybe_path {
-            Ok(p) => p,
-            Err(e) => {
-                // print the error if it doesn't have permission to read the dir, or other errors
-                eprintln!("{}", e);
-                continue;
-            }
-        };
+#[derive(Clone)]
+enum ContentMode {
+    IgnoreContentCase,
+    IgnoreLinesMatching(regex::Regex),
+    IgnoreFinalNewline,
+    HeadLines(usize),
+    JsonAware,
+    DiveArchives,
+}
 
-        // if the path doesn't have any parent directories, then just add it
-        if path.components().count() == 1 {
-            paths.push(path);
-            continue;
+#[derive(Clone)]
+pub struct ContentRule {
+    pattern: glob::Pattern, // relative paths of the files the mode applies to
+    mode: ContentMode,
+}
+
+impl ContentModes {
+    fn from_args(args: &CliArgs) -> Self {
+        ContentModes {
+            ignore_case: args.ignore_content_case,
+            ignored_lines: args.ignore_lines_matching.clone(),
+            ignore_final_newline: args.ignore_final_newline,
+            head_lines: args.head_lines,
+            json_aware: args.json_aware,
+            dive_archives: args.dive_archives,
         }
+    }
 
-        // otherwise remove the parent directory
-        paths.push(path.strip_prefix(&absolute_dir_path).unwrap().to_path_buf());
+    fn add(&mut self, mode: &ContentMode) {
+        match mode {
+            ContentMode::IgnoreContentCase => self.ignore_case = true,
+            ContentMode::IgnoreLinesMatching(regex) => self.ignored_lines.push(regex.clone()),
+            ContentMode::IgnoreFinalNewline => self.ignore_final_newline = true,
+            ContentMode::HeadLines(line_count) => self.head_lines = Some(*line_count),
+            ContentMode::JsonAware => self.json_aware = true,
+            ContentMode::DiveArchives => self.dive_archives = true,
+        }
     }
 
-    paths
-}
+    fn fingerprint(&self) -> String {
+        // Return a description of the modes, equal for modes that compare files the same way
 
-fn get_dir_diff(
-    source_dir_listing: &Vec<std::path::PathBuf>,
-    target_dir_listing: &Vec<std::path::PathBuf>,
-    source_dir: &std::path::PathBuf,
-    target_dir: &std::path::PathBuf,
-    compare_file_content: bool,
-) -> Vec<DirDiff<std::path::PathBuf>> {
-    // Return diff between two directories
-    // NOTE: this function assumes both directory listings are sorted by unicode values
+        let ignored_lines: Vec<&str> = self.ignored_lines.iter().map(|r| r.as_str()).collect();
 
-    let longest_listing = std::cmp::max(source_dir_listing.len(), target_dir_listing.len());
+        format!(
+            "ignore-case={} ignored-lines={:?} ignore-final-newline={} head-lines={:?} json={} \
+             archives={}",
+            self.ignore_case,
+            ignored_lines,
+            self.ignore_final_newline,
+            self.head_lines,
+            self.json_aware,
+            self.dive_archives
+        )
+    }
 
-    // indexes for both vectors
-    let mut source_index = 0;
-    let mut target_index = 0;
+    fn is_text(&self) -> bool {
+        self.ignore_case
+            || !self.ignored_lines.is_empty()
+            || self.ignore_final_newline
+            || self.head_lines.is_some()
+    }
+}
 
-    let mut diff_output = Vec::<DirDiff<std::path::PathBuf>>::new();
+fn comparator_fingerprint(args: &CliArgs, same_dir: bool) -> String {
+    // Return the SHA-256 of every option changing how the content of files is compared, so
+    // results stored with other options are not reused
 
-    // go through both arrays at the same time, to ensure O(n) time
-    for _ in 0..longest_listing {
-        if source_dir_listing[source_index] < target_dir_listing[target_index] {
-            diff_output.push(DirDiff::Removed(source_dir_listing[source_index].clone()));
+    let mut fingerprint = match same_dir {
+        true => String::from("same-file"),
+        false => format!(
+            "{} sample={:?}",
+            ContentModes::from_args(args).fingerprint(),
+            args.sample
+        ),
+    };
 
-            source_index += 1;
-        } else if source_dir_listing[source_index] > target_dir_listing[target_index] {
-            diff_output.push(DirDiff::Added(target_dir_listing[target_index].clone()));
+    for rule in &args.rule {
+        let mut modes = ContentModes::default();
+        modes.add(&rule.mode);
 
-            target_index += 1;
-        } else {
-            // the source and target file paths are equal (relative to the parent directory)
+        fingerprint.push_str(&format!(" rule {}: {}", rule.pattern, modes.fingerprint()));
+    }
 
-            let source_file_path: std::path::PathBuf =
-                [source_dir, &source_dir_listing[source_index]]
-                    .iter()
-                    .collect();
+    hash::hash_bytes(fingerprint.as_bytes())
+}
 
-            let target_file_path: std::path::PathBuf =
-                [target_dir, &target_dir_listing[target_index]]
-                    .iter()
-                    .collect();
+fn parse_content_rule(value: &str) -> Result<ContentRule, String> {
+    // Parse a `glob:mode` rule, where the mode is one of the content comparison options, with its
+    // value after a `=` (`*.log:ignore-lines-matching=^\d{4}-`). The glob ends at the first `:`
 
-            if compare_file_content && source_file_path.is_file() != target_file_path.is_file() {
-                // if two paths are the same, but one is a file and the other a directory, then it
-                // is considered CHANGED
-                diff_output.push(DirDiff::Similar(
-                    source_dir_listing[source_index].clone(),
-                    Some(DirDiffFileContent::Changed),
-                ));
-            } else if compare_file_content
-                && source_file_path.is_file()
-                && target_file_path.is_file()
-            {
-                // read file contents
-                let source_file_content =
-                    std::fs::read_to_string::<std::path::PathBuf>(source_file_path)
-                        .expect("Unable to read file");
-
-                let target_file_content =
-                    std::fs::read_to_string::<std::path::PathBuf>(target_file_path)
-                        .expect("Unable to read file");
-
-                // compare file contents and add to diff
-                if source_file_content == target_file_content {
-                    diff_output.push(DirDiff::Similar(
-                        source_dir_listing[source_index].clone(),
-                        Some(DirDiffFileContent::Unchanged),
-                    ));
-                } else {
-                    diff_output.push(DirDiff::Similar(
-                        source_dir_listing[source_index].clone(),
-                        Some(DirDiffFileContent::Changed),
-                    ));
-                }
-            } else {
-                // don't check file content
-                diff_output.push(DirDiff::Similar(
-                    source_dir_listing[source_index].clone(),
-                    None,
-                ));
-            }
+    let (pattern, mode) = value
+        .split_once(':')
+        .ok_or_else(|| format!("expected glob:mode, got {}", value))?;
 
-            source_index += 1;
-            target_index += 1;
+    let (mode_name, mode_value) = match mode.split_once('=') {
+        Some((mode_name, mode_value)) => (mode_name, Some(mode_value)),
+        None => (mode, None),
+    };
+
+    let mode = match (mode_name, mode_value) {
+        ("ignore-content-case", None) => ContentMode::IgnoreContentCase,
+        ("ignore-lines-matching", Some(regex)) => {
+            ContentMode::IgnoreLinesMatching(regex::Regex::new(regex).map_err(|e| e.to_string())?)
+        }
+        ("ignore-final-newline", None) => ContentMode::IgnoreFinalNewline,
+        ("head-lines", Some(line_count)) => {
+            ContentMode::HeadLines(line_count.parse::<usize>().map_err(|e| e.to_string())?)
         }
+        ("json-aware", None) => ContentMode::JsonAware,
+        ("dive-archives", None) => ContentMode::DiveArchives,
+        _ => {
+            return Err(format!(
+                "unknown mode {}, expected ignore-content-case, ignore-lines-matching=REGEX, \
+                 ignore-final-newline, head-lines=N, json-aware or dive-archives",
+                mode
+            ))
+        }
+    };
 
-        // add the remaining items to the dir diff if it reached the end of one dir listing
-        if source_index >= source_dir_listing.len() {
-            // add the remaining ADDED items of the other dir listing
-            for i in target_index..target_dir_listing.len() {
-                diff_output.push(DirDiff::Added(target_dir_listing[i].clone()));
-            }
+    Ok(ContentRule {
+        pattern: glob::Pattern::new(pattern).map_err(|e| e.to_string())?,
+        mode,
+    })
+}
 
-            break;
-        } else if target_index >= target_dir_listing.len() {
-            // add the remaining REMOVED items of the other dir listing
-            for i in source_index..source_dir_listing.len() {
-                diff_output.push(DirDiff::Removed(source_dir_listing[i].clone()));
-            }
+fn parse_repeat_count(value: &str) -> Result<usize, String> {
+    let count = value.parse::<usize>().map_err(|e| e.to_string())?;
 
-            break;
-        }
+    match count {
+        1.. => Ok(count),
+        _ => Err(String::from("the diff must be computed at least once")),
     }
+}
+
+fn parse_indent_width(value: &str) -> Result<usize, String> {
+    // Parse the width of a tree level, which must fit a branch character and a space
+
+    let width = value.parse::<usize>().map_err(|e| e.to_string())?;
 
-    diff_output
+    match width {
+        2.. => Ok(width),
+        _ => Err(String::from(
+            "the indentation must be at least 2 characters wide",
+        )),
+    }
 }
 
-fn print_dir_diff(
-    dir_diff: &Vec<DirDiff<std::path::PathBuf>>,
-    hide_similarities: bool,
-    color: bool,
-) {
-    for diff_fragment in dir_diff {
-        match diff_fragment {
-            DirDiff::Removed(path) => {
-                if color {
-                    println!("{} {}", "-".red(), path.to_str().unwrap().red());
-                } else {
-                    println!("- {}", path.to_str().unwrap());
-                }
-            }
-            DirDiff::Added(path) => {
-                if color {
-                    println!("{} {}", "+".green(), path.to_str().unwrap().green());
-                } else {
-                    println!("+ {}", path.to_str().unwrap());
-                }
-            }
-            DirDiff::Similar(path, file_content_similarity) => {
-                if !hide_similarities {
-                    match file_content_similarity {
-                        None => {
-                            println!("  {}", path.to_str().unwrap());
-                        }
-                        Some(DirDiffFileContent::Unchanged) => {
-                            println!(" {}", path.to_str().unwrap());
-                        }
-                        Some(DirDiffFileContent::Changed) => {
-                            println!("{} {}", "~".yellow(), path.to_str().unwrap().yellow());
-                        }
-                    }
-                }
-            }
-        }
+// smaller buffers make the number of reads explode without saving memory
+const MIN_BUFFER_SIZE: usize = 512;
+
+fn parse_size(value: &str) -> Result<usize, String> {
+    // Parse a number of bytes, optionally followed by K, M or G (powers of 1024)
+
+    let (number, multiplier) = match value.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&value[..value.len() - 1], 1 << 10),
+        Some('M') => (&value[..value.len() - 1], 1 << 20),
+        Some('G') => (&value[..value.len() - 1], 1 << 30),
+        _ => (value, 1),
+    };
+
+    number
+        .parse::<usize>()
+        .map_err(|e| e.to_string())?
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("{} is too big", value))
+}
+
+fn parse_buffer_size(value: &str) -> Result<usize, String> {
+    let buffer_size = parse_size(value)?;
+
+    match buffer_size >= MIN_BUFFER_SIZE {
+        true => Ok(buffer_size),
+        false => Err(format!(
+            "the buffer must be at least {} bytes",
+            MIN_BUFFER_SIZE
+        )),
     }
 }
 
-fn print_diff_summary(
-    dir_diff: &Vec<DirDiff<std::path::PathBuf>>,
-    hide_similarities: bool,
-    compare_file_content: bool,
-) {
-    let mut num_removed = 0;
-    let mut num_added = 0;
-    let mut num_similar = 0;
-    let mut num_changed = 0;
-    let mut num_unchanged = 0;
+fn parse_octal(value: &str) -> Result<u32, std::num::ParseIntError> {
+    u32::from_str_radix(value.trim_start_matches("0o"), 8)
+}
 
-    for diff_fragment in dir_diff {
-        match diff_fragment {
-            DirDiff::Removed(_) => num_removed += 1,
-            DirDiff::Added(_) => num_added += 1,
-            DirDiff::Similar(_, None) => num_similar += 1,
-            DirDiff::Similar(_, Some(DirDiffFileContent::Unchanged)) => num_unchanged += 1,
-            DirDiff::Similar(_, Some(DirDiffFileContent::Changed)) => num_changed += 1,
+fn check_dir_arg(dir: &std::path::Path, ignore_missing_dir: bool) -> Result<(), DirDiffError> {
+    // Check that a directory given as an argument is one, or is missing and missing directories
+    // are compared as empty ones. Symlinks are followed, but the errors name what they point to,
+    // since the path alone would look like a directory
+
+    let symlink_target = match dir.symlink_metadata() {
+        Ok(metadata) if metadata.file_type().is_symlink() => std::fs::read_link(dir).ok(),
+        _ => None,
+    };
+
+    if !dir.exists() {
+        return match symlink_target {
+            // a dangling symlink is a mistake, not a directory that doesn't exist yet
+            Some(target) => Err(DirDiffError::BrokenSymlink {
+                path: dir.to_path_buf(),
+                target,
+            }),
+            None if ignore_missing_dir => Ok(()),
+            None => Err(DirDiffError::MissingDirectory(dir.to_path_buf())),
+        };
+    }
+
+    if !dir.is_dir() {
+        return match symlink_target {
+            Some(target) => Err(DirDiffError::SymlinkNotADirectory {
+                path: dir.to_path_buf(),
+                target,
+            }),
+            None => Err(DirDiffError::NotADirectory(dir.to_path_buf())),
+        };
+    }
+
+    Ok(())
+}
+
+fn check_cli_args(args: &CliArgs) -> Result<(), DirDiffError> {
+    // clap requires the source directory outside of batch mode, and batch mode fills it in
+    let source_dir = args.source_dir.as_ref().unwrap();
+
+    for dir in std::iter::once(source_dir).chain(&args.target_dirs) {
+        check_dir_arg(dir, args.ignore_missing_dir)?;
+    }
+
+    // a manifest only has the hashes of the files, not their content, flattened paths are only
+    // file names, and rewritten paths are not the paths of the files
+    if matches!(args.format, OutputFormat::Patch | OutputFormat::Diffstat) {
+        if args.compare_to_manifest.is_some() {
+            return Err(DirDiffError::IncompatibleFormat("--compare-to-manifest"));
+        } else if args.flatten {
+            return Err(DirDiffError::IncompatibleFormat("--flatten"));
+        } else if !args.rewrite_source.is_empty() {
+            return Err(DirDiffError::IncompatibleFormat("--rewrite-source"));
+        } else if !args.rewrite_target.is_empty() {
+            return Err(DirDiffError::IncompatibleFormat("--rewrite-target"));
         }
     }
 
-    let mut summary = String::new();
+    // the counts are a line of text before the diff
+    if args.counts && args.format != OutputFormat::Text {
+        return Err(DirDiffError::IncompatibleFormat("--counts"));
+    }
 
-    summary.push_str(&format!("{} removed, {} added", num_removed, num_added));
+    // the entries are printed while the diff is computed, in the text format
+    if args.content_threads_chunked && args.format != OutputFormat::Text {
+        return Err(DirDiffError::IncompatibleFormat(
+            "--content-threads-chunked",
+        ));
+    }
 
-    // also add summary for similarities
-    if !hide_similarities {
-        summary.push_str(&format!(", {} similar", num_similar));
+    // applying can only mirror the source into one directory
+    if args.apply && args.target_dirs.len() > 1 {
+        return Err(DirDiffError::MultipleTargets);
+    }
 
-        if compare_file_content {
-            summary.push_str(&format!(
-                ", {} files changed, {} files unchanged",
-                num_changed, num_unchanged
-            ));
+    if let Some(relative_to) = &args.relative_to {
+        if !relative_to.exists() {
+            return Err(DirDiffError::MissingDirectory(relative_to.clone()));
         }
     }
 
-    println!("{}", summary);
+    Ok(())
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = CliArgs::parse();
+fn expand_path(path: &std::path::Path) -> Result<std::path::PathBuf, DirDiffError> {
+    // Return the path with `~` and environment variables (`$VAR` or `${VAR}`) expanded. Paths that
+    // are not UTF-8 can't be expanded, so they are kept as they are
 
-    // error if directories do not exist
-    check_cli_args(&args)?;
+    match path.to_str() {
+        Some(path_str) => shellexpand::full(path_str)
+            .map(|expanded_path| std::path::PathBuf::from(expanded_path.as_ref()))
+            .map_err(|e| DirDiffError::Expansion {
+                path: path.to_path_buf(),
+                message: e.to_string(),
+            }),
+        None => Ok(path.to_path_buf()),
+    }
+}
 
-    // list both directories
-    let source_dir_listing = get_dir_listing(&args.source_dir, args.depth);
-    let target_dir_listing = get_dir_listing(&args.target_dir, args.depth);
+fn expand_cli_args(args: &CliArgs) -> Result<CliArgs, DirDiffError> {
+    // Return the arguments with every directory expanded
 
-    // get diff
-    let dir_diff = get_dir_diff(
-        &source_dir_listing,
-        &target_dir_listing,
-        &args.source_dir,
-        &args.target_dir,
-        args.files,
-    );
+    Ok(CliArgs {
+        source_dir: args.source_dir.as_deref().map(expand_path).transpose()?,
+        target_dirs: args
+            .target_dirs
+            .iter()
+            .map(|target_dir| expand_path(target_dir))
+            .collect::<Result<Vec<_>, _>>()?,
+        relative_to: args.relative_to.as_deref().map(expand_path).transpose()?,
+        ..args.clone()
+    })
+}
+
+fn parse_patterns(patterns: &[String]) -> Result<Vec<glob::Pattern>, DirDiffError> {
+    Ok(patterns
+        .iter()
+        .map(|pattern| glob::Pattern::new(pattern))
+        .collect::<Result<Vec<_>, _>>()?)
+}
 
-    print_dir_diff(&dir_diff, args.quiet, !args.no_color);
-    print_diff_summary(&dir_diff, args.quiet, args.files);
+fn is_error_ignored(
+    path: &std::path::Path,
+    dir_path: &std::path::Path,
+    ignored_error_patterns: &[glob::Pattern],
+) -> bool {
+    // Return whether an error for this path should be silently dropped. Patterns can match either
+    // the absolute path or the path relative to the listed directory
 
-    Ok(())
+    let relative_path = path.strip_prefix(dir_path).unwrap_or(path);
+
+    ignored_error_patterns
+        .iter()
+        .any(|pattern| pattern.matches_path(path) || pattern.matches_path(relative_path))
+}
+
+fn normalize_dir_path(dir_path: &std::path::Path) -> std::path::PathBuf {
+    // Return the path without trailing slashes, `.` components and `dir/..` pairs, so equivalent
+    // spellings of a directory argument (`foo`, `foo/`, `./foo`, `foo/bar/..`) are the same path.
+    // `bar/..` is kept if bar is a symlink, since `..` then leads to the parent of its target
+
+    let mut normalized_path = std::path::PathBuf::new();
+
+    for component in dir_path.components() {
+        match component {
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir
+                if matches!(
+                    normalized_path.components().next_back(),
+                    Some(std::path::Component::Normal(_))
+                ) && !normalized_path.is_symlink() =>
+            {
+                normalized_path.pop();
+            }
+            // `/..` is `/`
+            std::path::Component::ParentDir
+                if matches!(
+                    normalized_path.components().next_back(),
+                    Some(std::path::Component::RootDir)
+                ) => {}
+            component => normalized_path.push(component),
+        }
+    }
+
+    // `.` or `foo/..`
+    if normalized_path.as_os_str().is_empty() {
+        normalized_path.push(".");
+    }
+
+    normalized_path
+}
+
+fn strip_dir_components(
+    dir_path: &std::path::Path,
+    components: usize,
+) -> Result<std::path::PathBuf, DirDiffError> {
+    // Return the directory that is `components` levels below dir_path, like `tar
+    // --strip-components`. Each level must only have a single directory, otherwise it isn't
+    // clear which one to compare. A missing directory stays missing
+
+    let mut stripped_dir_path = dir_path.to_path_buf();
+
+    for _ in 0..components {
+        if !stripped_dir_path.exists() {
+            break;
+        }
+
+        let entries = std::fs::read_dir(&stripped_dir_path)
+            .and_then(|read_dir| read_dir.collect::<std::io::Result<Vec<_>>>())
+            .map_err(|source| DirDiffError::Walk {
+                path: stripped_dir_path.clone(),
+                source,
+            })?;
+
+        match entries.as_slice() {
+            [entry] if entry.path().is_dir() => stripped_dir_path = entry.path(),
+            _ => return Err(DirDiffError::CannotStrip(stripped_dir_path)),
+        }
+    }
+
+    Ok(stripped_dir_path)
+}
+
+fn canonicalize_dir(dir_path: &std::path::Path) -> Result<std::path::PathBuf, DirDiffError> {
+    std::fs::canonicalize(dir_path).map_err(|source| DirDiffError::Walk {
+        path: dir_path.to_path_buf(),
+        source,
+    })
+}
+
+fn check_entry_count(
+    entry_count: usize,
+    dir_path: &std::path::Path,
+    max_entries: Option<usize>,
+) -> Result<(), DirDiffError> {
+    match max_entries {
+        Some(max_entries) if entry_count > max_entries => Err(DirDiffError::TooManyEntries {
+            path: dir_path.to_path_buf(),
+            max_entries,
+        }),
+        _ => Ok(()),
+    }
+}
+
+fn get_dir_listing(
+    dir_path: &std::path::Path,
+    walk_options: &WalkOptions,
+    ignored_error_patterns: &[glob::Pattern],
+    max_entries: Option<usize>,
+    print_error: &mut dyn FnMut(&DirDiffError),
+) -> Result<Vec<std::path::PathBuf>, DirDiffError> {
+    // Return a full recursive directory listing, relative to the directory. Stop with an error as
+    // soon as the listing gets longer than max_entries, instead of listing the whole tree. The
+    // paths that can't be listed are given to print_error, unless their errors are ignored
+
+    // walk the canonicalized directory, so `.` and `./dir` have the same errors as the absolute
+    // directory
+    let absolute_dir_path = canonicalize_dir(dir_path)?;
+
+    let mut paths: Vec<std::path::PathBuf> = Vec::new();
+
+    for maybe_path in walk_dir(&absolute_dir_path, walk_options) {
+        interrupt::check()?;
+
+        match maybe_path {
+            Ok(path) => paths.push(path),
+            Err(e @ DirDiffError::WalkTimeout(_)) => return Err(e),
+            Err(e) => {
+                // print the error if it doesn't have permission to read the dir, or other errors
+                let ignored = match &e {
+                    DirDiffError::Walk { path, .. } => {
+                        is_error_ignored(path, &absolute_dir_path, ignored_error_patterns)
+                    }
+                    _ => false,
+                };
+
+                if !ignored {
+                    print_error(&e);
+                }
+            }
+        }
+
+        check_entry_count(paths.len(), dir_path, max_entries)?;
+    }
+
+    Ok(paths)
+}
+
+fn read_path_list(list_path: &std::path::Path) -> Result<Vec<std::path::PathBuf>, DirDiffError> {
+    // Return the paths of a --paths-from file, one per line, relative to the compared directories.
+    // Blank lines are skipped, and a leading ./ is allowed, as printed by find or an SCM
+
+    let content = std::fs::read_to_string(list_path).map_err(|source| DirDiffError::Read {
+        path: list_path.to_path_buf(),
+        source,
+    })?;
+
+    let mut paths = Vec::new();
+
+    for line in content.lines() {
+        if line.is_empty() {
+            continue;
+        }
+
+        let path = std::path::Path::new(line)
+            .components()
+            .filter(|component| *component != std::path::Component::CurDir)
+            .collect::<std::path::PathBuf>();
+
+        // the paths are joined to the compared directories, so they must stay inside them
+        let is_relative = path
+            .components()
+            .all(|component| matches!(component, std::path::Component::Normal(_)));
+
+        if !is_relative || path.as_os_str().is_empty() {
+            return Err(DirDiffError::InvalidListedPath(std::path::PathBuf::from(
+                line,
+            )));
+        }
+
+        paths.push(path);
+    }
+
+    Ok(paths)
+}
+
+fn get_listed_paths(
+    dir_paths: &[std::path::PathBuf],
+    listed_paths: &[std::path::PathBuf],
+) -> Vec<std::path::PathBuf> {
+    // Return the listing of the listed paths that are in at least one of the directories, in
+    // place of walking them. The listing needs to go through sort_dir_listing to remove duplicates
+
+    listed_paths
+        .iter()
+        .filter(|path| {
+            dir_paths
+                .iter()
+                .any(|dir_path| dir_path.join(path).symlink_metadata().is_ok())
+        })
+        .cloned()
+        .collect()
+}
+
+fn is_inaccessible(path: &std::path::Path) -> bool {
+    // Return whether the path can't be looked up for lack of permissions, as opposed to not
+    // existing. This is the case of every path in a directory that can't be entered
+
+    matches!(
+        path.symlink_metadata(),
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied
+    )
+}
+
+fn is_special_file(path: &std::path::Path) -> bool {
+    // Return whether the path points to something that is neither a regular file nor a directory
+    // (FIFO, socket, block or character device). Reading these can block forever, so their
+    // content should never be compared
+
+    match std::fs::metadata(path) {
+        Ok(metadata) => {
+            let file_type = metadata.file_type();
+            !file_type.is_file() && !file_type.is_dir()
+        }
+        Err(_) => false,
+    }
+}
+
+fn get_union_dir_listing(
+    dir_paths: &[std::path::PathBuf],
+    walk_options: &WalkOptions,
+    ignored_error_patterns: &[glob::Pattern],
+    max_entries: Option<usize>,
+    print_error: &mut dyn FnMut(&DirDiffError),
+) -> Result<Vec<std::path::PathBuf>, DirDiffError> {
+    // Return the listing of every path that is in at least one of the directories. The listing
+    // needs to go through sort_dir_listing to remove duplicates
+
+    let mut paths: Vec<std::path::PathBuf> = Vec::new();
+
+    for dir_path in dir_paths {
+        paths.extend(get_dir_listing(
+            dir_path,
+            walk_options,
+            ignored_error_patterns,
+            max_entries,
+            print_error,
+        )?);
+
+        // the cap is on the whole listing, not on each directory
+        check_entry_count(paths.len(), dir_path, max_entries)?;
+    }
+
+    Ok(paths)
+}
+
+fn compare_paths(
+    a: &std::path::Path,
+    b: &std::path::Path,
+    path_case: PathCase,
+) -> std::cmp::Ordering {
+    // Compare paths component by component, ignoring case if needed
+
+    match path_case {
+        PathCase::Sensitive => a.cmp(b),
+        PathCase::Insensitive => {
+            let fold = |path: &std::path::Path| -> Vec<String> {
+                path.components()
+                    .map(|c| c.as_os_str().to_string_lossy().to_lowercase())
+                    .collect()
+            };
+
+            fold(a).cmp(&fold(b))
+        }
+    }
+}
+
+fn rewrite_dir_listing(
+    dir_listing: Vec<std::path::PathBuf>,
+    rewrites: &[PathRewrite],
+) -> Result<(Vec<std::path::PathBuf>, RewrittenPaths), DirDiffError> {
+    // Apply every rewrite, in order, to each path of the listing, and return the rewritten listing
+    // with the real path of each rewritten path. Paths that are not UTF-8 are kept as they are
+
+    let mut rewritten_listing = Vec::with_capacity(dir_listing.len());
+    let mut rewritten_paths = RewrittenPaths::new();
+    let mut listed_paths = std::collections::HashSet::new();
+
+    for path in dir_listing {
+        let rewritten_path = match path.to_str() {
+            Some(path_str) => std::path::PathBuf::from(rewrites.iter().fold(
+                path_str.to_string(),
+                |path_str, rewrite| {
+                    rewrite
+                        .pattern
+                        .replace(&path_str, rewrite.replacement.as_str())
+                        .into_owned()
+                },
+            )),
+            None => path.clone(),
+        };
+
+        // two files can't be compared under the same path
+        if !listed_paths.insert(rewritten_path.clone()) {
+            return Err(DirDiffError::RewriteCollision(rewritten_path));
+        }
+
+        if rewritten_path != path {
+            rewritten_paths.insert(rewritten_path.clone(), path);
+        }
+
+        rewritten_listing.push(rewritten_path);
+    }
+
+    Ok((rewritten_listing, rewritten_paths))
+}
+
+fn sort_dir_listing(dir_listing: &mut Vec<std::path::PathBuf>, path_case: PathCase) {
+    // Sort a listing in the order get_dir_diff expects, and remove paths that are the same
+    dir_listing.sort_by(|a, b| compare_paths(a, b, path_case));
+    dir_listing.dedup_by(|a, b| compare_paths(a, b, path_case) == std::cmp::Ordering::Equal);
+}
+
+fn find_target_dir<'a>(
+    target_dirs: &'a [std::path::PathBuf],
+    path: &std::path::Path,
+) -> &'a std::path::PathBuf {
+    // Return the first target directory that contains the path, or the first target directory if
+    // none of them do
+
+    target_dirs
+        .iter()
+        .find(|target_dir| target_dir.join(path).symlink_metadata().is_ok())
+        .unwrap_or(&target_dirs[0])
+}
+
+#[cfg(unix)]
+fn permissions_differ(
+    source_path: &std::path::Path,
+    target_path: &std::path::Path,
+    permission_mask: u32,
+) -> std::io::Result<bool> {
+    // Return whether the permission bits in the mask are different for both paths
+    use std::os::unix::fs::PermissionsExt;
+
+    let source_mode = std::fs::metadata(source_path)?.permissions().mode();
+    let target_mode = std::fs::metadata(target_path)?.permissions().mode();
+
+    Ok(source_mode & permission_mask & 0o7777 != target_mode & permission_mask & 0o7777)
+}
+
+#[cfg(unix)]
+fn find_mode_notes(
+    dir_diff: &[DirDiff<std::path::PathBuf>],
+    source_dir: &std::path::Path,
+    target_dirs: &[std::path::PathBuf],
+) -> Vec<(std::path::PathBuf, String)> {
+    // Return a note with the permission bits of each similar path, once if they are the same in
+    // both directories and as source -> target otherwise. Paths that can't be read are left out
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = |path: &std::path::Path| {
+        std::fs::metadata(path)
+            .ok()
+            .map(|metadata| metadata.permissions().mode() & 0o7777)
+    };
+
+    dir_diff
+        .iter()
+        .filter_map(|diff_fragment| match diff_fragment {
+            DirDiff::Similar(path, _) => Some(path),
+            _ => None,
+        })
+        .filter_map(|path| {
+            let source_mode = mode(&source_dir.join(path))?;
+            let target_mode = mode(&find_target_dir(target_dirs, path).join(path))?;
+
+            let note = match source_mode == target_mode {
+                true => format!("mode {:o}", source_mode),
+                false => format!("mode {:o} -> {:o}", source_mode, target_mode),
+            };
+
+            Some((path.clone(), note))
+        })
+        .collect()
+}
+
+#[cfg(unix)]
+fn find_allocation_notes(
+    dir_diff: &[DirDiff<std::path::PathBuf>],
+    source_dir: &std::path::Path,
+    target_dirs: &[std::path::PathBuf],
+) -> Vec<(std::path::PathBuf, String)> {
+    // Return a note with the allocated size of each similar file whose allocated size differs in
+    // both directories, such as a sparse file with holes and a copy of it that was filled in.
+    // Files that can't be read are left out
+    use std::os::unix::fs::MetadataExt;
+
+    // st_blocks is in 512-byte units, whatever the block size of the file system
+    let allocated_size = |path: &std::path::Path| {
+        std::fs::metadata(path)
+            .ok()
+            .filter(|metadata| metadata.is_file())
+            .map(|metadata| metadata.blocks() * 512)
+    };
+
+    dir_diff
+        .iter()
+        .filter_map(|diff_fragment| match diff_fragment {
+            DirDiff::Similar(path, _) => Some(path),
+            _ => None,
+        })
+        .filter_map(|path| {
+            let source_size = allocated_size(&source_dir.join(path))?;
+            let target_size = allocated_size(&find_target_dir(target_dirs, path).join(path))?;
+
+            (source_size != target_size).then(|| {
+                (
+                    path.clone(),
+                    format!("allocated {} -> {} bytes", source_size, target_size),
+                )
+            })
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+fn permissions_differ(
+    source_path: &std::path::Path,
+    target_path: &std::path::Path,
+    _permission_mask: u32,
+) -> std::io::Result<bool> {
+    // Return whether only one of the paths is read-only, the only permission outside of unix
+
+    let source_readonly = std::fs::metadata(source_path)?.permissions().readonly();
+    let target_readonly = std::fs::metadata(target_path)?.permissions().readonly();
+
+    Ok(source_readonly != target_readonly)
+}
+
+fn dir_children_differ(
+    source_dir_path: &std::path::Path,
+    target_dir_path: &std::path::Path,
+) -> std::io::Result<bool> {
+    // Return whether the names of the immediate children of both directories are different
+
+    let children = |dir_path: &std::path::Path| {
+        std::fs::read_dir(dir_path)?
+            .map(|entry| Ok(entry?.file_name()))
+            .collect::<std::io::Result<std::collections::BTreeSet<_>>>()
+    };
+
+    Ok(children(source_dir_path)? != children(target_dir_path)?)
+}
+
+type PendingComparison = (usize, std::path::PathBuf, std::path::PathBuf);
+
+// prints entries of the diff as soon as they are complete, in order
+type DiffPrinter<'a> = &'a mut dyn FnMut(&[DirDiff<std::path::PathBuf>]) -> std::io::Result<()>;
+
+fn compare_files(
+    pending_comparisons: &[PendingComparison],
+    options: &DiffOptions,
+    on_result: &mut dyn FnMut(usize, std::io::Result<DirDiffFileContent>),
+) {
+    // Compare the content of each pair of files, reading at most options.io_threads pairs at the
+    // same time. on_result gets the index and result of each pair in the order of the pairs, as
+    // soon as the pair and every pair before it are compared
+
+    let compare = |(_, source_file_path, target_file_path): &PendingComparison| {
+        interrupt::check()?;
+
+        options
+            .comparator
+            .compare(source_file_path, target_file_path)
+    };
+
+    if options.io_threads <= 1 {
+        for (i, pending_comparison) in pending_comparisons.iter().enumerate() {
+            on_result(i, compare(pending_comparison));
+        }
+
+        return;
+    }
+
+    // every thread takes the next pair that nobody is comparing yet
+    let next_comparison = std::sync::atomic::AtomicUsize::new(0);
+
+    std::thread::scope(|scope| {
+        let (sender, receiver) = std::sync::mpsc::channel();
+
+        for _ in 0..options.io_threads {
+            let sender = sender.clone();
+
+            scope.spawn(|| {
+                let sender = sender;
+
+                loop {
+                    let i = next_comparison.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
+                    if i >= pending_comparisons.len() {
+                        break;
+                    }
+
+                    if sender.send((i, compare(&pending_comparisons[i]))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        // the receiver ends once every thread is done with its sender
+        drop(sender);
+
+        // results that came before the ones of earlier pairs wait here
+        let mut reorder_buffer = std::collections::BTreeMap::new();
+        let mut next_result = 0;
+
+        for (i, result) in receiver {
+            reorder_buffer.insert(i, result);
+
+            while let Some(result) = reorder_buffer.remove(&next_result) {
+                on_result(next_result, result);
+                next_result += 1;
+            }
+        }
+    });
+}
+
+fn has_unique_paths(dir_diff: &[DirDiff<std::path::PathBuf>]) -> bool {
+    let mut paths = std::collections::HashSet::new();
+
+    dir_diff
+        .iter()
+        .all(|diff_fragment| paths.insert(diff_fragment.path()))
+}
+
+fn count_files_and_dirs(
+    dir_listing: &[std::path::PathBuf],
+    full_path: impl Fn(&std::path::Path) -> std::path::PathBuf,
+) -> (usize, usize) {
+    // Return the number of files (anything that is not a directory) and directories of a listing,
+    // given how to find the path of a listed path on disk
+
+    let dir_count = dir_listing
+        .iter()
+        .filter(|path| full_path(path).is_dir())
+        .count();
+
+    (dir_listing.len() - dir_count, dir_count)
+}
+
+fn count_presence_differences(
+    source_dir_listing: &[std::path::PathBuf],
+    target_dir_listing: &[std::path::PathBuf],
+    path_case: PathCase,
+) -> (usize, usize) {
+    // Return the number of removed and added paths, merging both listings like get_dir_diff but
+    // without building the diff or reading anything but the listings
+
+    // NOTE: this function assumes both directory listings went through sort_dir_listing
+
+    let mut removed = 0;
+    let mut added = 0;
+
+    let mut source_index = 0;
+    let mut target_index = 0;
+
+    while source_index < source_dir_listing.len() && target_index < target_dir_listing.len() {
+        match compare_paths(
+            &source_dir_listing[source_index],
+            &target_dir_listing[target_index],
+            path_case,
+        ) {
+            std::cmp::Ordering::Less => {
+                removed += 1;
+                source_index += 1;
+            }
+            std::cmp::Ordering::Greater => {
+                added += 1;
+                target_index += 1;
+            }
+            std::cmp::Ordering::Equal => {
+                source_index += 1;
+                target_index += 1;
+            }
+        }
+    }
+
+    removed += source_dir_listing.len() - source_index;
+    added += target_dir_listing.len() - target_index;
+
+    (removed, added)
+}
+
+fn get_dir_diff(
+    source_dir_listing: &[std::path::PathBuf],
+    target_dir_listing: &[std::path::PathBuf],
+    source_dir: &std::path::Path,
+    target_dirs: &[std::path::PathBuf],
+    options: &DiffOptions,
+    mut printer: Option<DiffPrinter>,
+) -> Result<Vec<DirDiff<std::path::PathBuf>>, DirDiffError> {
+    // Return diff between two directories. With a printer, the entries of the diff are given to it
+    // in order as soon as they are complete, while the files after them are still compared
+    // NOTE: this function assumes both directory listings went through sort_dir_listing
+
+    // the root of a directory is not one of its paths
+    debug_assert!(source_dir_listing
+        .iter()
+        .chain(target_dir_listing)
+        .all(|path| !path.as_os_str().is_empty()));
+
+    // indexes for both vectors
+    let mut source_index = 0;
+    let mut target_index = 0;
+
+    let mut diff_output = Vec::<DirDiff<std::path::PathBuf>>::new();
+
+    // index in diff_output, source file path and target file path of the files to compare
+    let mut pending_comparisons = Vec::new();
+
+    // go through both arrays at the same time, to ensure O(n) time
+    while source_index < source_dir_listing.len() && target_index < target_dir_listing.len() {
+        let ordering = compare_paths(
+            &source_dir_listing[source_index],
+            &target_dir_listing[target_index],
+            options.path_case,
+        );
+
+        if ordering == std::cmp::Ordering::Less {
+            diff_output.push(DirDiff::Removed(source_dir_listing[source_index].clone()));
+
+            source_index += 1;
+        } else if ordering == std::cmp::Ordering::Greater {
+            diff_output.push(DirDiff::Added(target_dir_listing[target_index].clone()));
+
+            target_index += 1;
+        } else {
+            // the source and target file paths are equal (relative to the parent directory)
+
+            let source_file_path = source_dir.join(real_path(
+                &options.source_paths,
+                &source_dir_listing[source_index],
+            ));
+
+            let target_path = real_path(&options.target_paths, &target_dir_listing[target_index]);
+            let target_file_path = find_target_dir(target_dirs, target_path).join(target_path);
+
+            let permissions_changed = match options.permission_mask {
+                Some(permission_mask) => {
+                    permissions_differ(&source_file_path, &target_file_path, permission_mask)
+                        .map_err(|source| DirDiffError::Compare {
+                            path: source_dir_listing[source_index].clone(),
+                            source,
+                        })?
+                }
+                None => false,
+            };
+
+            let creation_time_changed = options.compare_creation_times
+                && source_file_path.is_file()
+                && target_file_path.is_file()
+                && compare::creation_times_differ(&source_file_path, &target_file_path);
+
+            if permissions_changed || creation_time_changed {
+                // no need to compare the content
+                diff_output.push(DirDiff::Similar(
+                    source_dir_listing[source_index].clone(),
+                    Some(DirDiffFileContent::Changed),
+                ));
+            } else if options.compare_dir_children
+                && source_file_path.is_dir()
+                && target_file_path.is_dir()
+            {
+                // the children themselves may not be listed, when the depth is limited
+                let dir_children_changed =
+                    dir_children_differ(&source_file_path, &target_file_path).map_err(
+                        |source| DirDiffError::Compare {
+                            path: source_dir_listing[source_index].clone(),
+                            source,
+                        },
+                    )?;
+
+                diff_output.push(DirDiff::Similar(
+                    source_dir_listing[source_index].clone(),
+                    dir_children_changed.then_some(DirDiffFileContent::Changed),
+                ));
+            } else if options.compare_file_content
+                && options.compare_symlink_targets
+                && source_file_path.is_symlink()
+                && target_file_path.is_symlink()
+            {
+                // the target paths are compared as text, what they point to is never read
+                let symlink_targets_equal = std::fs::read_link(&source_file_path)
+                    .and_then(|source_link| {
+                        Ok(source_link == std::fs::read_link(&target_file_path)?)
+                    })
+                    .map_err(|source| DirDiffError::Compare {
+                        path: source_dir_listing[source_index].clone(),
+                        source,
+                    })?;
+
+                diff_output.push(DirDiff::Similar(
+                    source_dir_listing[source_index].clone(),
+                    Some(match symlink_targets_equal {
+                        true => DirDiffFileContent::Unchanged,
+                        false => DirDiffFileContent::Changed,
+                    }),
+                ));
+            } else if options.compare_file_content
+                && (is_special_file(&source_file_path) || is_special_file(&target_file_path))
+            {
+                // don't read special files
+                diff_output.push(DirDiff::Similar(
+                    source_dir_listing[source_index].clone(),
+                    Some(DirDiffFileContent::Special),
+                ));
+            } else if options.compare_file_content
+                && source_file_path.is_file() != target_file_path.is_file()
+            {
+                // if two paths are the same, but one is a file and the other a directory, then it
+                // is considered CHANGED
+                diff_output.push(DirDiff::Similar(
+                    source_dir_listing[source_index].clone(),
+                    Some(DirDiffFileContent::Changed),
+                ));
+            } else if options.compare_file_content
+                && source_file_path.is_file()
+                && target_file_path.is_file()
+            {
+                // compare the contents once the listings are merged
+                pending_comparisons.push((diff_output.len(), source_file_path, target_file_path));
+
+                diff_output.push(DirDiff::Similar(
+                    source_dir_listing[source_index].clone(),
+                    None,
+                ));
+            } else {
+                // don't check file content
+                diff_output.push(DirDiff::Similar(
+                    source_dir_listing[source_index].clone(),
+                    None,
+                ));
+            }
+
+            source_index += 1;
+            target_index += 1;
+        }
+    }
+
+    // one of the dir listings is at its end, the remaining items of the other one are REMOVED or
+    // ADDED
+    for path in &source_dir_listing[source_index..] {
+        diff_output.push(DirDiff::Removed(path.clone()));
+    }
+
+    for path in &target_dir_listing[target_index..] {
+        diff_output.push(DirDiff::Added(path.clone()));
+    }
+
+    // every item of both dir listings is in the diff exactly once, similar paths being an item of
+    // each listing. Otherwise the summary wouldn't match the printed paths
+    debug_assert_eq!(
+        diff_output
+            .iter()
+            .map(|diff_fragment| match diff_fragment {
+                DirDiff::Similar(_, _) => 2,
+                _ => 1,
+            })
+            .sum::<usize>(),
+        source_dir_listing.len() + target_dir_listing.len()
+    );
+    debug_assert!(has_unique_paths(&diff_output));
+
+    // a path missing from a listing may only be in a directory that couldn't be listed
+    for diff_fragment in &mut diff_output {
+        let inaccessible = match &*diff_fragment {
+            DirDiff::Removed(path) => target_dirs
+                .iter()
+                .any(|target_dir| is_inaccessible(&target_dir.join(path))),
+            DirDiff::Added(path) => is_inaccessible(&source_dir.join(path)),
+            _ => false,
+        };
+
+        if inaccessible {
+            let path = diff_fragment.path().clone();
+            *diff_fragment = DirDiff::Similar(path, Some(DirDiffFileContent::Inaccessible));
+        }
+    }
+
+    // the first entry not given to the printer yet
+    let mut printed_entries = 0;
+    let mut first_error = None;
+
+    compare_files(
+        &pending_comparisons,
+        options,
+        &mut |i, file_content_similarity| {
+            if first_error.is_some() {
+                return;
+            }
+
+            let index = pending_comparisons[i].0;
+
+            if let DirDiff::Similar(path, content) = &mut diff_output[index] {
+                *content = match file_content_similarity {
+                    Ok(file_content_similarity) => Some(file_content_similarity),
+                    Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                        Some(DirDiffFileContent::Inaccessible)
+                    }
+                    Err(source) => {
+                        first_error = Some(DirDiffError::Compare {
+                            path: path.clone(),
+                            source,
+                        });
+                        return;
+                    }
+                };
+            }
+
+            // every entry up to this one is complete
+            if let Some(printer) = printer.as_mut() {
+                match printer(&diff_output[printed_entries..=index]) {
+                    Ok(()) => printed_entries = index + 1,
+                    Err(e) => first_error = Some(DirDiffError::from(e)),
+                }
+            }
+        },
+    );
+
+    // the comparisons stop early when interrupted, so their errors are not real ones
+    interrupt::check()?;
+
+    if let Some(e) = first_error {
+        return Err(e);
+    }
+
+    if let Some(printer) = printer {
+        printer(&diff_output[printed_entries..])?;
+    }
+
+    Ok(diff_output)
+}
+
+fn prune_empty_subtrees(
+    dir_diff: Vec<DirDiff<std::path::PathBuf>>,
+) -> Vec<DirDiff<std::path::PathBuf>> {
+    // Remove the similar and unchanged paths that contain no removed, added, changed or special
+    // paths, so only the directories leading to differences are kept
+
+    let mut differing_ancestors = std::collections::HashSet::new();
+
+    for diff_fragment in &dir_diff {
+        if !matches!(
+            diff_fragment.status(),
+            DiffStatus::Similar | DiffStatus::Unchanged
+        ) {
+            differing_ancestors.extend(
+                diff_fragment
+                    .path()
+                    .ancestors()
+                    .skip(1)
+                    .map(|ancestor| ancestor.to_path_buf()),
+            );
+        }
+    }
+
+    dir_diff
+        .into_iter()
+        .filter(|diff_fragment| match diff_fragment.status() {
+            DiffStatus::Similar | DiffStatus::Unchanged => {
+                differing_ancestors.contains(diff_fragment.path())
+            }
+            _ => true,
+        })
+        .collect()
+}
+
+fn sort_by_components(
+    mut dir_diff: Vec<DirDiff<std::path::PathBuf>>,
+    mut compare_components: impl FnMut(&str, &str) -> std::cmp::Ordering,
+) -> Vec<DirDiff<std::path::PathBuf>> {
+    // Return the diff sorted with the paths compared component by component, so directories stay
+    // before their content
+
+    dir_diff.sort_by(|a, b| {
+        let a_components = a
+            .path()
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy());
+        let b_components = b
+            .path()
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy());
+
+        a_components
+            .zip(b_components)
+            .map(|(a_component, b_component)| compare_components(&a_component, &b_component))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| {
+                a.path()
+                    .components()
+                    .count()
+                    .cmp(&b.path().components().count())
+            })
+    });
+
+    dir_diff
+}
+
+fn natural_sort(dir_diff: Vec<DirDiff<std::path::PathBuf>>) -> Vec<DirDiff<std::path::PathBuf>> {
+    // Return the diff sorted with numbers in names compared by value (file2 before file10)
+    sort_by_components(dir_diff, natord::compare)
+}
+
+fn collation_locale() -> String {
+    // Return the locale of the environment used for collation, or an empty string if none is set
+
+    ["LC_ALL", "LC_COLLATE", "LANG"]
+        .iter()
+        .filter_map(|variable| std::env::var(variable).ok())
+        .find(|locale| !locale.is_empty())
+        .unwrap_or_default()
+}
+
+fn collation_tailoring(locale: &str) -> Option<feruca::Tailoring> {
+    // Return the collation of the locale, or None if it has none. Only the root order of the
+    // Unicode collation (which is the English one) and its tailoring for languages written in the
+    // Arabic script are available
+
+    let language = locale.split(['_', '.', '@']).next().unwrap_or_default();
+
+    match language {
+        "ar" | "fa" | "ur" | "ps" | "ckb" => {
+            Some(feruca::Tailoring::Cldr(feruca::Locale::ArabicScript))
+        }
+        "" | "C" | "POSIX" | "en" => Some(feruca::Tailoring::Cldr(feruca::Locale::Root)),
+        _ => None,
+    }
+}
+
+fn locale_sort(dir_diff: Vec<DirDiff<std::path::PathBuf>>) -> Vec<DirDiff<std::path::PathBuf>> {
+    // Return the diff sorted like people expect names to be in a dictionary (accented letters next
+    // to unaccented ones, case ignored first...), instead of by byte value
+
+    let tailoring = collation_tailoring(&collation_locale())
+        .unwrap_or(feruca::Tailoring::Cldr(feruca::Locale::Root));
+    let mut collator = feruca::Collator::new(tailoring, true, true);
+
+    sort_by_components(dir_diff, |a, b| collator.collate(a, b))
+}
+
+fn keep_identical_paths(
+    dir_diff: Vec<DirDiff<std::path::PathBuf>>,
+) -> Vec<DirDiff<std::path::PathBuf>> {
+    // Return the diff with only the paths that are the same on both sides
+
+    dir_diff
+        .into_iter()
+        .filter(|diff_fragment| {
+            matches!(
+                diff_fragment,
+                DirDiff::Similar(_, None | Some(DirDiffFileContent::Unchanged))
+            )
+        })
+        .collect()
+}
+
+fn rebase_path(
+    path: &std::path::Path,
+    parent_dir: &std::path::Path,
+    base_dir: &std::path::Path,
+) -> std::path::PathBuf {
+    // Return the path (relative to parent_dir) relative to base_dir instead, or the absolute path
+    // if it is not inside base_dir. Both directories are assumed to be canonicalized
+
+    let absolute_path = parent_dir.join(path);
+
+    match absolute_path.strip_prefix(base_dir) {
+        Ok(p) => p.to_path_buf(),
+        Err(_) => {
+            eprintln!(
+                "warning: {} is outside of {}",
+                absolute_path.display(),
+                base_dir.display()
+            );
+            absolute_path
+        }
+    }
+}
+
+fn rebase_dir_diff(
+    dir_diff: Vec<DirDiff<std::path::PathBuf>>,
+    source_dir: &std::path::Path,
+    target_dirs: &[std::path::PathBuf],
+    base_dir: &std::path::Path,
+) -> Result<Vec<DirDiff<std::path::PathBuf>>, DirDiffError> {
+    // Return the diff with every path relative to base_dir instead of the compared directories
+
+    let canonicalize = |dir: &std::path::Path| {
+        std::fs::canonicalize(dir).map_err(|source| DirDiffError::Read {
+            path: dir.to_path_buf(),
+            source,
+        })
+    };
+
+    let source_dir = canonicalize(source_dir)?;
+    let base_dir = canonicalize(base_dir)?;
+
+    let canonical_target_dirs = target_dirs
+        .iter()
+        .map(|target_dir| canonicalize(target_dir))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // the canonical path of find_target_dir, which falls back to the first target directory too
+    let canonical_target_dir = |path: &std::path::Path| {
+        let target_dir = find_target_dir(target_dirs, path);
+
+        target_dirs
+            .iter()
+            .zip(&canonical_target_dirs)
+            .find(|(dir, _)| *dir == target_dir)
+            .map_or(&canonical_target_dirs[0], |(_, canonical_dir)| {
+                canonical_dir
+            })
+    };
+
+    Ok(dir_diff
+        .into_iter()
+        .map(|diff_fragment| match diff_fragment {
+            // removed and similar paths exist in the source, added paths only in the target
+            DirDiff::Removed(path) => DirDiff::Removed(rebase_path(&path, &source_dir, &base_dir)),
+            DirDiff::Added(path) => {
+                DirDiff::Added(rebase_path(&path, canonical_target_dir(&path), &base_dir))
+            }
+            DirDiff::Similar(path, file_content_similarity) => DirDiff::Similar(
+                rebase_path(&path, &source_dir, &base_dir),
+                file_content_similarity,
+            ),
+            // renamed files are in the source under their first path, in the target under the
+            // second one
+            DirDiff::Renamed(from, to) => DirDiff::Renamed(
+                rebase_path(&from, &source_dir, &base_dir),
+                rebase_path(&to, canonical_target_dir(&to), &base_dir),
+            ),
+            DirDiff::RenamedChanged(from, to, similarity) => DirDiff::RenamedChanged(
+                rebase_path(&from, &source_dir, &base_dir),
+                rebase_path(&to, canonical_target_dir(&to), &base_dir),
+                similarity,
+            ),
+            DirDiff::CaseChanged(from, to, file_content_similarity) => DirDiff::CaseChanged(
+                rebase_path(&from, &source_dir, &base_dir),
+                rebase_path(&to, canonical_target_dir(&to), &base_dir),
+                file_content_similarity,
+            ),
+        })
+        .collect())
+}
+
+fn posix_path(path: &std::path::Path) -> std::path::PathBuf {
+    // Return the path with / as separator. Only the separator of the platform is replaced, since
+    // elsewhere than on windows \ can be part of a file name
+
+    match std::path::MAIN_SEPARATOR {
+        '/' => path.to_path_buf(),
+        separator => std::path::PathBuf::from(path.to_string_lossy().replace(separator, "/")),
+    }
+}
+
+fn to_posix_paths(dir_diff: Vec<DirDiff<std::path::PathBuf>>) -> Vec<DirDiff<std::path::PathBuf>> {
+    // Return the diff with / as separator in every path, for tools that don't expect \
+
+    dir_diff
+        .into_iter()
+        .map(|diff_fragment| match diff_fragment {
+            DirDiff::Removed(path) => DirDiff::Removed(posix_path(&path)),
+            DirDiff::Added(path) => DirDiff::Added(posix_path(&path)),
+            DirDiff::Similar(path, file_content_similarity) => {
+                DirDiff::Similar(posix_path(&path), file_content_similarity)
+            }
+            DirDiff::Renamed(from, to) => DirDiff::Renamed(posix_path(&from), posix_path(&to)),
+            DirDiff::RenamedChanged(from, to, similarity) => {
+                DirDiff::RenamedChanged(posix_path(&from), posix_path(&to), similarity)
+            }
+            DirDiff::CaseChanged(from, to, file_content_similarity) => {
+                DirDiff::CaseChanged(posix_path(&from), posix_path(&to), file_content_similarity)
+            }
+        })
+        .collect()
+}
+
+fn add_path_note(
+    path_notes: &mut std::collections::HashMap<std::path::PathBuf, String>,
+    path: std::path::PathBuf,
+    note: String,
+) {
+    // Add a note printed after a path, after the notes it already has
+
+    path_notes
+        .entry(path)
+        .and_modify(|notes| *notes = format!("{}, {}", notes, note))
+        .or_insert(note);
+}
+
+fn print_dir_diff(
+    out: &mut impl std::io::Write,
+    dir_diff: &[DirDiff<std::path::PathBuf>],
+    hide_similarities: bool,
+    color: bool,
+    theme: ColorTheme,
+    matching_dirs: Option<(&std::path::Path, &[std::path::PathBuf])>,
+    path_notes: &std::collections::HashMap<std::path::PathBuf, String>,
+) -> std::io::Result<()> {
+    // matching_dirs are the canonicalized source and target directories, if the source and target
+    // paths of similar entries should be printed. The notes in path_notes (hashes...) are printed
+    // after their path
+
+    let removed_style = theme.removed();
+    let added_style = theme.added();
+    let changed_style = theme.changed();
+    let renamed_style = theme.renamed();
+
+    let similar_path_display = |path: &std::path::PathBuf| {
+        let path_display = match matching_dirs {
+            Some((source_dir, target_dirs)) => format!(
+                "{} ({} <-> {})",
+                path.display(),
+                source_dir.join(path).display(),
+                find_target_dir(target_dirs, path).join(path).display()
+            ),
+            None => path.to_str().unwrap().to_string(),
+        };
+
+        match path_notes.get(path) {
+            Some(note) => format!("{} ({})", path_display, note),
+            None => path_display,
+        }
+    };
+
+    for diff_fragment in dir_diff {
+        // stop between two lines, so the output is not cut in the middle of one
+        interrupt::check()?;
+
+        match diff_fragment {
+            DirDiff::Removed(path) => {
+                writeln!(
+                    out,
+                    "{} {}",
+                    removed_style.paint(removed_style.symbol, color),
+                    removed_style.paint(path.to_str().unwrap(), color)
+                )?;
+            }
+            DirDiff::Added(path) => {
+                writeln!(
+                    out,
+                    "{} {}",
+                    added_style.paint(added_style.symbol, color),
+                    added_style.paint(path.to_str().unwrap(), color)
+                )?;
+            }
+            DirDiff::Similar(path, file_content_similarity) => {
+                if !hide_similarities {
+                    match file_content_similarity {
+                        None => {
+                            writeln!(out, "  {}", similar_path_display(path))?;
+                        }
+                        Some(DirDiffFileContent::Unchanged) => {
+                            writeln!(out, " {}", similar_path_display(path))?;
+                        }
+                        Some(DirDiffFileContent::Changed) => {
+                            writeln!(
+                                out,
+                                "{} {}",
+                                changed_style.paint(changed_style.symbol, color),
+                                changed_style.paint(&similar_path_display(path), color)
+                            )?;
+                        }
+                        Some(DirDiffFileContent::Special) => {
+                            writeln!(out, "  {} (special)", similar_path_display(path))?;
+                        }
+                        Some(DirDiffFileContent::Inaccessible) => {
+                            writeln!(out, "  {} (inaccessible)", similar_path_display(path))?;
+                        }
+                    }
+                }
+            }
+            DirDiff::Renamed(from, to) => {
+                writeln!(
+                    out,
+                    "{} {}",
+                    renamed_style.paint(renamed_style.symbol, color),
+                    renamed_style.paint(&format!("{} -> {}", from.display(), to.display()), color)
+                )?;
+            }
+            DirDiff::RenamedChanged(from, to, similarity) => {
+                writeln!(
+                    out,
+                    "{} {}",
+                    renamed_style.paint(renamed_style.symbol, color),
+                    renamed_style.paint(
+                        &format!(
+                            "{} -> {} ({}% similar)",
+                            from.display(),
+                            to.display(),
+                            similarity
+                        ),
+                        color
+                    )
+                )?;
+            }
+            DirDiff::CaseChanged(from, to, file_content_similarity) => {
+                let note = match file_content_similarity {
+                    Some(DirDiffFileContent::Changed) => "case changed, content changed",
+                    _ => "case changed",
+                };
+
+                writeln!(
+                    out,
+                    "{} {}",
+                    renamed_style.paint(renamed_style.symbol, color),
+                    renamed_style.paint(
+                        &format!("{} -> {} ({})", from.display(), to.display(), note),
+                        color
+                    )
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn print_diff_summary(
+    out: &mut impl std::io::Write,
+    dir_diff: &[DirDiff<std::path::PathBuf>],
+    hide_similarities: bool,
+    compare_file_content: bool,
+    color: bool,
+    theme: ColorTheme,
+) -> std::io::Result<()> {
+    let counts = DiffCounts::from_dir_diff(dir_diff);
+
+    // use the same colors as the diff entries
+    let removed_summary = theme
+        .removed()
+        .paint(&format!("{} removed", counts.removed), color);
+    let added_summary = theme
+        .added()
+        .paint(&format!("{} added", counts.added), color);
+    let changed_summary = theme
+        .changed()
+        .paint(&format!("{} files changed", counts.changed), color);
+
+    let mut summary = String::new();
+
+    summary.push_str(&format!("{}, {}", removed_summary, added_summary));
+
+    // renames are only detected with --detect-renames
+    if counts.renamed + counts.renamed_changed > 0 {
+        let renamed_summary = theme.renamed().paint(
+            &format!(
+                "{} renamed, {} renamed and changed",
+                counts.renamed, counts.renamed_changed
+            ),
+            color,
+        );
+
+        summary.push_str(&format!(", {}", renamed_summary));
+    }
+
+    // case-only renames are only detected with --detect-case-renames
+    if counts.case_changed > 0 {
+        let case_changed_summary = theme
+            .renamed()
+            .paint(&format!("{} case changed", counts.case_changed), color);
+
+        summary.push_str(&format!(", {}", case_changed_summary));
+    }
+
+    // paths that can't be read are neither missing nor compared
+    if counts.inaccessible > 0 {
+        summary.push_str(&format!(", {} inaccessible", counts.inaccessible));
+    }
+
+    // also add summary for similarities
+    if !hide_similarities {
+        summary.push_str(&format!(", {} similar", counts.similar));
+
+        if compare_file_content {
+            summary.push_str(&format!(
+                ", {}, {} files unchanged, {} special files",
+                changed_summary, counts.unchanged, counts.special
+            ));
+        }
+    }
+
+    writeln!(out, "{}", summary)
+}
+
+fn print_changed_files(
+    out: &mut impl std::io::Write,
+    dir_diff: &[DirDiff<std::path::PathBuf>],
+    target_dirs: &[std::path::PathBuf],
+    separator: u8,
+) -> std::io::Result<()> {
+    // Print the path of every added or changed file, followed by the separator, for other tools
+    // to read. Added directories are left out, since their files are listed too, and renamed files
+    // are printed under their new path
+
+    for diff_fragment in dir_diff {
+        let path = match diff_fragment {
+            DirDiff::Added(path) => {
+                let is_dir = find_target_dir(target_dirs, path)
+                    .join(path)
+                    .symlink_metadata()
+                    .map(|metadata| metadata.is_dir())
+                    .unwrap_or(false);
+
+                match is_dir {
+                    true => continue,
+                    false => path,
+                }
+            }
+            DirDiff::Similar(path, Some(DirDiffFileContent::Changed)) => path,
+            DirDiff::Renamed(_, to) | DirDiff::RenamedChanged(_, to, _) => to,
+            _ => continue,
+        };
+
+        out.write_all(&path_to_bytes(path))?;
+        out.write_all(&[separator])?;
+    }
+
+    Ok(())
+}
+
+fn print_dir_summary(
+    out: &mut impl std::io::Write,
+    dir_diff: &[DirDiff<std::path::PathBuf>],
+) -> std::io::Result<()> {
+    // Print a table of the removed, added and changed paths in each top-level directory, to see
+    // where the differences are. Top-level paths are counted in "."
+
+    let mut dir_counts = std::collections::BTreeMap::new();
+
+    for diff_fragment in dir_diff {
+        let mut components = diff_fragment.path().components();
+
+        let top_level_dir = match (components.next(), components.next()) {
+            (Some(first_component), Some(_)) => {
+                first_component.as_os_str().to_string_lossy().to_string()
+            }
+            _ => String::from("."),
+        };
+
+        dir_counts
+            .entry(top_level_dir)
+            .or_insert_with(DiffCounts::default)
+            .add(diff_fragment.status());
+    }
+
+    // directories without differences are left out
+    let dir_counts: Vec<(String, DiffCounts)> = dir_counts
+        .into_iter()
+        .filter(|(_, counts)| counts.removed + counts.added + counts.changed > 0)
+        .collect();
+
+    let dir_width = dir_counts
+        .iter()
+        .map(|(dir, _)| dir.chars().count())
+        .chain(std::iter::once("directory".len()))
+        .max()
+        .unwrap();
+
+    writeln!(
+        out,
+        "{:width$}  removed  added  changed",
+        "directory",
+        width = dir_width
+    )?;
+
+    for (dir, counts) in &dir_counts {
+        writeln!(
+            out,
+            "{:width$}  {:>7}  {:>5}  {:>7}",
+            dir,
+            counts.removed,
+            counts.added,
+            counts.changed,
+            width = dir_width
+        )?;
+    }
+
+    Ok(())
+}
+
+fn print_filter_explanation(
+    out: &mut impl std::io::Write,
+    dir_path: &std::path::Path,
+    walk_options: &WalkOptions,
+) -> Result<(), DirDiffError> {
+    // Print whether each path of the directory is kept or dropped by the filters, and the filter
+    // dropping it
+
+    writeln!(out, "{}:", dir_path.display())?;
+
+    for explained_path in explain_walk(dir_path, walk_options) {
+        match explained_path? {
+            (path, None) => writeln!(out, "  kept    {}", path.display())?,
+            (path, Some(reason)) => writeln!(out, "  dropped {} ({})", path.display(), reason)?,
+        }
+    }
+
+    Ok(())
+}
+
+// the size from which LineBufWriter writes its complete lines
+const OUTPUT_BUFFER_SIZE: usize = 8192;
+
+struct LineBufWriter<W: Write> {
+    inner: W,
+    buffer: Vec<u8>, // the bytes not written yet, of complete lines and a partial last one
+}
+
+impl<W: Write> LineBufWriter<W> {
+    fn new(inner: W) -> Self {
+        LineBufWriter {
+            inner,
+            buffer: Vec::with_capacity(OUTPUT_BUFFER_SIZE),
+        }
+    }
+}
+
+impl<W: Write> Write for LineBufWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // Buffer the output like a BufWriter, but only write complete lines before the end, so
+        // the messages printed on stderr in the meantime are not inserted in the middle of a line
+
+        self.buffer.extend_from_slice(buf);
+
+        if self.buffer.len() >= OUTPUT_BUFFER_SIZE {
+            if let Some(line_end) = self.buffer.iter().rposition(|&b| b == b'\n') {
+                self.inner.write_all(&self.buffer[..=line_end])?;
+                self.buffer.drain(..=line_end);
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.write_all(&self.buffer)?;
+        self.buffer.clear();
+
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for LineBufWriter<W> {
+    fn drop(&mut self) {
+        // like a BufWriter, write what is left, and there is no one to report an error to
+        let _ = self.flush();
+    }
+}
+
+fn open_output(args: &CliArgs) -> (Option<std::process::Child>, LineBufWriter<Box<dyn Write>>) {
+    // Return the pager process, if the output is paged, and the buffered output. The output is
+    // paged if asked and a human reads it. Applying asks for confirmation in the terminal and
+    // diff tools can use it, so they are never paged
+
+    let stdout = std::io::stdout();
+
+    let pager = if args.pager
+        && !args.apply
+        && args.external_diff.is_none()
+        && std::io::IsTerminal::is_terminal(&stdout)
+        && matches!(
+            args.format,
+            OutputFormat::Text | OutputFormat::Patch | OutputFormat::Diffstat
+        ) {
+        pager::spawn_pager()
+    } else {
+        None
+    };
+
+    let (pager_process, output): (_, Box<dyn Write>) = match pager {
+        Some((pager_process, pager_input)) => (Some(pager_process), Box::new(pager_input)),
+        None => (None, Box::new(stdout.lock())),
+    };
+
+    // buffer the output instead of flushing every line
+    (pager_process, LineBufWriter::new(output))
+}
+
+fn repeat_dir_diff(
+    compute_dir_diff: &dyn Fn() -> Result<Vec<DirDiff<std::path::PathBuf>>, DirDiffError>,
+    dir_diff: &[DirDiff<std::path::PathBuf>],
+    repeat: usize,
+    first_duration: std::time::Duration,
+) -> Result<(), DirDiffError> {
+    // Compute the diff again until it was computed `repeat` times, with the caches now warm, and
+    // print the min, median and max time to stderr. Every diff must be the same as the first one
+
+    let statuses = |dir_diff: &[DirDiff<std::path::PathBuf>]| {
+        dir_diff
+            .iter()
+            .map(|diff_fragment| (diff_fragment.path().clone(), diff_fragment.status()))
+            .collect::<Vec<_>>()
+    };
+
+    let first_statuses = statuses(dir_diff);
+    let mut durations = vec![first_duration];
+
+    for iteration in 2..=repeat {
+        let start = std::time::Instant::now();
+        let repeated_dir_diff = compute_dir_diff()?;
+        durations.push(start.elapsed());
+
+        if statuses(&repeated_dir_diff) != first_statuses {
+            eprintln!(
+                "warning: the diff of run {} is not the same as the first one",
+                iteration
+            );
+        }
+    }
+
+    durations.sort();
+
+    let milliseconds = |duration: std::time::Duration| duration.as_secs_f64() * 1000.0;
+
+    eprintln!(
+        "compare x{}: min {:.3}ms, median {:.3}ms, max {:.3}ms",
+        repeat,
+        milliseconds(durations[0]),
+        milliseconds(durations[durations.len() / 2]),
+        milliseconds(durations[durations.len() - 1])
+    );
+
+    Ok(())
+}
+
+fn print_timings(timings: &[(&str, std::time::Duration)]) {
+    // Print how long each phase took to stderr, so it doesn't mix with the diff
+
+    for (phase, duration) in timings {
+        eprintln!("{}: {:.3}ms", phase, duration.as_secs_f64() * 1000.0);
+    }
+
+    eprintln!(
+        "total: {:.3}ms",
+        timings
+            .iter()
+            .map(|(_, duration)| duration.as_secs_f64() * 1000.0)
+            .sum::<f64>()
+    );
+}
+
+fn content_comparator(modes: &ContentModes, args: &CliArgs) -> Box<dyn ContentComparator> {
+    // Return the comparator of the content of files for a combination of modes, going from the
+    // most to the least specific way of reading the files
+
+    let comparator: Box<dyn ContentComparator> = if modes.is_text() {
+        Box::new(compare::TextComparator {
+            ignore_case: modes.ignore_case,
+            ignored_lines: modes.ignored_lines.clone(),
+            ignore_final_newline: modes.ignore_final_newline,
+            head_lines: modes.head_lines,
+        })
+    } else if let Some(sample_size) = args.sample {
+        Box::new(compare::SampleComparator { sample_size })
+    } else if args.mmap {
+        Box::new(compare::MmapComparator {
+            buffer_size: args.buffer_size,
+        })
+    } else {
+        Box::new(compare::ByteComparator {
+            buffer_size: args.buffer_size,
+        })
+    };
+
+    let comparator: Box<dyn ContentComparator> = match modes.json_aware {
+        true => Box::new(compare::JsonComparator { comparator }),
+        false => comparator,
+    };
+
+    match modes.dive_archives {
+        true => Box::new(compare::ArchiveComparator {
+            comparator,
+            buffer_size: args.buffer_size,
+        }),
+        false => comparator,
+    }
+}
+
+fn run(args: &CliArgs) -> Result<i32, DirDiffError> {
+    // Compare the directories and print the result, returning the exit code
+
+    let expanded_args;
+    let args = if args.expand_paths {
+        expanded_args = expand_cli_args(args)?;
+        &expanded_args
+    } else {
+        args
+    };
+
+    // everything that depends on the terminal, the environment or the platform is fixed. Listings
+    // are always sorted and comparison results always kept in order, so nothing else can change
+    let stable_args;
+    let args = if args.stable_output {
+        stable_args = CliArgs {
+            no_color: true,
+            pager: false,
+            posix_paths: true,
+            path_case: PathCase::Sensitive,
+            ..args.clone()
+        };
+        &stable_args
+    } else {
+        args
+    };
+
+    // paths differing only in case must be listed apart to be paired
+    let case_rename_args;
+    let args = if args.detect_case_renames {
+        case_rename_args = CliArgs {
+            path_case: PathCase::Sensitive,
+            ..args.clone()
+        };
+        &case_rename_args
+    } else {
+        args
+    };
+
+    // error if directories do not exist
+    check_cli_args(args)?;
+
+    let ignored_error_patterns = parse_patterns(&args.ignore_errors_in)?;
+
+    let args_source_dir = args.source_dir.as_ref().unwrap();
+
+    // with --since, the revision is the source and the working tree is the target
+    #[cfg(feature = "git")]
+    let revision_tree = match &args.since {
+        Some(revision) => Some(git::checkout_revision(args_source_dir, revision)?),
+        None => None,
+    };
+
+    #[cfg(feature = "git")]
+    let (source_dir, target_dirs) = match &revision_tree {
+        Some(revision_tree) => (revision_tree.path.clone(), vec![args_source_dir.clone()]),
+        None => (args_source_dir.clone(), args.target_dirs.clone()),
+    };
+
+    #[cfg(not(feature = "git"))]
+    let (source_dir, target_dirs) = (args_source_dir.clone(), args.target_dirs.clone());
+
+    // equivalent spellings of the directories must give identical output
+    let source_dir = normalize_dir_path(&source_dir);
+    let target_dirs: Vec<std::path::PathBuf> = target_dirs
+        .iter()
+        .map(|target_dir| normalize_dir_path(target_dir))
+        .collect();
+
+    // differently named top directories are skipped, so the trees below them are aligned
+    let (source_dir, target_dirs) = match args.strip_components {
+        0 => (source_dir, target_dirs),
+        components => (
+            strip_dir_components(&source_dir, components)?,
+            target_dirs
+                .iter()
+                .map(|target_dir| strip_dir_components(target_dir, components))
+                .collect::<Result<Vec<_>, _>>()?,
+        ),
+    };
+
+    // list both directories
+    let walk_options = WalkOptions {
+        depth: args.depth,
+        include: parse_patterns(&args.include)?,
+        exclude: parse_patterns(&args.exclude)?,
+        exclude_dirs: parse_patterns(&args.exclude_dir)?,
+        hidden: !args.skip_hidden,
+        follow_symlinks: !args.no_follow_symlinks,
+        one_file_system: args.one_file_system,
+        // the time limit is for all the directories
+        deadline: args
+            .max_walk_time
+            .map(|max_walk_time| std::time::Instant::now() + max_walk_time),
+    };
+
+    if args.explain_filters {
+        let stdout = std::io::stdout();
+        let mut out = std::io::BufWriter::new(stdout.lock());
+
+        for dir in std::iter::once(&source_dir).chain(&target_dirs) {
+            print_filter_explanation(&mut out, dir, &walk_options)?;
+        }
+
+        out.flush()?;
+
+        return Ok(0);
+    }
+
+    // duration of each phase, for --timing
+    let mut timings: Vec<(&str, std::time::Duration)> = Vec::new();
+    let walk_start = std::time::Instant::now();
+
+    // the listed paths are looked up instead of walking the directories
+    let listed_paths = match &args.paths_from {
+        Some(list_path) => Some(read_path_list(list_path)?),
+        None => None,
+    };
+
+    // the directories are listed even if some of their paths can't be
+    let mut print_error = |e: &DirDiffError| eprintln!("{}", e);
+
+    // check_cli_args only lets missing directories through with --ignore-missing-dir
+    let mut source_dir_listing = match source_dir.exists() {
+        true if listed_paths.is_some() => get_listed_paths(
+            std::slice::from_ref(&source_dir),
+            listed_paths.as_ref().unwrap(),
+        ),
+        true => get_dir_listing(
+            &source_dir,
+            &walk_options,
+            &ignored_error_patterns,
+            args.max_entries,
+            &mut print_error,
+        )?,
+        false => Vec::new(),
+    };
+
+    if args.manifest {
+        sort_dir_listing(&mut source_dir_listing, args.path_case);
+
+        let stdout = std::io::stdout();
+        let mut out = std::io::BufWriter::new(stdout.lock());
+
+        hash::print_manifest(&mut out, &source_dir_listing, &source_dir, args.buffer_size)?;
+
+        out.flush()?;
+
+        return Ok(0);
+    }
+
+    let existing_target_dirs: Vec<std::path::PathBuf> = target_dirs
+        .iter()
+        .filter(|target_dir| target_dir.exists())
+        .cloned()
+        .collect();
+
+    // comparing a directory to itself can only find similar paths, so it isn't listed and read a
+    // second time
+    let same_dir = match target_dirs.as_slice() {
+        // the same path can be rewritten to different files
+        _ if !args.rewrite_source.is_empty() || !args.rewrite_target.is_empty() => false,
+        // a missing directory (with --ignore-missing-dir) is empty, not the same as another one
+        [target_dir] if source_dir.exists() && target_dir.exists() => {
+            canonicalize_dir(&source_dir)? == canonicalize_dir(target_dir)?
+        }
+        _ => false,
+    };
+
+    if same_dir {
+        eprintln!(
+            "note: {} and {} are the same directory",
+            source_dir.display(),
+            target_dirs[0].display()
+        );
+    }
+
+    let target_dir_listing = match (same_dir, &listed_paths) {
+        (true, _) => source_dir_listing.clone(),
+        (false, Some(listed_paths)) => get_listed_paths(&existing_target_dirs, listed_paths),
+        (false, None) => get_union_dir_listing(
+            &existing_target_dirs,
+            &walk_options,
+            &ignored_error_patterns,
+            args.max_entries,
+            &mut print_error,
+        )?,
+    };
+
+    timings.push(("walk", walk_start.elapsed()));
+    let sort_start = std::time::Instant::now();
+
+    // rewritten paths are merged as if they were the real ones
+    let (mut source_dir_listing, source_paths) =
+        rewrite_dir_listing(source_dir_listing, &args.rewrite_source)?;
+    let (mut target_dir_listing, target_paths) =
+        rewrite_dir_listing(target_dir_listing, &args.rewrite_target)?;
+
+    sort_dir_listing(&mut source_dir_listing, args.path_case);
+    sort_dir_listing(&mut target_dir_listing, args.path_case);
+
+    timings.push(("sort", sort_start.elapsed()));
+
+    // the repository itself is not part of the revision
+    #[cfg(feature = "git")]
+    let target_dir_listing = match &revision_tree {
+        Some(_) => target_dir_listing
+            .into_iter()
+            .filter(|path| !path.starts_with(".git"))
+            .collect(),
+        None => target_dir_listing,
+    };
+
+    // counted before the diff is filtered, like the trees on disk
+    let listing_counts = match args.counts {
+        true => Some((
+            count_files_and_dirs(&source_dir_listing, |path| {
+                source_dir.join(real_path(&source_paths, path))
+            }),
+            count_files_and_dirs(&target_dir_listing, |path| {
+                let path = real_path(&target_paths, path);
+                find_target_dir(&target_dirs, path).join(path)
+            }),
+        )),
+        false => None,
+    };
+
+    if args.count_only {
+        let (removed, added) =
+            count_presence_differences(&source_dir_listing, &target_dir_listing, args.path_case);
+
+        println!("{} removed, {} added", removed, added);
+
+        let statuses = [(DiffStatus::Removed, removed), (DiffStatus::Added, added)];
+
+        // the same exit codes as with the full diff, for the statuses that can be counted
+        let exit_code = gated_exit_code(
+            statuses
+                .iter()
+                .filter(|(_, count)| *count > 0)
+                .map(|(status, _)| *status),
+            &args.exit_code,
+            &args.fail_on,
+        );
+
+        if args.timing {
+            print_timings(&timings);
+        }
+
+        return Ok(exit_code);
+    }
+
+    let comparator: Box<dyn ContentComparator> = if same_dir {
+        Box::new(compare::SameFileComparator)
+    } else {
+        if let Some(sample_size) = args.sample {
+            eprintln!(
+                "note: only the first and last {} bytes of files are compared, files reported as \
+                 unchanged may differ",
+                sample_size
+            );
+        }
+
+        let modes = ContentModes::from_args(args);
+
+        // the modes of the rules with the same pattern are combined, on top of the global ones
+        let mut rule_modes: Vec<(glob::Pattern, ContentModes)> = Vec::new();
+
+        for rule in &args.rule {
+            match rule_modes
+                .iter_mut()
+                .find(|(pattern, _)| *pattern == rule.pattern)
+            {
+                Some((_, modes)) => modes.add(&rule.mode),
+                None => {
+                    let mut modes = modes.clone();
+                    modes.add(&rule.mode);
+                    rule_modes.push((rule.pattern.clone(), modes));
+                }
+            }
+        }
+
+        let comparator = content_comparator(&modes, args);
+
+        match rule_modes.is_empty() {
+            true => comparator,
+            false => Box::new(compare::RuleComparator {
+                source_dir: source_dir.clone(),
+                rules: rule_modes
+                    .iter()
+                    .map(|(pattern, modes)| (pattern.clone(), content_comparator(modes, args)))
+                    .collect(),
+                comparator,
+            }),
+        }
+    };
+
+    let comparator: Box<dyn ContentComparator> = match args.read_retries {
+        0 => comparator,
+        retries => Box::new(compare::RetryComparator {
+            comparator,
+            retries,
+        }),
+    };
+
+    // outermost, so stored results skip the retries too
+    let comparator: Box<dyn ContentComparator> = match &args.state_file {
+        Some(state_file_path) => Box::new(state::StateComparator::open(
+            comparator,
+            state_file_path,
+            &comparator_fingerprint(args, same_dir),
+        )?),
+        None => comparator,
+    };
+
+    let mut passes: Vec<DiffPass> = Vec::new();
+
+    if args.prune_empty {
+        passes.push(Box::new(prune_empty_subtrees));
+    }
+
+    if args.report_identical {
+        passes.push(Box::new(keep_identical_paths));
+    }
+
+    // last, so the other passes see the order of the merge
+    if args.natural_sort {
+        passes.push(Box::new(natural_sort));
+    } else if args.locale_sort {
+        let locale = collation_locale();
+
+        // the root order is still close to the one of most languages written in Latin letters
+        if collation_tailoring(&locale).is_none() {
+            eprintln!(
+                "note: there is no collation for the locale {}, the root Unicode order is used",
+                locale
+            );
+        }
+
+        passes.push(Box::new(locale_sort));
+    }
+
+    // creation times are only available on some platforms and file systems
+    let creation_times_supported = !source_dir.exists()
+        || std::fs::metadata(&source_dir)
+            .and_then(|metadata| metadata.created())
+            .is_ok();
+
+    if args.btime && !creation_times_supported {
+        eprintln!("warning: creation times are not available here, --btime is ignored");
+    }
+
+    let diff_options = DiffOptions {
+        // applying, patches, diffstats and diff tools need to know which files changed
+        compare_file_content: args.files
+            || args.apply
+            || args.changed_files_only
+            || args.external_diff.is_some()
+            || args.on_changed_run.is_some()
+            || args.format == OutputFormat::Patch
+            || args.format == OutputFormat::Diffstat,
+        comparator,
+        io_threads: args.threads_io,
+        permission_mask: match args.perms {
+            true => Some(args.perm_mask.unwrap_or(0o7777)),
+            false => None,
+        },
+        compare_creation_times: args.btime && creation_times_supported,
+        compare_dir_children: args.dir_content,
+        path_case: args.path_case,
+        compare_symlink_targets: args.compare_symlink_as_content,
+        source_paths,
+        target_paths,
+    };
+
+    // get diff
+    let compare_start = std::time::Instant::now();
+
+    // with a manifest, the listed directory is the target
+    let compute_dir_diff = || -> Result<Vec<DirDiff<std::path::PathBuf>>, DirDiffError> {
+        let dir_diff = match &args.compare_to_manifest {
+            Some(manifest_path) => {
+                // a manifest or a directory index served over HTTP
+                #[cfg(feature = "http")]
+                let manifest = match http::is_url(manifest_path) {
+                    true => http::fetch_manifest(manifest_path, args.buffer_size)?,
+                    false => hash::read_manifest(manifest_path)?,
+                };
+
+                #[cfg(not(feature = "http"))]
+                let manifest = hash::read_manifest(manifest_path)?;
+
+                hash::diff_against_manifest(
+                    manifest,
+                    &source_dir_listing,
+                    &source_dir,
+                    args.path_case,
+                    args.buffer_size,
+                )?
+            }
+            None if args.flatten => flatten::get_flat_dir_diff(
+                &source_dir_listing,
+                &target_dir_listing,
+                &source_dir,
+                &target_dirs,
+                diff_options.compare_file_content,
+                args.buffer_size,
+            )?,
+            None => get_dir_diff(
+                &source_dir_listing,
+                &target_dir_listing,
+                &source_dir,
+                &target_dirs,
+                &diff_options,
+                None,
+            )?,
+        };
+
+        // case-only renames first, so they are not paired with other files by content
+        let dir_diff = match args.detect_case_renames {
+            true => rename::detect_case_renames(
+                dir_diff,
+                &source_dir,
+                &target_dirs,
+                diff_options
+                    .compare_file_content
+                    .then_some(diff_options.comparator.as_ref()),
+            )?,
+            false => dir_diff,
+        };
+
+        let dir_diff = match args.detect_renames {
+            true => rename::detect_renames(
+                dir_diff,
+                &source_dir,
+                &target_dirs,
+                args.rename_threshold,
+                args.buffer_size,
+            )?,
+            false => dir_diff,
+        };
+
+        Ok(dir_diff)
+    };
+
+    // the output is opened before the diff is computed when it is streamed
+    let mut streamed_output = match args.content_threads_chunked {
+        true => Some(open_output(args)),
+        false => None,
+    };
+
+    let dir_diff = match &mut streamed_output {
+        Some((_, out)) => {
+            // notes are only found once the diff is complete
+            let no_notes = std::collections::HashMap::new();
+
+            let mut printer = |entries: &[DirDiff<std::path::PathBuf>]| {
+                print_dir_diff(
+                    out,
+                    entries,
+                    args.quiet,
+                    !args.no_color,
+                    args.color_theme,
+                    None,
+                    &no_notes,
+                )?;
+
+                out.flush()
+            };
+
+            get_dir_diff(
+                &source_dir_listing,
+                &target_dir_listing,
+                &source_dir,
+                &target_dirs,
+                &diff_options,
+                Some(&mut printer),
+            )?
+        }
+        None => compute_dir_diff()?,
+    };
+
+    let compare_duration = compare_start.elapsed();
+    timings.push(("compare", compare_duration));
+
+    if args.repeat > 1 {
+        repeat_dir_diff(&compute_dir_diff, &dir_diff, args.repeat, compare_duration)?;
+    }
+
+    let output_start = std::time::Instant::now();
+
+    let exit_code = gated_exit_code(
+        dir_diff.iter().map(|diff_fragment| diff_fragment.status()),
+        &args.exit_code,
+        &args.fail_on,
+    );
+
+    let (pager_process, mut out) = match streamed_output {
+        Some(output) => output,
+        None => open_output(args),
+    };
+
+    // apply the diff before paths get rebased for printing
+    if args.apply {
+        sync::apply_dir_diff(
+            &mut out,
+            &dir_diff,
+            &source_dir,
+            &target_dirs[0],
+            args.yes,
+            args.dry_run,
+        )?;
+
+        out.flush()?;
+
+        if args.timing {
+            timings.push(("apply", output_start.elapsed()));
+            print_timings(&timings);
+        }
+
+        return Ok(exit_code);
+    }
+
+    // hash the files while the paths are still relative to the compared directories
+    let file_hashes = if args.show_hashes {
+        hash::find_file_hashes(&dir_diff, &source_dir, &target_dirs, args.buffer_size)?
+    } else {
+        hash::FileHashes::new()
+    };
+
+    // why changed files are changed, when it is not obvious
+    let mut path_notes = std::collections::HashMap::new();
+
+    // the notes are found by reading the files at the paths of the diff
+    let paths_rewritten = !args.rewrite_source.is_empty() || !args.rewrite_target.is_empty();
+
+    if matches!(args.format, OutputFormat::Text | OutputFormat::Json) && !paths_rewritten {
+        // files that are the same but for a final newline are common, and often not a real change
+        if diff_options.compare_file_content {
+            for path in compare::find_final_newline_changes(&dir_diff, &source_dir, &target_dirs)? {
+                add_path_note(&mut path_notes, path, String::from("final newline"));
+            }
+        }
+
+        if diff_options.compare_creation_times {
+            for path in compare::find_creation_time_changes(&dir_diff, &source_dir, &target_dirs) {
+                add_path_note(&mut path_notes, path, String::from("creation time"));
+            }
+        }
+
+        #[cfg(unix)]
+        if args.sparse_aware {
+            for (path, note) in find_allocation_notes(&dir_diff, &source_dir, &target_dirs) {
+                add_path_note(&mut path_notes, path, note);
+            }
+        }
+
+        #[cfg(unix)]
+        if args.show_perms {
+            for (path, note) in find_mode_notes(&dir_diff, &source_dir, &target_dirs) {
+                add_path_note(&mut path_notes, path, note);
+            }
+        }
+    }
+
+    let changed_files = match &args.external_diff {
+        Some(_) => external::find_changed_files(&dir_diff, &source_dir, &target_dirs),
+        None => Vec::new(),
+    };
+
+    let hook_files = match &args.on_changed_run {
+        Some(_) => external::find_changed_and_added_files(&dir_diff, &target_dirs),
+        None => Vec::new(),
+    };
+
+    // find duplicates while the paths are still relative to the compared directories
+    let duplicate_groups = if args.dedup_report {
+        dedup::find_duplicate_groups(&dir_diff, &source_dir, &target_dirs, args.buffer_size)?
+    } else {
+        Vec::new()
+    };
+
+    let dir_diff = apply_passes(dir_diff, &passes);
+
+    // report paths relative to another directory if asked
+    let dir_diff = match &args.relative_to {
+        Some(base_dir) => rebase_dir_diff(dir_diff, &source_dir, &target_dirs, base_dir)?,
+        None => dir_diff,
+    };
+
+    // only the printed paths change, the notes and hashes must still be found by path
+    let (dir_diff, mut path_notes, file_hashes) = match args.posix_paths {
+        true => (
+            to_posix_paths(dir_diff),
+            path_notes
+                .into_iter()
+                .map(|(path, note)| (posix_path(&path), note))
+                .collect(),
+            file_hashes
+                .into_iter()
+                .map(|(path, hashes)| (posix_path(&path), hashes))
+                .collect(),
+        ),
+        false => (dir_diff, path_notes, file_hashes),
+    };
+
+    if let Some(summary_file) = &args.summary_file {
+        json::write_summary_file(summary_file, &dir_diff)?;
+    }
+
+    // whether the summary counts changed and unchanged files
+    let summary_has_content = diff_options.compare_file_content
+        || args.perms
+        || args.btime
+        || args.dir_content
+        || args.compare_to_manifest.is_some();
+
+    // browsing needs a terminal, the diff is printed instead of failing in scripts
+    #[cfg(feature = "tui")]
+    let browse = args.tui && std::io::IsTerminal::is_terminal(&std::io::stdout());
+
+    #[cfg(feature = "tui")]
+    if args.tui && !browse {
+        eprintln!("note: the output is not a terminal, the diff is printed instead of browsed");
+    }
+
+    match args.format {
+        #[cfg(feature = "tui")]
+        _ if browse => tui::browse(
+            &dir_diff,
+            args.quiet,
+            &tui::DiffSources {
+                source_dir: &source_dir,
+                target_dirs: &target_dirs,
+                context: args.context,
+                algorithm: args.diff_algorithm,
+                theme: args.color_theme,
+                color: !args.no_color,
+            },
+        )?,
+        _ if args.changed_files_only => {
+            let separator = if args.print0 { b'\0' } else { b'\n' };
+            print_changed_files(&mut out, &dir_diff, &target_dirs, separator)?
+        }
+        OutputFormat::Text => {
+            // JSON has separate fields for the hashes
+            for (path, (source_hash, target_hash)) in &file_hashes {
+                let hash_note = match source_hash == target_hash {
+                    true => source_hash.clone(),
+                    false => format!("{} -> {}", source_hash, target_hash),
+                };
+
+                add_path_note(&mut path_notes, path.clone(), hash_note);
+            }
+
+            let canonical_dirs = if args.print_matching_pairs {
+                Some((
+                    canonicalize_dir(&source_dir)?,
+                    target_dirs
+                        .iter()
+                        .map(|target_dir| canonicalize_dir(target_dir))
+                        .collect::<Result<Vec<_>, _>>()?,
+                ))
+            } else {
+                None
+            };
+
+            if let Some((
+                (source_file_count, source_dir_count),
+                (target_file_count, target_dir_count),
+            )) = listing_counts
+            {
+                writeln!(
+                    out,
+                    "source: {} files, {} dirs; target: {} files, {} dirs",
+                    source_file_count, source_dir_count, target_file_count, target_dir_count
+                )?;
+            }
+
+            // streamed entries are already printed
+            if !args.content_threads_chunked {
+                print_dir_diff(
+                    &mut out,
+                    &dir_diff,
+                    args.quiet,
+                    !args.no_color,
+                    args.color_theme,
+                    canonical_dirs.as_ref().map(|(source_dir, target_dirs)| {
+                        (source_dir.as_path(), target_dirs.as_slice())
+                    }),
+                    &path_notes,
+                )?;
+            }
+
+            if !args.no_summary {
+                print_diff_summary(
+                    &mut out,
+                    &dir_diff,
+                    args.quiet,
+                    summary_has_content,
+                    !args.no_color,
+                    args.color_theme,
+                )?;
+            }
+
+            if args.dir_summary {
+                print_dir_summary(&mut out, &dir_diff)?;
+            }
+
+            dedup::print_duplicate_groups(&mut out, &duplicate_groups)?;
+        }
+        OutputFormat::Patch => patch::print_patch(
+            &mut out,
+            &dir_diff,
+            &source_dir,
+            &target_dirs,
+            args.context,
+            args.diff_algorithm,
+        )?,
+        OutputFormat::Json => {
+            json::print_json(&mut out, &dir_diff, args.quiet, &file_hashes, &path_notes)?
+        }
+        OutputFormat::Diffstat => patch::print_diffstat(
+            &mut out,
+            &dir_diff,
+            &source_dir,
+            &target_dirs,
+            args.diff_algorithm,
+        )?,
+        OutputFormat::JsonTree => json::print_json_tree(&mut out, &dir_diff, args.quiet)?,
+        OutputFormat::Github => github::print_github_annotations(&mut out, &dir_diff)?,
+        OutputFormat::Junit => junit::print_junit(&mut out, &dir_diff)?,
+        OutputFormat::Changelog => changelog::print_changelog(&mut out, &dir_diff)?,
+        OutputFormat::Binary => binary::print_binary(&mut out, &dir_diff, args.quiet)?,
+        OutputFormat::Tree => {
+            tree::print_tree(
+                &mut out,
+                &dir_diff,
+                args.quiet,
+                args.ascii_tree,
+                args.tree_indent,
+                !args.no_color,
+                args.color_theme,
+            )?;
+
+            if !args.no_summary {
+                print_diff_summary(
+                    &mut out,
+                    &dir_diff,
+                    args.quiet,
+                    summary_has_content,
+                    !args.no_color,
+                    args.color_theme,
+                )?;
+            }
+        }
+    }
+
+    out.flush()?;
+
+    // the pager only ends once its input is closed
+    drop(out);
+
+    if let Some(mut pager_process) = pager_process {
+        let _ = pager_process.wait();
+    }
+
+    if let Some(diff_command) = &args.external_diff {
+        external::run_external_diff(diff_command, &changed_files)?;
+    }
+
+    if let Some(command_line) = &args.on_changed_run {
+        external::run_on_changed_command(command_line, &hook_files)?;
+    }
+
+    if args.timing {
+        timings.push(("output", output_start.elapsed()));
+        print_timings(&timings);
+    }
+
+    Ok(exit_code)
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> std::path::PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+
+    std::path::PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> std::path::PathBuf {
+    std::path::PathBuf::from(String::from_utf8_lossy(bytes).as_ref())
+}
+
+#[cfg(unix)]
+fn path_to_bytes(path: &std::path::Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn path_to_bytes(path: &std::path::Path) -> Vec<u8> {
+    path.to_string_lossy().as_bytes().to_vec()
+}
+
+fn read_batch_pairs(
+    null_input: bool,
+) -> Result<Vec<(std::path::PathBuf, std::path::PathBuf)>, DirDiffError> {
+    // Read the `source<TAB>target` pairs of directories from stdin, separated by newline or NUL
+    // characters. Empty records are skipped
+    use std::io::Read;
+
+    let mut input = Vec::new();
+    std::io::stdin()
+        .read_to_end(&mut input)
+        .map_err(|source| DirDiffError::Read {
+            path: std::path::PathBuf::from("-"),
+            source,
+        })?;
+
+    let separator = if null_input { b'\0' } else { b'\n' };
+
+    input
+        .split(|byte| *byte == separator)
+        .map(|record| match null_input {
+            true => record,
+            false => record.strip_suffix(b"\r").unwrap_or(record),
+        })
+        .enumerate()
+        .filter(|(_, record)| !record.is_empty())
+        .map(
+            |(index, record)| match record.iter().position(|byte| *byte == b'\t') {
+                Some(tab_index) => Ok((
+                    path_from_bytes(&record[..tab_index]),
+                    path_from_bytes(&record[tab_index + 1..]),
+                )),
+                None => Err(DirDiffError::InvalidBatchRecord(index + 1)),
+            },
+        )
+        .collect()
+}
+
+fn print_error(e: &DirDiffError, format: OutputFormat) {
+    // Print the error to stderr, as a JSON object with the JSON formats, so their consumers can
+    // parse it too
+
+    match format {
+        OutputFormat::Json | OutputFormat::JsonTree => {
+            eprintln!("{}", serde_json::json!({ "error": e.to_string() }))
+        }
+        _ => eprintln!("Error: {}", e),
+    }
+}
+
+fn run_batch(args: &CliArgs) -> Result<i32, DirDiffError> {
+    // Compare every pair of directories read from stdin, each after a label line. An error only
+    // stops the comparison of its pair. The exit code is the highest exit code of all pairs
+
+    let mut exit_code = 0;
+
+    for (source_dir, target_dir) in read_batch_pairs(args.null_input)? {
+        println!("==> {} {} <==", source_dir.display(), target_dir.display());
+
+        let pair_args = CliArgs {
+            source_dir: Some(source_dir),
+            target_dirs: vec![target_dir],
+            ..args.clone()
+        };
+
+        let pair_exit_code = match run(&pair_args) {
+            Ok(pair_exit_code) => pair_exit_code,
+            Err(DirDiffError::Interrupted) => return Err(DirDiffError::Interrupted),
+            Err(e) => {
+                print_error(&e, args.format);
+                1
+            }
+        };
+
+        exit_code = exit_code.max(pair_exit_code);
+    }
+
+    Ok(exit_code)
+}
+
+fn main() {
+    let args = CliArgs::parse();
+
+    if args.list_formats {
+        match print_formats(&mut std::io::stdout().lock()) {
+            Ok(()) => std::process::exit(0),
+            Err(_) => std::process::exit(1),
+        }
+    }
+
+    interrupt::install_handler();
+
+    let result = match args.batch {
+        true => run_batch(&args),
+        false => run(&args),
+    };
+
+    match result {
+        Ok(exit_code) => std::process::exit(exit_code),
+        Err(e) => {
+            print_error(&e, args.format);
+
+            // like shells do for processes killed by SIGINT
+            match e {
+                DirDiffError::Interrupted => std::process::exit(130),
+                _ => std::process::exit(1),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_tree(dir: &std::path::Path, files: &[(&str, &str)]) -> Vec<std::path::PathBuf> {
+        // Write the files (and their parent directories) in dir, and return the sorted listing of
+        // dir
+
+        for (path, content) in files {
+            let file_path = dir.join(path);
+
+            std::fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+            std::fs::write(file_path, content).unwrap();
+        }
+
+        let mut listing: Vec<std::path::PathBuf> = walkdir::WalkDir::new(dir)
+            .min_depth(1)
+            .into_iter()
+            .map(|entry| {
+                entry
+                    .unwrap()
+                    .path()
+                    .strip_prefix(dir)
+                    .unwrap()
+                    .to_path_buf()
+            })
+            .collect();
+
+        sort_dir_listing(&mut listing, PathCase::Sensitive);
+
+        listing
+    }
+
+    fn diff_options(io_threads: usize) -> DiffOptions {
+        DiffOptions {
+            compare_file_content: true,
+            comparator: Box::new(compare::ByteComparator { buffer_size: 4096 }),
+            io_threads,
+            permission_mask: None,
+            compare_creation_times: false,
+            compare_dir_children: false,
+            path_case: PathCase::Sensitive,
+            compare_symlink_targets: false,
+            source_paths: RewrittenPaths::new(),
+            target_paths: RewrittenPaths::new(),
+        }
+    }
+
+    #[test]
+    fn summary_counts_match_printed_entries_on_uneven_listings() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        let source_listing = write_tree(
+            source_dir.path(),
+            &[
+                ("a", "a"),
+                ("b/1", "1"),
+                ("b/2", "2"),
+                ("b/3/x", "x"),
+                ("c", "c"),
+                ("d", "d"),
+                ("e", "e"),
+                ("z/1", "1"),
+            ],
+        );
+        let target_listing = write_tree(
+            target_dir.path(),
+            &[("b/2", "changed"), ("c", "c"), ("f", "f"), ("g/1", "1")],
+        );
+
+        for (source_listing, target_listing, source_dir, target_dir) in [
+            (&source_listing, &target_listing, &source_dir, &target_dir),
+            (&target_listing, &source_listing, &target_dir, &source_dir),
+        ] {
+            let dir_diff = get_dir_diff(
+                source_listing,
+                target_listing,
+                source_dir.path(),
+                &[target_dir.path().to_path_buf()],
+                &diff_options(1),
+                None,
+            )
+            .unwrap();
+
+            let mut printed = Vec::new();
+
+            print_dir_diff(
+                &mut printed,
+                &dir_diff,
+                false,
+                false,
+                ColorTheme::Default,
+                None,
+                &std::collections::HashMap::new(),
+            )
+            .unwrap();
+
+            let printed = String::from_utf8(printed).unwrap();
+            let count_lines = |prefix: &str| {
+                printed
+                    .lines()
+                    .filter(|line| line.starts_with(prefix))
+                    .count()
+            };
+
+            let removed = count_lines("- ");
+            let added = count_lines("+ ");
+            let changed = count_lines("~ ");
+            let similar = count_lines("  ");
+            let unchanged = count_lines(" ") - similar;
+
+            // every listed path is printed once, similar paths once for both listings
+            assert_eq!(
+                removed + added + 2 * (changed + similar + unchanged),
+                source_listing.len() + target_listing.len()
+            );
+            assert_eq!(
+                summary(&dir_diff, false),
+                format!(
+                    "{} removed, {} added, {} similar, {} files changed, {} files unchanged, \
+                     0 special files\n",
+                    removed, added, similar, changed, unchanged
+                )
+            );
+        }
+    }
+
+    fn walk_options() -> WalkOptions {
+        WalkOptions {
+            depth: None,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            exclude_dirs: Vec::new(),
+            hidden: true,
+            follow_symlinks: true,
+            one_file_system: false,
+            deadline: None,
+        }
+    }
+
+    fn summary(dir_diff: &[DirDiff<std::path::PathBuf>], color: bool) -> String {
+        let mut out = Vec::new();
+
+        print_diff_summary(&mut out, dir_diff, false, true, color, ColorTheme::Default).unwrap();
+
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn parallel_comparisons_keep_the_serial_order() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        // files of very different sizes, so comparisons finish out of order
+        let files: Vec<(String, String)> = (0..200)
+            .map(|i| (format!("d{}/f{}", i % 7, i), "x".repeat((i * 7919) % 20000)))
+            .collect();
+        let source_files: Vec<(&str, &str)> = files
+            .iter()
+            .map(|(path, content)| (path.as_str(), content.as_str()))
+            .collect();
+        let target_files: Vec<(&str, &str)> = files
+            .iter()
+            .enumerate()
+            .map(|(i, (path, content))| match i % 3 {
+                0 => (path.as_str(), "changed"),
+                _ => (path.as_str(), content.as_str()),
+            })
+            .collect();
+
+        let source_listing = write_tree(source_dir.path(), &source_files);
+        let target_listing = write_tree(target_dir.path(), &target_files);
+        let target_dirs = [target_dir.path().to_path_buf()];
+
+        let diff = |io_threads: usize| {
+            // the diff, and the entries given to the printer
+            let mut printed = Vec::new();
+            let mut printer = |entries: &[DirDiff<std::path::PathBuf>]| {
+                printed.extend(entries.iter().map(|entry| format!("{:?}", entry)));
+                Ok(())
+            };
+
+            let dir_diff = get_dir_diff(
+                &source_listing,
+                &target_listing,
+                source_dir.path(),
+                &target_dirs,
+                &diff_options(io_threads),
+                Some(&mut printer),
+            )
+            .unwrap();
+
+            let dir_diff: Vec<String> = dir_diff
+                .iter()
+                .map(|entry| format!("{:?}", entry))
+                .collect();
+
+            (dir_diff, printed)
+        };
+
+        let (serial_diff, serial_printed) = diff(1);
+
+        assert_eq!(serial_diff, serial_printed);
+
+        for io_threads in [2, 8] {
+            let (parallel_diff, parallel_printed) = diff(io_threads);
+
+            assert_eq!(parallel_diff, serial_diff);
+            assert_eq!(parallel_printed, serial_printed);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn normalizing_keeps_parent_of_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+
+        std::fs::create_dir_all(dir.path().join("real/child")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("real/child"), dir.path().join("link")).unwrap();
+
+        assert_eq!(
+            normalize_dir_path(&dir.path().join("real/child/..")),
+            dir.path().join("real")
+        );
+        assert_eq!(
+            normalize_dir_path(&dir.path().join("link/..")),
+            dir.path().join("link/..")
+        );
+        assert_eq!(
+            normalize_dir_path(std::path::Path::new("./missing/../x/")),
+            std::path::PathBuf::from("x")
+        );
+    }
+
+    #[test]
+    fn exit_codes_fit_in_a_byte() {
+        assert!(matches!(
+            parse_exit_code("changed=255"),
+            Ok((DiffStatus::Changed, 255))
+        ));
+        assert!(parse_exit_code("changed=256").is_err());
+        assert!(parse_exit_code("changed=-1").is_err());
+        assert!(parse_exit_code("changed").is_err());
+    }
+
+    #[test]
+    fn summary_is_colored_like_the_entries() {
+        colored::control::set_override(true);
+
+        let dir_diff = vec![
+            DirDiff::Removed(std::path::PathBuf::from("a")),
+            DirDiff::Added(std::path::PathBuf::from("b")),
+            DirDiff::Similar(
+                std::path::PathBuf::from("c"),
+                Some(DirDiffFileContent::Changed),
+            ),
+        ];
+
+        let colored_summary = summary(&dir_diff, true);
+
+        assert!(colored_summary.contains("\x1b[31m1 removed\x1b[0m"));
+        assert!(colored_summary.contains("\x1b[32m1 added\x1b[0m"));
+        assert!(colored_summary.contains("\x1b[33m1 files changed\x1b[0m"));
+
+        assert_eq!(
+            summary(&dir_diff, false),
+            "1 removed, 1 added, 0 similar, 1 files changed, 0 files unchanged, \
+             0 special files\n"
+        );
+    }
+
+    fn statuses(dir_diff: &[DirDiff<std::path::PathBuf>]) -> Vec<String> {
+        // Return the status and path of every entry, for readable assertions
+
+        dir_diff
+            .iter()
+            .map(|diff_fragment| {
+                format!(
+                    "{} {}",
+                    diff_fragment.status().name(),
+                    diff_fragment.path().display()
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rebases_paths_on_a_parent_directory() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let project_dir = canonicalize_dir(temp_dir.path()).unwrap();
+
+        let old_dir = project_dir.join("old");
+        let new_dir = project_dir.join("new");
+
+        let old_listing = write_tree(&old_dir, &[("src/a.rs", "a"), ("src/b.rs", "b")]);
+        let new_listing = write_tree(&new_dir, &[("src/b.rs", "b"), ("src/c.rs", "c")]);
+        let target_dirs = [new_dir.clone()];
+
+        let dir_diff = || {
+            get_dir_diff(
+                &old_listing,
+                &new_listing,
+                &old_dir,
+                &target_dirs,
+                &diff_options(1),
+                None,
+            )
+            .unwrap()
+        };
+
+        // removed and similar paths are in the source, added ones in the target
+        assert_eq!(
+            statuses(&rebase_dir_diff(dir_diff(), &old_dir, &target_dirs, &project_dir).unwrap()),
+            [
+                "similar old/src",
+                "removed old/src/a.rs",
+                "unchanged old/src/b.rs",
+                "added new/src/c.rs",
+            ]
+        );
+
+        // paths outside of the base directory stay absolute
+        assert_eq!(
+            statuses(&rebase_dir_diff(dir_diff(), &old_dir, &target_dirs, &old_dir).unwrap())[3],
+            format!("added {}", new_dir.join("src/c.rs").display())
+        );
+    }
+
+    // fails the test if the content of a file is compared
+    struct UnreadComparator;
+
+    impl ContentComparator for UnreadComparator {
+        fn compare(
+            &self,
+            source_file_path: &std::path::Path,
+            _target_file_path: &std::path::Path,
+        ) -> std::io::Result<DirDiffFileContent> {
+            panic!("{} was read", source_file_path.display());
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn special_files_are_not_read() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        for dir in [source_dir.path(), target_dir.path()] {
+            let status = std::process::Command::new("mkfifo")
+                .arg(dir.join("pipe"))
+                .status()
+                .unwrap();
+            assert!(status.success());
+
+            std::os::unix::net::UnixListener::bind(dir.join("socket")).unwrap();
+        }
+
+        let source_listing = write_tree(source_dir.path(), &[]);
+        let target_listing = write_tree(target_dir.path(), &[]);
+
+        let options = DiffOptions {
+            comparator: Box::new(UnreadComparator),
+            ..diff_options(1)
+        };
+
+        let dir_diff = get_dir_diff(
+            &source_listing,
+            &target_listing,
+            source_dir.path(),
+            &[target_dir.path().to_path_buf()],
+            &options,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(statuses(&dir_diff), ["special pipe", "special socket"]);
+        assert!(summary(&dir_diff, false).ends_with(", 2 special files\n"));
+    }
+
+    #[test]
+    fn applying_refuses_filters() {
+        let parse =
+            |args: &[&str]| CliArgs::try_parse_from([&["dirdiff", "a", "b"], args].concat());
+
+        assert!(parse(&["--apply"]).is_ok());
+
+        for filter in [
+            &["--depth", "1"][..],
+            &["--include", "*.rs"],
+            &["--exclude", "*.rs"],
+            &["--exclude-dir", "target"],
+            &["--skip-hidden"],
+            &["--paths-from", "paths"],
+        ] {
+            assert!(parse(&[&["--apply"], filter].concat()).is_err());
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn ignored_errors_are_not_printed() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // following a symlink to a parent directory is a walk error, even for root
+        for loop_dir in ["known", "other"] {
+            std::fs::create_dir(dir.path().join(loop_dir)).unwrap();
+            std::os::unix::fs::symlink("..", dir.path().join(loop_dir).join("loop")).unwrap();
+        }
+
+        let mut errors = Vec::new();
+
+        get_dir_listing(
+            dir.path(),
+            &walk_options(),
+            &parse_patterns(&[String::from("known/*")]).unwrap(),
+            None,
+            &mut |e| errors.push(e.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("other/loop"));
+    }
+
+    // fails every comparison with an error that is not a permission error
+    struct BrokenComparator;
+
+    impl ContentComparator for BrokenComparator {
+        fn compare(
+            &self,
+            _source_file_path: &std::path::Path,
+            _target_file_path: &std::path::Path,
+        ) -> std::io::Result<DirDiffFileContent> {
+            Err(std::io::Error::from(std::io::ErrorKind::InvalidData))
+        }
+    }
+
+    #[test]
+    fn failures_have_their_own_error_variant() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir_path = dir.path();
+
+        write_tree(
+            dir_path,
+            &[("file", ""), ("split/a/f", ""), ("split/b/f", "")],
+        );
+
+        assert!(matches!(
+            check_dir_arg(&dir_path.join("missing"), false),
+            Err(DirDiffError::MissingDirectory(_))
+        ));
+        assert!(check_dir_arg(&dir_path.join("missing"), true).is_ok());
+        assert!(matches!(
+            check_dir_arg(&dir_path.join("file"), false),
+            Err(DirDiffError::NotADirectory(_))
+        ));
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink("missing", dir_path.join("broken_link")).unwrap();
+            std::os::unix::fs::symlink("file", dir_path.join("file_link")).unwrap();
+
+            assert!(matches!(
+                check_dir_arg(&dir_path.join("broken_link"), true),
+                Err(DirDiffError::BrokenSymlink { .. })
+            ));
+            assert!(matches!(
+                check_dir_arg(&dir_path.join("file_link"), false),
+                Err(DirDiffError::SymlinkNotADirectory { .. })
+            ));
+        }
+
+        assert!(matches!(
+            strip_dir_components(&dir_path.join("split"), 1),
+            Err(DirDiffError::CannotStrip(_))
+        ));
+        assert!(matches!(
+            parse_patterns(&[String::from("[")]),
+            Err(DirDiffError::InvalidPattern(_))
+        ));
+        assert!(matches!(
+            read_path_list(&dir_path.join("missing")),
+            Err(DirDiffError::Read { .. })
+        ));
+
+        std::fs::write(dir_path.join("paths"), "a\n../b\n").unwrap();
+
+        assert!(matches!(
+            read_path_list(&dir_path.join("paths")),
+            Err(DirDiffError::InvalidListedPath(_))
+        ));
+        assert!(matches!(
+            rewrite_dir_listing(
+                vec!["a".into(), "b".into()],
+                &[parse_path_rewrite("[ab]=c").unwrap()]
+            ),
+            Err(DirDiffError::RewriteCollision(_))
+        ));
+
+        let listing = write_tree(dir_path, &[]);
+        let options = DiffOptions {
+            comparator: Box::new(BrokenComparator),
+            ..diff_options(1)
+        };
+
+        assert!(matches!(
+            get_dir_diff(
+                &listing,
+                &listing,
+                dir_path,
+                &[dir_path.to_path_buf()],
+                &options,
+                None
+            ),
+            Err(DirDiffError::Compare { .. })
+        ));
+
+        // only errors writing the output are output errors
+        assert!(matches!(
+            DirDiffError::from(std::io::Error::from(std::io::ErrorKind::BrokenPipe)),
+            DirDiffError::Output(_)
+        ));
+    }
+
+    #[test]
+    fn entries_use_the_codes_of_the_theme() {
+        colored::control::set_override(true);
+
+        let dir_diff = vec![
+            DirDiff::Removed(std::path::PathBuf::from("a")),
+            DirDiff::Added(std::path::PathBuf::from("b")),
+            DirDiff::Similar(
+                std::path::PathBuf::from("c"),
+                Some(DirDiffFileContent::Changed),
+            ),
+        ];
+
+        let print = |theme: ColorTheme| {
+            let mut out = Vec::new();
+
+            print_dir_diff(
+                &mut out,
+                &dir_diff,
+                false,
+                true,
+                theme,
+                None,
+                &std::collections::HashMap::new(),
+            )
+            .unwrap();
+
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(
+            print(ColorTheme::ColorblindSafe),
+            "\x1b[38;2;255;140;0m<\x1b[0m \x1b[38;2;255;140;0ma\x1b[0m\n\
+             \x1b[34m>\x1b[0m \x1b[34mb\x1b[0m\n\
+             \x1b[35m!\x1b[0m \x1b[35mc\x1b[0m\n"
+        );
+        assert_eq!(
+            print(ColorTheme::MonochromeSymbols),
+            "[-] a\n[+] b\n[~] c\n"
+        );
+    }
+
+    #[test]
+    fn paths_are_similar_if_in_any_target() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dirs = [(); 3].map(|_| tempfile::tempdir().unwrap());
+
+        let source_listing = write_tree(
+            source_dir.path(),
+            &[("mirrored", "content"), ("missing", "")],
+        );
+
+        write_tree(target_dirs[0].path(), &[("other", "")]);
+        write_tree(target_dirs[1].path(), &[("mirrored", "content")]);
+        write_tree(target_dirs[2].path(), &[]);
+
+        let target_dirs: Vec<std::path::PathBuf> = target_dirs
+            .iter()
+            .map(|target_dir| target_dir.path().to_path_buf())
+            .collect();
+
+        let mut target_listing =
+            get_union_dir_listing(&target_dirs, &walk_options(), &[], None, &mut |e| {
+                panic!("{}", e)
+            })
+            .unwrap();
+        sort_dir_listing(&mut target_listing, PathCase::Sensitive);
+
+        let dir_diff = get_dir_diff(
+            &source_listing,
+            &target_listing,
+            source_dir.path(),
+            &target_dirs,
+            &diff_options(1),
+            None,
+        )
+        .unwrap();
+
+        // the content is compared with the file of the target it is in
+        assert_eq!(
+            statuses(&dir_diff),
+            ["unchanged mirrored", "removed missing", "added other"]
+        );
+    }
+
+    struct RecordingWriter(std::rc::Rc<std::cell::RefCell<Vec<Vec<u8>>>>);
+
+    impl Write for RecordingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn buffered_output_reaches_the_terminal_in_whole_lines() {
+        let writes = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+
+        // stdout is line buffered, like the LineWriter standing for it here, but writes the
+        // partial line it keeps when a longer write comes. The listing is several times the size
+        // of the buffer, so it is written in the middle
+        let mut out = LineBufWriter::new(std::io::LineWriter::new(RecordingWriter(writes.clone())));
+
+        let dir_diff: Vec<DirDiff<std::path::PathBuf>> = (0..5000)
+            .map(|i| DirDiff::Added(std::path::PathBuf::from(format!("dir/file{}", i))))
+            .collect();
+
+        print_dir_diff(
+            &mut out,
+            &dir_diff,
+            false,
+            false,
+            ColorTheme::Default,
+            None,
+            &std::collections::HashMap::new(),
+        )
+        .unwrap();
+        out.flush().unwrap();
+
+        let writes = writes.borrow();
+
+        assert!(writes.len() > 1);
+        assert!(writes.iter().all(|write| write.ends_with(b"\n")));
+        assert_eq!(
+            writes.concat().iter().filter(|&&b| b == b'\n').count(),
+            5000
+        );
+    }
+
+    #[test]
+    fn empty_files_are_compared_in_every_mode() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        let source_listing = write_tree(
+            source_dir.path(),
+            &[("both_empty", ""), ("grown", ""), ("removed", "")],
+        );
+        let target_listing = write_tree(target_dir.path(), &[("both_empty", ""), ("grown", "x")]);
+
+        let comparators: Vec<Box<dyn ContentComparator>> = vec![
+            Box::new(compare::ByteComparator { buffer_size: 4096 }),
+            Box::new(compare::MmapComparator { buffer_size: 4096 }),
+            Box::new(compare::SampleComparator { sample_size: 16 }),
+            Box::new(compare::TextComparator {
+                ignore_case: false,
+                ignored_lines: Vec::new(),
+                ignore_final_newline: false,
+                head_lines: None,
+            }),
+        ];
+
+        for comparator in comparators {
+            let options = DiffOptions {
+                comparator,
+                ..diff_options(1)
+            };
+
+            let dir_diff = get_dir_diff(
+                &source_listing,
+                &target_listing,
+                source_dir.path(),
+                &[target_dir.path().to_path_buf()],
+                &options,
+                None,
+            )
+            .unwrap();
+
+            assert_eq!(
+                statuses(&dir_diff),
+                ["unchanged both_empty", "changed grown", "removed removed"]
+            );
+        }
+    }
+
+    #[test]
+    fn fail_on_gates_each_status() {
+        let every_status = <DiffStatus as clap::ValueEnum>::value_variants();
+
+        // without --fail-on, no status fails
+        assert_eq!(gated_exit_code(every_status.iter().copied(), &[], &[]), 0);
+
+        for &gated_status in every_status {
+            // fails if a path has the status, whatever the other statuses
+            assert_eq!(
+                gated_exit_code(every_status.iter().copied(), &[], &[gated_status]),
+                1,
+                "{}",
+                gated_status.name()
+            );
+
+            let other_statuses = every_status
+                .iter()
+                .copied()
+                .filter(|&status| status != gated_status);
+
+            assert_eq!(
+                gated_exit_code(other_statuses, &[], &[gated_status]),
+                0,
+                "{}",
+                gated_status.name()
+            );
+        }
+
+        // the highest code wins
+        assert_eq!(
+            gated_exit_code(
+                [DiffStatus::Added, DiffStatus::Removed].into_iter(),
+                &[(DiffStatus::Removed, 3)],
+                &[DiffStatus::Added]
+            ),
+            3
+        );
+    }
+
+    #[test]
+    fn relative_dirs_are_listed_like_absolute_ones() {
+        // the tests run in the crate directory, so a directory in it can be given relative to `.`
+        let source_dir = tempfile::tempdir_in(".").unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        let files = [("a", "a"), ("dir/b", "b"), ("dir/c", "c")];
+        write_tree(source_dir.path(), &files);
+        let target_listing = write_tree(target_dir.path(), &files[..2]);
+
+        let relative_dir = std::path::Path::new(".").join(source_dir.path().file_name().unwrap());
+
+        let print_diff = |dir: &std::path::Path| {
+            let source_listing =
+                get_dir_listing(dir, &walk_options(), &[], None, &mut |e| panic!("{}", e)).unwrap();
+
+            let dir_diff = get_dir_diff(
+                &source_listing,
+                &target_listing,
+                dir,
+                &[target_dir.path().to_path_buf()],
+                &diff_options(1),
+                None,
+            )
+            .unwrap();
+
+            let mut out = Vec::new();
+
+            print_dir_diff(
+                &mut out,
+                &dir_diff,
+                false,
+                false,
+                ColorTheme::Default,
+                None,
+                &std::collections::HashMap::new(),
+            )
+            .unwrap();
+
+            String::from_utf8(out).unwrap()
+        };
+
+        let relative_output = print_diff(&relative_dir);
+
+        assert_eq!(relative_output, print_diff(source_dir.path()));
+        assert_eq!(relative_output, " a\n  dir\n dir/b\n- dir/c\n");
+    }
+
+    struct AlwaysUnchangedComparator;
+
+    impl ContentComparator for AlwaysUnchangedComparator {
+        fn compare(
+            &self,
+            _source_file_path: &std::path::Path,
+            _target_file_path: &std::path::Path,
+        ) -> std::io::Result<DirDiffFileContent> {
+            Ok(DirDiffFileContent::Unchanged)
+        }
+    }
+
+    #[test]
+    fn files_are_compared_with_the_comparator_of_the_options() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        let source_listing = write_tree(source_dir.path(), &[("a", "1"), ("b", "same")]);
+        let target_listing = write_tree(target_dir.path(), &[("a", "22"), ("b", "same")]);
+
+        let diff = |options: &DiffOptions| {
+            let dir_diff = get_dir_diff(
+                &source_listing,
+                &target_listing,
+                source_dir.path(),
+                &[target_dir.path().to_path_buf()],
+                options,
+                None,
+            )
+            .unwrap();
+
+            statuses(&dir_diff)
+        };
+
+        assert_eq!(diff(&diff_options(1)), ["changed a", "unchanged b"]);
+
+        let options = DiffOptions {
+            comparator: Box::new(AlwaysUnchangedComparator),
+            ..diff_options(1)
+        };
+
+        assert_eq!(diff(&options), ["unchanged a", "unchanged b"]);
+    }
+
+    #[cfg(feature = "git")]
+    #[test]
+    fn since_diffs_the_working_tree_against_a_revision() {
+        let repo_dir = tempfile::tempdir().unwrap();
+        write_tree(repo_dir.path(), &[("modified", "old"), ("kept", "kept")]);
+
+        // commit both files, then modify one of them in the working tree
+        let repo = git2::Repository::init(repo_dir.path()).unwrap();
+        let mut index = repo.index().unwrap();
+        index
+            .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("dirdiff", "dirdiff@example.com").unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "files", &tree, &[])
+            .unwrap();
+
+        std::fs::write(repo_dir.path().join("modified"), "new").unwrap();
+
+        let revision_tree = git::checkout_revision(repo_dir.path(), "HEAD").unwrap();
+
+        // the .git directory is hidden
+        let walk_options = WalkOptions {
+            hidden: false,
+            ..walk_options()
+        };
+        let list = |dir: &std::path::Path| {
+            let mut listing =
+                get_dir_listing(dir, &walk_options, &[], None, &mut |e| panic!("{}", e)).unwrap();
+            sort_dir_listing(&mut listing, PathCase::Sensitive);
+            listing
+        };
+
+        let dir_diff = get_dir_diff(
+            &list(&revision_tree.path),
+            &list(repo_dir.path()),
+            &revision_tree.path,
+            &[repo_dir.path().to_path_buf()],
+            &diff_options(1),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(statuses(&dir_diff), ["unchanged kept", "changed modified"]);
+    }
+
+    #[test]
+    fn prune_empty_keeps_only_subtrees_with_differences() {
+        let similar = |path: &str| DirDiff::Similar(std::path::PathBuf::from(path), None);
+        let unchanged = |path: &str| {
+            DirDiff::Similar(
+                std::path::PathBuf::from(path),
+                Some(DirDiffFileContent::Unchanged),
+            )
+        };
+
+        let dir_diff = vec![
+            similar("deep"),
+            similar("deep/a"),
+            similar("deep/a/b"),
+            unchanged("deep/a/b/file"),
+            similar("mixed"),
+            similar("mixed/same"),
+            unchanged("mixed/same/file"),
+            similar("mixed/sub"),
+            DirDiff::Similar(
+                std::path::PathBuf::from("mixed/sub/file"),
+                Some(DirDiffFileContent::Changed),
+            ),
+            unchanged("mixed/unchanged"),
+            unchanged("top"),
+        ];
+
+        assert_eq!(
+            statuses(&prune_empty_subtrees(dir_diff)),
+            [
+                "similar mixed",
+                "similar mixed/sub",
+                "changed mixed/sub/file"
+            ]
+        );
+    }
+
+    // counts the comparisons running at the same time, and compares paths instead of files
+    #[derive(Default)]
+    struct ConcurrencyComparator {
+        running: std::sync::atomic::AtomicUsize,
+        max_running: std::sync::Arc<std::sync::atomic::AtomicUsize>, // shared with the test
+    }
+
+    impl ContentComparator for ConcurrencyComparator {
+        fn compare(
+            &self,
+            source_file_path: &std::path::Path,
+            target_file_path: &std::path::Path,
+        ) -> std::io::Result<DirDiffFileContent> {
+            use std::sync::atomic::Ordering;
+
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_running.fetch_max(running, Ordering::SeqCst);
+
+            std::thread::sleep(std::time::Duration::from_millis(2));
+
+            self.running.fetch_sub(1, Ordering::SeqCst);
+
+            match source_file_path == target_file_path {
+                true => Ok(DirDiffFileContent::Unchanged),
+                false => Ok(DirDiffFileContent::Changed),
+            }
+        }
+    }
+
+    #[test]
+    fn io_threads_bound_the_comparisons_running_at_once() {
+        let pending_comparisons: Vec<PendingComparison> = (0..40)
+            .map(|i| {
+                let target_file_path = match i % 3 {
+                    0 => format!("other{}", i),
+                    _ => format!("file{}", i),
+                };
+
+                (i, format!("file{}", i).into(), target_file_path.into())
+            })
+            .collect();
+
+        for io_threads in [1, 2, 4, 16] {
+            let comparator = ConcurrencyComparator::default();
+            let max_running = comparator.max_running.clone();
+
+            let options = DiffOptions {
+                comparator: Box::new(comparator),
+                ..diff_options(io_threads)
+            };
+
+            let mut results = Vec::new();
+            compare_files(&pending_comparisons, &options, &mut |i, result| {
+                results.push((i, result.unwrap()))
+            });
+
+            let expected: Vec<(usize, DirDiffFileContent)> = (0..40)
+                .map(|i| match i % 3 {
+                    0 => (i, DirDiffFileContent::Changed),
+                    _ => (i, DirDiffFileContent::Unchanged),
+                })
+                .collect();
+
+            assert_eq!(format!("{:?}", results), format!("{:?}", expected));
+
+            let max_running = max_running.load(std::sync::atomic::Ordering::SeqCst);
+            assert!(
+                max_running <= io_threads,
+                "{} > {}",
+                max_running,
+                io_threads
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn permission_bits_outside_the_mask_are_not_compared() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        let source_listing = write_tree(source_dir.path(), &[("group_write", "x"), ("owner", "x")]);
+        let target_listing = write_tree(target_dir.path(), &[("group_write", "x"), ("owner", "x")]);
+
+        let set_mode = |dir: &tempfile::TempDir, path: &str, mode: u32| {
+            std::fs::set_permissions(dir.path().join(path), std::fs::Permissions::from_mode(mode))
+                .unwrap()
+        };
+
+        set_mode(&source_dir, "group_write", 0o644);
+        set_mode(&target_dir, "group_write", 0o664);
+        set_mode(&source_dir, "owner", 0o644);
+        set_mode(&target_dir, "owner", 0o744);
+
+        let diff = |permission_mask: u32| {
+            let options = DiffOptions {
+                permission_mask: Some(permission_mask),
+                ..diff_options(1)
+            };
+
+            let dir_diff = get_dir_diff(
+                &source_listing,
+                &target_listing,
+                source_dir.path(),
+                &[target_dir.path().to_path_buf()],
+                &options,
+                None,
+            )
+            .unwrap();
+
+            statuses(&dir_diff)
+        };
+
+        assert_eq!(diff(0o7777), ["changed group_write", "changed owner"]);
+        // group and other write bits masked out
+        assert_eq!(diff(0o7755), ["unchanged group_write", "changed owner"]);
+    }
+
+    #[test]
+    fn summary_file_is_written_next_to_the_text_output() {
+        let summary_dir = tempfile::tempdir().unwrap();
+        let summary_path = summary_dir.path().join("summary.json");
+
+        let dir_diff = vec![
+            DirDiff::Removed(std::path::PathBuf::from("a")),
+            DirDiff::Added(std::path::PathBuf::from("b")),
+            DirDiff::Similar(
+                std::path::PathBuf::from("c"),
+                Some(DirDiffFileContent::Unchanged),
+            ),
+        ];
+
+        json::write_summary_file(&summary_path, &dir_diff).unwrap();
+
+        let mut out = Vec::new();
+        print_dir_diff(
+            &mut out,
+            &dir_diff,
+            false,
+            false,
+            ColorTheme::Default,
+            None,
+            &std::collections::HashMap::new(),
+        )
+        .unwrap();
+
+        // the output stays text, and the summary file is only JSON
+        assert_eq!(String::from_utf8(out).unwrap(), "- a\n+ b\n c\n");
+
+        let summary: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&summary_path).unwrap()).unwrap();
+
+        assert_eq!(summary["removed"], 1);
+        assert_eq!(summary["added"], 1);
+        assert_eq!(summary["unchanged"], 1);
+        assert_eq!(summary["changed"], 0);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn long_nested_paths_are_listed() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        // longer than the 260 characters of MAX_PATH, which needs the verbatim prefix
+        let long_path: std::path::PathBuf = (0..30).map(|i| format!("directory{:02}", i)).collect();
+        let file_path = long_path.join("file");
+
+        for dir in [&source_dir, &target_dir] {
+            let verbatim_dir = std::fs::canonicalize(dir.path()).unwrap();
+
+            std::fs::create_dir_all(verbatim_dir.join(&long_path)).unwrap();
+            std::fs::write(verbatim_dir.join(&file_path), "content").unwrap();
+        }
+
+        let list = |dir: &std::path::Path| {
+            let mut listing =
+                get_dir_listing(dir, &walk_options(), &[], None, &mut |e| panic!("{}", e)).unwrap();
+            sort_dir_listing(&mut listing, PathCase::Insensitive);
+            listing
+        };
+
+        let source_listing = list(source_dir.path());
+
+        assert_eq!(source_listing.len(), 31);
+        assert_eq!(source_listing.last().unwrap(), &file_path);
+
+        let options = DiffOptions {
+            path_case: PathCase::Insensitive,
+            ..diff_options(1)
+        };
+
+        let dir_diff = get_dir_diff(
+            &source_listing,
+            &list(target_dir.path()),
+            source_dir.path(),
+            &[target_dir.path().to_path_buf()],
+            &options,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(
+            statuses(&dir_diff).last().unwrap(),
+            &format!("unchanged {}", file_path.display())
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn case_variants_are_the_same_path_by_default() {
+        let args = CliArgs::try_parse_from(["dirdiff", "a", "b"]).unwrap();
+        assert!(args.path_case == PathCase::Insensitive);
+
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        let mut source_listing = write_tree(source_dir.path(), &[("Dir/Readme.md", "x")]);
+        let mut target_listing = write_tree(target_dir.path(), &[("dir/README.md", "x")]);
+        sort_dir_listing(&mut source_listing, args.path_case);
+        sort_dir_listing(&mut target_listing, args.path_case);
+
+        let options = DiffOptions {
+            path_case: args.path_case,
+            ..diff_options(1)
+        };
+
+        let dir_diff = get_dir_diff(
+            &source_listing,
+            &target_listing,
+            source_dir.path(),
+            &[target_dir.path().to_path_buf()],
+            &options,
+            None,
+        )
+        .unwrap();
+
+        // the paths are matched, the source spelling is kept
+        assert_eq!(
+            statuses(&dir_diff),
+            ["similar Dir", "unchanged Dir/Readme.md"]
+        );
+    }
+
+    #[test]
+    fn matching_pairs_show_both_absolute_paths() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dirs = [(); 2].map(|_| tempfile::tempdir().unwrap());
+
+        write_tree(source_dir.path(), &[("dir/file", "x")]);
+        write_tree(target_dirs[1].path(), &[("dir/file", "x")]);
+
+        let source_dir = canonicalize_dir(source_dir.path()).unwrap();
+        let target_dirs: Vec<std::path::PathBuf> = target_dirs
+            .iter()
+            .map(|target_dir| canonicalize_dir(target_dir.path()).unwrap())
+            .collect();
+
+        let dir_diff = vec![
+            DirDiff::Similar(
+                std::path::PathBuf::from("dir/file"),
+                Some(DirDiffFileContent::Unchanged),
+            ),
+            DirDiff::Added(std::path::PathBuf::from("added")),
+        ];
+
+        let mut out = Vec::new();
+        print_dir_diff(
+            &mut out,
+            &dir_diff,
+            false,
+            false,
+            ColorTheme::Default,
+            Some((&source_dir, &target_dirs)),
+            &std::collections::HashMap::new(),
+        )
+        .unwrap();
+
+        // the target path is in the target directory that has the file
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                " dir/file ({} <-> {})\n+ added\n",
+                source_dir.join("dir/file").display(),
+                target_dirs[1].join("dir/file").display()
+            )
+        );
+    }
+
+    #[test]
+    fn walk_stops_past_the_maximum_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        write_tree(dir.path(), &[("a", ""), ("b/c", ""), ("b/d", "")]);
+
+        let list = |max_entries: usize| {
+            get_dir_listing(
+                dir.path(),
+                &walk_options(),
+                &[],
+                Some(max_entries),
+                &mut |e| panic!("{}", e),
+            )
+        };
+
+        assert_eq!(list(4).unwrap().len(), 4);
+
+        match list(3) {
+            Err(e @ DirDiffError::TooManyEntries { .. }) => assert_eq!(
+                e.to_string(),
+                format!(
+                    "{} has more than 3 entries (see --max-entries)",
+                    dir.path().display()
+                )
+            ),
+            other => panic!("expected TooManyEntries, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn one_file_system_does_not_enter_mount_points() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        write_tree(dir.path(), &[("local/file", "")]);
+
+        let device = |path: &std::path::Path| std::fs::metadata(path).map(|m| m.dev()).ok();
+
+        // a symlink to a non-empty directory of another file system stands for a mount point
+        let other_file_system = ["/dev/pts", "/dev", "/proc/self"].into_iter().find(|path| {
+            let path = std::path::Path::new(path);
+
+            device(path).is_some_and(|d| Some(d) != device(dir.path()))
+                && std::fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_some())
+        });
+
+        let Some(other_file_system) = other_file_system else {
+            // every directory is on the same file system here
+            return;
+        };
+
+        std::os::unix::fs::symlink(other_file_system, dir.path().join("mount")).unwrap();
+
+        let walk_options = WalkOptions {
+            one_file_system: true,
+            ..walk_options()
+        };
+
+        let listing = get_dir_listing(dir.path(), &walk_options, &[], None, &mut |e| {
+            panic!("{}", e)
+        })
+        .unwrap();
+
+        // the mount point itself is listed, not its content
+        assert_eq!(
+            listing,
+            ["local", "local/file", "mount"].map(std::path::PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn strip_components_aligns_differently_named_roots() {
+        let source_root = tempfile::tempdir().unwrap();
+        let target_root = tempfile::tempdir().unwrap();
+
+        write_tree(source_root.path(), &[("v1/same", "x"), ("v1/old", "")]);
+        write_tree(target_root.path(), &[("v2/same", "x"), ("v2/new", "")]);
+
+        let source_dir = strip_dir_components(source_root.path(), 1).unwrap();
+        let target_dir = strip_dir_components(target_root.path(), 1).unwrap();
+
+        assert_eq!(source_dir, source_root.path().join("v1"));
+        assert_eq!(target_dir, target_root.path().join("v2"));
+
+        let list = |dir: &std::path::Path| {
+            let mut listing =
+                get_dir_listing(dir, &walk_options(), &[], None, &mut |e| panic!("{}", e)).unwrap();
+            sort_dir_listing(&mut listing, PathCase::Sensitive);
+            listing
+        };
+
+        let dir_diff = get_dir_diff(
+            &list(&source_dir),
+            &list(&target_dir),
+            &source_dir,
+            std::slice::from_ref(&target_dir),
+            &diff_options(1),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(
+            statuses(&dir_diff),
+            ["added new", "removed old", "unchanged same"]
+        );
+
+        // a level with several entries can't be stripped
+        write_tree(source_root.path(), &[("README", "")]);
+
+        assert!(matches!(
+            strip_dir_components(source_root.path(), 1),
+            Err(DirDiffError::CannotStrip(_))
+        ));
+    }
+
+    #[test]
+    fn report_identical_only_keeps_identical_paths() {
+        let dir_diff = vec![
+            DirDiff::Removed(std::path::PathBuf::from("removed")),
+            DirDiff::Added(std::path::PathBuf::from("added")),
+            DirDiff::Similar(std::path::PathBuf::from("dir"), None),
+            DirDiff::Similar(
+                std::path::PathBuf::from("dir/changed"),
+                Some(DirDiffFileContent::Changed),
+            ),
+            DirDiff::Similar(
+                std::path::PathBuf::from("dir/same"),
+                Some(DirDiffFileContent::Unchanged),
+            ),
+            DirDiff::Similar(
+                std::path::PathBuf::from("fifo"),
+                Some(DirDiffFileContent::Special),
+            ),
+            DirDiff::Renamed(
+                std::path::PathBuf::from("from"),
+                std::path::PathBuf::from("to"),
+            ),
+        ];
+
+        let identical_paths = keep_identical_paths(dir_diff);
+
+        assert_eq!(
+            statuses(&identical_paths),
+            ["similar dir", "unchanged dir/same"]
+        );
+
+        // the JSON output has the same entries
+        let mut out = Vec::new();
+        json::print_json(
+            &mut out,
+            &identical_paths,
+            false,
+            &hash::FileHashes::new(),
+            &std::collections::HashMap::new(),
+        )
+        .unwrap();
+
+        let output: serde_json::Value = serde_json::from_slice(&out).unwrap();
+
+        assert_eq!(
+            output["entries"],
+            serde_json::json!([
+                {"path": "dir", "status": "similar"},
+                {"path": "dir/same", "status": "unchanged"},
+            ])
+        );
+    }
+
+    #[test]
+    fn directories_are_expanded_with_the_environment() {
+        let home = std::path::PathBuf::from(std::env::var("HOME").unwrap());
+
+        let args = CliArgs::try_parse_from([
+            "dirdiff",
+            "$HOME/source",
+            "~/target",
+            "--relative-to",
+            "${HOME}",
+        ])
+        .unwrap();
+        let args = expand_cli_args(&args).unwrap();
+
+        assert_eq!(args.source_dir, Some(home.join("source")));
+        assert_eq!(args.target_dirs, [home.join("target")]);
+        assert_eq!(args.relative_to, Some(home));
+
+        // an unset variable is an error, not an empty string
+        assert!(matches!(
+            expand_path(std::path::Path::new("$DIRDIFF_TEST_UNSET_VARIABLE/dir")),
+            Err(DirDiffError::Expansion { .. })
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_targets_are_compared_as_content() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        let symlink = |dir: &tempfile::TempDir, path: &str, link_target: &str| {
+            std::os::unix::fs::symlink(link_target, dir.path().join(path)).unwrap()
+        };
+
+        // the files pointed to don't need to exist, they are never read
+        symlink(&source_dir, "different", "a.txt");
+        symlink(&target_dir, "different", "b.txt");
+        symlink(&source_dir, "same", "../shared/file");
+        symlink(&target_dir, "same", "../shared/file");
+
+        let listing = ["different", "same"].map(std::path::PathBuf::from);
+
+        let options = DiffOptions {
+            comparator: Box::new(UnreadComparator),
+            compare_symlink_targets: true,
+            ..diff_options(1)
+        };
+
+        let dir_diff = get_dir_diff(
+            &listing,
+            &listing,
+            source_dir.path(),
+            &[target_dir.path().to_path_buf()],
+            &options,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(statuses(&dir_diff), ["changed different", "unchanged same"]);
+    }
+
+    #[test]
+    fn passes_transform_the_diff_in_order() {
+        let dir_diff = vec![
+            DirDiff::Removed(std::path::PathBuf::from("removed")),
+            DirDiff::Similar(std::path::PathBuf::from("dir"), None),
+            DirDiff::Similar(
+                std::path::PathBuf::from("dir/same"),
+                Some(DirDiffFileContent::Unchanged),
+            ),
+            DirDiff::Added(std::path::PathBuf::from("added")),
+        ];
+
+        let passes: Vec<DiffPass> = vec![
+            Box::new(|dir_diff: Vec<DirDiff<std::path::PathBuf>>| {
+                dir_diff
+                    .into_iter()
+                    .filter(|diff_fragment| !matches!(diff_fragment, DirDiff::Similar(..)))
+                    .collect()
+            }),
+            Box::new(natural_sort),
+        ];
+
+        assert_eq!(
+            statuses(&apply_passes(dir_diff, &passes)),
+            ["added added", "removed removed"]
+        );
+    }
+
+    #[test]
+    fn natural_sort_puts_file2_before_file10() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        let files = [("dir10/file", ""), ("dir2/file10", ""), ("dir2/file2", "")];
+        let source_listing = write_tree(source_dir.path(), &files);
+        let target_listing = write_tree(target_dir.path(), &files);
+
+        let dir_diff = get_dir_diff(
+            &source_listing,
+            &target_listing,
+            source_dir.path(),
+            &[target_dir.path().to_path_buf()],
+            &diff_options(1),
+            None,
+        )
+        .unwrap();
+
+        // the merge order is the byte order
+        assert_eq!(
+            statuses(&dir_diff),
+            [
+                "similar dir10",
+                "unchanged dir10/file",
+                "similar dir2",
+                "unchanged dir2/file10",
+                "unchanged dir2/file2"
+            ]
+        );
+
+        // directories stay before their content
+        assert_eq!(
+            statuses(&natural_sort(dir_diff)),
+            [
+                "similar dir2",
+                "unchanged dir2/file2",
+                "unchanged dir2/file10",
+                "similar dir10",
+                "unchanged dir10/file"
+            ]
+        );
+    }
+
+    #[test]
+    fn list_formats_prints_every_format() {
+        let mut out = Vec::new();
+        print_formats(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        let listed: Vec<(&str, &str)> = out
+            .lines()
+            .map(|line| line.split_once(' ').unwrap())
+            .collect();
+
+        let names: Vec<&str> = listed.iter().map(|(name, _)| *name).collect();
+
+        assert_eq!(
+            names,
+            [
+                "text",
+                "patch",
+                "json",
+                "github",
+                "diffstat",
+                "json-tree",
+                "junit",
+                "tree",
+                "changelog",
+                "binary"
+            ]
+        );
+        assert_eq!(
+            names.len(),
+            <OutputFormat as clap::ValueEnum>::value_variants().len()
+        );
+
+        for (name, description) in listed {
+            assert!(!description.trim().is_empty(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn buffer_sizes_do_not_change_the_results() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        // larger than the buffers, and not a multiple of their size
+        let content: String = (0..1024 * 1024 + 7)
+            .map(|index| char::from(b'a' + (index % 26) as u8))
+            .collect();
+        let changed_at = |index: usize| {
+            let mut changed_content = content.clone();
+            changed_content.replace_range(index..index + 1, "#");
+            changed_content
+        };
+
+        let source_listing = write_tree(
+            source_dir.path(),
+            &[
+                ("last", &content),
+                ("middle", &content),
+                ("same", &content),
+                ("small", "small"),
+            ],
+        );
+        let target_listing = write_tree(
+            target_dir.path(),
+            &[
+                ("last", &changed_at(content.len() - 1)),
+                ("middle", &changed_at(content.len() / 2)),
+                ("same", &content),
+                ("small", "small"),
+            ],
+        );
+
+        let results = |buffer_size| {
+            let options = DiffOptions {
+                comparator: Box::new(compare::ByteComparator { buffer_size }),
+                ..diff_options(1)
+            };
+
+            let dir_diff = get_dir_diff(
+                &source_listing,
+                &target_listing,
+                source_dir.path(),
+                &[target_dir.path().to_path_buf()],
+                &options,
+                None,
+            )
+            .unwrap();
+
+            let mut hashes = Vec::new();
+            hash::print_manifest(&mut hashes, &source_listing, source_dir.path(), buffer_size)
+                .unwrap();
+
+            (statuses(&dir_diff), String::from_utf8(hashes).unwrap())
+        };
+
+        let (statuses, hashes) = results(MIN_BUFFER_SIZE);
+
+        assert_eq!(
+            statuses,
+            [
+                "changed last",
+                "changed middle",
+                "unchanged same",
+                "unchanged small"
+            ]
+        );
+        assert_eq!(results(4 * 1024 * 1024), (statuses, hashes));
+
+        assert!(parse_buffer_size("1M").is_ok());
+        assert!(parse_buffer_size(&(MIN_BUFFER_SIZE - 1).to_string()).is_err());
+    }
+
+    #[test]
+    fn files_created_at_different_times_are_changed_with_btime() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        let source_listing = write_tree(source_dir.path(), &[("file", "same")]);
+
+        // creation times depend on the platform and the file system
+        if std::fs::metadata(source_dir.path().join("file"))
+            .and_then(|metadata| metadata.created())
+            .is_err()
+        {
+            return;
+        }
+
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        let target_listing = write_tree(target_dir.path(), &[("file", "same")]);
+
+        let target_dirs = [target_dir.path().to_path_buf()];
+
+        let diff = |compare_creation_times| {
+            let options = DiffOptions {
+                compare_creation_times,
+                ..diff_options(1)
+            };
+
+            get_dir_diff(
+                &source_listing,
+                &target_listing,
+                source_dir.path(),
+                &target_dirs,
+                &options,
+                None,
+            )
+            .unwrap()
+        };
+
+        assert_eq!(statuses(&diff(false)), ["unchanged file"]);
+
+        let dir_diff = diff(true);
+
+        assert_eq!(statuses(&dir_diff), ["changed file"]);
+        // the note tells why the same content is changed
+        assert_eq!(
+            compare::find_creation_time_changes(&dir_diff, source_dir.path(), &target_dirs),
+            std::collections::HashSet::from([std::path::PathBuf::from("file")])
+        );
+    }
+
+    #[test]
+    fn dir_summary_counts_the_differences_of_each_top_level_directory() {
+        let dir_diff = vec![
+            DirDiff::Removed("README".into()),
+            DirDiff::Similar("docs".into(), None),
+            DirDiff::Added("docs/guide.md".into()),
+            DirDiff::Added("docs/api/index.md".into()),
+            DirDiff::Similar("docs/intro.md".into(), Some(DirDiffFileContent::Changed)),
+            DirDiff::Similar("src".into(), None),
+            DirDiff::Removed("src/old.rs".into()),
+            DirDiff::Similar("src/lib.rs".into(), Some(DirDiffFileContent::Changed)),
+            DirDiff::Similar("src/main.rs".into(), Some(DirDiffFileContent::Unchanged)),
+            DirDiff::Similar("tests".into(), None),
+            DirDiff::Similar("tests/cli.rs".into(), Some(DirDiffFileContent::Unchanged)),
+        ];
+
+        let mut out = Vec::new();
+        print_dir_summary(&mut out, &dir_diff).unwrap();
+
+        // tests has no differences
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "directory  removed  added  changed\n\
+             .                1      0        0\n\
+             docs             0      2        1\n\
+             src              1      0        1\n"
+        );
+    }
+
+    #[test]
+    fn dir_content_flags_directories_that_gained_a_child_at_depth_1() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        write_tree(source_dir.path(), &[("grown/a", "a"), ("same/a", "a")]);
+        write_tree(
+            target_dir.path(),
+            &[("grown/a", "a"), ("grown/b", "b"), ("same/a", "a")],
+        );
+
+        let options = WalkOptions {
+            depth: Some(1),
+            ..walk_options()
+        };
+
+        let list = |dir: &std::path::Path| {
+            get_dir_listing(dir, &options, &[], None, &mut |e| panic!("{}", e)).unwrap()
+        };
+
+        let source_listing = list(source_dir.path());
+        let target_listing = list(target_dir.path());
+
+        // the children are not listed at depth 1
+        assert_eq!(
+            target_listing,
+            ["grown", "same"].map(std::path::PathBuf::from)
+        );
+
+        let diff = |compare_dir_children| {
+            let options = DiffOptions {
+                compare_dir_children,
+                ..diff_options(1)
+            };
+
+            statuses(
+                &get_dir_diff(
+                    &source_listing,
+                    &target_listing,
+                    source_dir.path(),
+                    &[target_dir.path().to_path_buf()],
+                    &options,
+                    None,
+                )
+                .unwrap(),
+            )
+        };
+
+        assert_eq!(diff(false), ["similar grown", "similar same"]);
+        assert_eq!(diff(true), ["changed grown", "similar same"]);
+    }
+
+    #[test]
+    fn locale_sort_puts_accented_names_next_to_unaccented_ones() {
+        let unchanged =
+            |path: &str| DirDiff::Similar(path.into(), Some(DirDiffFileContent::Unchanged));
+
+        // in byte order, as the merge produces them
+        let dir_diff = vec![
+            unchanged("Zebra"),
+            unchanged("apple"),
+            unchanged("eclair"),
+            DirDiff::Similar("f\u{e9}te".into(), None),
+            unchanged("f\u{e9}te/b"),
+            unchanged("f\u{e9}te/\u{c9}t\u{e9}"),
+            unchanged("zoo"),
+            unchanged("\u{e9}clair"),
+        ];
+
+        let byte_order = statuses(&dir_diff);
+        let locale_order = statuses(&locale_sort(dir_diff));
+
+        assert_eq!(
+            locale_order,
+            [
+                "unchanged apple",
+                "unchanged eclair",
+                "unchanged \u{e9}clair",
+                "similar f\u{e9}te",
+                "unchanged f\u{e9}te/b",
+                "unchanged f\u{e9}te/\u{c9}t\u{e9}",
+                "unchanged Zebra",
+                "unchanged zoo",
+            ]
+        );
+        assert_ne!(locale_order, byte_order);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn posix_paths_are_printed_with_forward_slashes() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        let source_listing = write_tree(source_dir.path(), &[("dir/sub/removed", "")]);
+        let target_listing = write_tree(target_dir.path(), &[("dir/sub/added", "")]);
+
+        let dir_diff = get_dir_diff(
+            &source_listing,
+            &target_listing,
+            source_dir.path(),
+            &[target_dir.path().to_path_buf()],
+            &diff_options(1),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(statuses(&dir_diff)[2], "added dir\\sub\\added");
+
+        assert_eq!(
+            statuses(&to_posix_paths(dir_diff)),
+            [
+                "similar dir",
+                "similar dir/sub",
+                "added dir/sub/added",
+                "removed dir/sub/removed"
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn posix_paths_keep_backslashes_in_names() {
+        assert_eq!(
+            posix_path("dir/back\\slash".as_ref()),
+            std::path::PathBuf::from("dir/back\\slash")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unreadable_target_paths_are_inaccessible_not_removed() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        let files = [("dir/child", "x"), ("file", "x")];
+        let source_listing = write_tree(source_dir.path(), &files);
+        write_tree(target_dir.path(), &files);
+
+        let set_mode = |path: &str, mode: u32| {
+            std::fs::set_permissions(
+                target_dir.path().join(path),
+                std::fs::Permissions::from_mode(mode),
+            )
+            .unwrap()
+        };
+
+        set_mode("file", 0o000);
+        set_mode("dir", 0o000);
+
+        // root can read anything, so there is nothing to test
+        if std::fs::read(target_dir.path().join("file")).is_ok() {
+            set_mode("dir", 0o755);
+            return;
+        }
+
+        let target_listing =
+            get_dir_listing(target_dir.path(), &walk_options(), &[], None, &mut |_| {}).unwrap();
+
+        let dir_diff = get_dir_diff(
+            &source_listing,
+            &target_listing,
+            source_dir.path(),
+            &[target_dir.path().to_path_buf()],
+            &diff_options(1),
+            None,
+        );
+
+        // let the temporary directory be removed
+        set_mode("dir", 0o755);
+
+        assert_eq!(
+            statuses(&dir_diff.unwrap()),
+            ["similar dir", "inaccessible dir/child", "inaccessible file"]
+        );
+    }
+
+    #[test]
+    fn repeat_computes_the_diff_the_asked_number_of_times() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        let source_listing = write_tree(source_dir.path(), &[("a", "1"), ("b", "b"), ("c", "c")]);
+        let target_listing = write_tree(target_dir.path(), &[("a", "2"), ("b", "b"), ("d", "d")]);
+
+        let options = diff_options(4);
+
+        // the statuses of every computed diff
+        let runs = std::cell::RefCell::new(Vec::new());
+
+        let compute_dir_diff = || {
+            let dir_diff = get_dir_diff(
+                &source_listing,
+                &target_listing,
+                source_dir.path(),
+                &[target_dir.path().to_path_buf()],
+                &options,
+                None,
+            )?;
+
+            runs.borrow_mut().push(statuses(&dir_diff));
+
+            Ok(dir_diff)
+        };
+
+        let dir_diff = compute_dir_diff().unwrap();
+
+        repeat_dir_diff(
+            &compute_dir_diff,
+            &dir_diff,
+            5,
+            std::time::Duration::from_millis(1),
+        )
+        .unwrap();
+
+        let runs = runs.into_inner();
+
+        assert_eq!(runs.len(), 5);
+        for run in &runs {
+            assert_eq!(run, &["changed a", "unchanged b", "removed c", "added d"]);
+        }
+    }
+
+    #[test]
+    fn rewritten_source_paths_align_with_the_target() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        let source_listing = write_tree(
+            source_dir.path(),
+            &[("src/a.rs", "a"), ("src/sub/b.rs", "b"), ("srcs", "")],
+        );
+        let target_listing = write_tree(
+            target_dir.path(),
+            &[("lib/a.rs", "a"), ("lib/sub/b.rs", "changed"), ("srcs", "")],
+        );
+
+        let rewrites = [parse_path_rewrite("^src(/|$)=lib$1").unwrap()];
+
+        let (mut source_listing, source_paths) =
+            rewrite_dir_listing(source_listing, &rewrites).unwrap();
+        sort_dir_listing(&mut source_listing, PathCase::Sensitive);
+
+        // only the paths under src are rewritten
+        assert_eq!(
+            source_paths.get(std::path::Path::new("lib/sub/b.rs")),
+            Some(&std::path::PathBuf::from("src/sub/b.rs"))
+        );
+        assert!(!source_paths.contains_key(std::path::Path::new("srcs")));
+
+        let options = DiffOptions {
+            source_paths,
+            ..diff_options(1)
+        };
+
+        let dir_diff = get_dir_diff(
+            &source_listing,
+            &target_listing,
+            source_dir.path(),
+            &[target_dir.path().to_path_buf()],
+            &options,
+            None,
+        )
+        .unwrap();
+
+        // the files are compared with their real paths
+        assert_eq!(
+            statuses(&dir_diff),
+            [
+                "similar lib",
+                "unchanged lib/a.rs",
+                "similar lib/sub",
+                "changed lib/sub/b.rs",
+                "unchanged srcs"
+            ]
+        );
+
+        // two paths rewritten to the same one can't be compared
+        assert!(matches!(
+            rewrite_dir_listing(
+                vec!["src".into(), "lib".into()],
+                &[parse_path_rewrite("^src$=lib").unwrap()]
+            ),
+            Err(DirDiffError::RewriteCollision(_))
+        ));
+    }
+
+    #[test]
+    fn count_only_counts_the_paths_of_a_single_side() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        let source_listing = write_tree(
+            source_dir.path(),
+            &[("a", "1"), ("dir/b", ""), ("dir/c", ""), ("old/d", "")],
+        );
+        let target_listing = write_tree(
+            target_dir.path(),
+            &[
+                ("a", "2"),
+                ("dir/b", ""),
+                ("new/e", ""),
+                ("new/f", ""),
+                ("z", ""),
+            ],
+        );
+
+        let counts = DiffCounts::from_dir_diff(
+            &get_dir_diff(
+                &source_listing,
+                &target_listing,
+                source_dir.path(),
+                &[target_dir.path().to_path_buf()],
+                &diff_options(1),
+                None,
+            )
+            .unwrap(),
+        );
+
+        assert_eq!((counts.removed, counts.added), (3, 4));
+        assert_eq!(
+            count_presence_differences(&source_listing, &target_listing, PathCase::Sensitive),
+            (3, 4)
+        );
+
+        // only the listings are used, the files are never looked at
+        drop((source_dir, target_dir));
+
+        assert_eq!(
+            count_presence_differences(&source_listing, &target_listing, PathCase::Sensitive),
+            (3, 4)
+        );
+
+        let listing = |paths: &[&str]| -> Vec<std::path::PathBuf> {
+            paths.iter().map(std::path::PathBuf::from).collect()
+        };
+
+        assert_eq!(
+            count_presence_differences(
+                &listing(&["A", "b"]),
+                &listing(&["a", "B", "c"]),
+                PathCase::Insensitive
+            ),
+            (0, 1)
+        );
+    }
+
+    #[test]
+    fn renamed_entries_are_cyan_and_counted() {
+        colored::control::set_override(true);
+
+        let dir_diff = vec![
+            DirDiff::Added("added".into()),
+            DirDiff::Renamed("old".into(), "new".into()),
+            DirDiff::RenamedChanged("draft".into(), "final".into(), 90),
+            DirDiff::Removed("removed".into()),
+        ];
+
+        let print = |color: bool| {
+            let mut out = Vec::new();
+
+            print_dir_diff(
+                &mut out,
+                &dir_diff,
+                false,
+                color,
+                ColorTheme::Default,
+                None,
+                &std::collections::HashMap::new(),
+            )
+            .unwrap();
+
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(
+            print(true),
+            "\x1b[32m+\x1b[0m \x1b[32madded\x1b[0m\n\
+             \x1b[36mR\x1b[0m \x1b[36mold -> new\x1b[0m\n\
+             \x1b[36mR\x1b[0m \x1b[36mdraft -> final (90% similar)\x1b[0m\n\
+             \x1b[31m-\x1b[0m \x1b[31mremoved\x1b[0m\n"
+        );
+        assert_eq!(
+            print(false),
+            "+ added\nR old -> new\nR draft -> final (90% similar)\n- removed\n"
+        );
+
+        let colored_summary = summary(&dir_diff, true);
+
+        assert!(
+            colored_summary.contains("\x1b[36m1 renamed, 1 renamed and changed\x1b[0m"),
+            "{}",
+            colored_summary
+        );
+        assert!(summary(&dir_diff, false).contains(", 1 renamed, 1 renamed and changed,"));
+    }
+    #[cfg(unix)]
+    #[test]
+    fn sparse_files_have_a_note_with_their_allocated_sizes() {
+        use std::os::unix::fs::MetadataExt;
+
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        write_tree(source_dir.path(), &[("same", "x")]);
+        write_tree(target_dir.path(), &[("same", "x")]);
+
+        // a hole in the source, the same zeros written out in the target
+        std::fs::File::create(source_dir.path().join("image"))
+            .unwrap()
+            .set_len(1 << 20)
+            .unwrap();
+        std::fs::write(target_dir.path().join("image"), vec![0; 1 << 20]).unwrap();
+
+        let allocated_size = |dir: &tempfile::TempDir| {
+            std::fs::metadata(dir.path().join("image"))
+                .unwrap()
+                .blocks()
+                * 512
+        };
+
+        // the file system has no sparse files, so there is nothing to test
+        if allocated_size(&source_dir) >= allocated_size(&target_dir) {
+            return;
+        }
+
+        let dir_diff = [
+            DirDiff::Similar("image".into(), Some(DirDiffFileContent::Unchanged)),
+            DirDiff::Similar("same".into(), Some(DirDiffFileContent::Unchanged)),
+        ];
+
+        assert_eq!(
+            find_allocation_notes(
+                &dir_diff,
+                source_dir.path(),
+                &[target_dir.path().to_path_buf()]
+            ),
+            [(
+                std::path::PathBuf::from("image"),
+                format!(
+                    "allocated {} -> {} bytes",
+                    allocated_size(&source_dir),
+                    allocated_size(&target_dir)
+                )
+            )]
+        );
+    }
 }
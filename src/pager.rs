@@ -0,0 +1,108 @@
+use std::io::Write;
+
+pub struct PagerInput(std::process::ChildStdin); // the input of the pager process
+
+impl Write for PagerInput {
+    // Once the user quits the pager, the rest of the output is dropped instead of failing
+
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.0.write(buf) {
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(buf.len()),
+            result => result,
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.0.flush() {
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+            result => result,
+        }
+    }
+}
+
+pub fn spawn_pager() -> Option<(std::process::Child, PagerInput)> {
+    // Start $PAGER (or less), like git does, and return it with its input. Return None if there
+    // is no pager to start, so the output goes to stdout
+
+    start_pager(&std::env::var("PAGER").unwrap_or_else(|_| String::from("less")))
+}
+
+fn start_pager(pager_command: &str) -> Option<(std::process::Child, PagerInput)> {
+    if pager_command.trim().is_empty() || pager_command.trim() == "cat" {
+        return None;
+    }
+
+    // $PAGER can have arguments, so let the shell parse it
+    #[cfg(unix)]
+    let mut command = {
+        let mut command = std::process::Command::new("sh");
+        command.arg("-c").arg(pager_command);
+        command
+    };
+
+    #[cfg(not(unix))]
+    let mut command = {
+        let mut words = pager_command.split_whitespace();
+        let mut command = std::process::Command::new(words.next()?);
+        command.args(words);
+        command
+    };
+
+    // quit if the output fits on the screen, keep the colors and don't clear the screen
+    if std::env::var_os("LESS").is_none() {
+        command.env("LESS", "FRX");
+    }
+
+    let mut pager = command
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            eprintln!(
+                "warning: unable to start the pager {}: {}",
+                pager_command, e
+            )
+        })
+        .ok()?;
+
+    let pager_input = PagerInput(pager.stdin.take()?);
+
+    Some((pager, pager_input))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn pages_the_output_through_the_pager_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let paged_path = dir.path().join("paged");
+
+        // a pager that saves what it is given, and the LESS it is run with
+        let (mut pager, mut pager_input) = start_pager(&format!(
+            "(echo \"$LESS\"; cat) > '{}'",
+            paged_path.display()
+        ))
+        .unwrap();
+
+        pager_input
+            .write_all(b"\x1b[31m- removed\x1b[0m\n")
+            .unwrap();
+        drop(pager_input);
+        pager.wait().unwrap();
+
+        let expected_less = std::env::var("LESS").unwrap_or_else(|_| String::from("FRX"));
+
+        assert_eq!(
+            std::fs::read_to_string(&paged_path).unwrap(),
+            format!("{}\n\x1b[31m- removed\x1b[0m\n", expected_less)
+        );
+    }
+
+    #[test]
+    fn cat_and_empty_pagers_are_not_started() {
+        assert!(start_pager("cat").is_none());
+        assert!(start_pager(" ").is_none());
+    }
+}
@@ -0,0 +1,660 @@
+use std::io::Write;
+
+use crate::{find_target_dir, DirDiff, DirDiffError, DirDiffFileContent};
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum DiffAlgorithm {
+    Myers,    // the shortest diff, like diff and git by default
+    Patience, // matches unique lines first, cleaner hunks for moved blocks of code
+    Lcs,      // the longest common subsequence, slower on big files
+}
+
+fn text_diff<'a>(
+    source_content: &'a str,
+    target_content: &'a str,
+    algorithm: DiffAlgorithm,
+) -> similar::TextDiff<'a, 'a, 'a, str> {
+    let algorithm = match algorithm {
+        DiffAlgorithm::Myers => similar::Algorithm::Myers,
+        DiffAlgorithm::Patience => similar::Algorithm::Patience,
+        DiffAlgorithm::Lcs => similar::Algorithm::Lcs,
+    };
+
+    similar::TextDiff::configure()
+        .algorithm(algorithm)
+        .diff_lines(source_content, target_content)
+}
+
+pub fn read_text_file(path: &std::path::Path) -> Result<Option<String>, DirDiffError> {
+    // Return the content of a file, or None if it is binary (not UTF-8 or contains a NUL byte)
+
+    let content = std::fs::read(path).map_err(|source| DirDiffError::Read {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    if content.contains(&0) {
+        return Ok(None);
+    }
+
+    Ok(String::from_utf8(content).ok())
+}
+
+pub fn print_file_patch(
+    out: &mut impl Write,
+    (source_path, target_path): (&std::path::Path, &std::path::Path),
+    source_file_path: Option<&std::path::Path>,
+    target_file_path: Option<&std::path::Path>,
+    context: usize,
+    algorithm: DiffAlgorithm,
+) -> Result<(), DirDiffError> {
+    // Print the unified diff that turns the source file into the target file. The paths are the
+    // relative ones shown in the header, they only differ for renamed files. A missing file is
+    // diffed as if it were empty. context is the number of unchanged lines around each hunk
+
+    let source_name = match source_file_path {
+        Some(_) => format!("a/{}", source_path.display()),
+        None => String::from("/dev/null"),
+    };
+
+    let target_name = match target_file_path {
+        Some(_) => format!("b/{}", target_path.display()),
+        None => String::from("/dev/null"),
+    };
+
+    let source_content = match source_file_path {
+        Some(p) => read_text_file(p)?,
+        None => Some(String::new()),
+    };
+
+    let target_content = match target_file_path {
+        Some(p) => read_text_file(p)?,
+        None => Some(String::new()),
+    };
+
+    match (source_content, target_content) {
+        (Some(source_content), Some(target_content)) => {
+            write!(
+                out,
+                "{}",
+                text_diff(&source_content, &target_content, algorithm)
+                    .unified_diff()
+                    .context_radius(context)
+                    .header(&source_name, &target_name)
+            )?;
+        }
+        _ => {
+            // don't inline binary content
+            writeln!(
+                out,
+                "Binary files {} and {} differ",
+                source_name, target_name
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+fn file_mode(path: &std::path::Path) -> Result<&'static str, DirDiffError> {
+    // Return the git mode of a file: executable if any execute bit is set, regular otherwise.
+    // Platforms without execute bits only have regular files
+
+    let metadata = std::fs::metadata(path).map_err(|source| DirDiffError::Read {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        if metadata.permissions().mode() & 0o111 != 0 {
+            return Ok("100755");
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = metadata;
+
+    Ok("100644")
+}
+
+fn print_git_header(
+    out: &mut impl Write,
+    from: &std::path::Path,
+    to: &std::path::Path,
+) -> Result<(), DirDiffError> {
+    writeln!(out, "diff --git a/{} b/{}", from.display(), to.display())?;
+
+    Ok(())
+}
+
+pub fn print_patch(
+    out: &mut impl Write,
+    dir_diff: &[DirDiff<std::path::PathBuf>],
+    source_dir: &std::path::Path,
+    target_dirs: &[std::path::PathBuf],
+    context: usize,
+    algorithm: DiffAlgorithm,
+) -> Result<(), DirDiffError> {
+    // Print a git-style patch that transforms the source directory into the target directory,
+    // which `git apply` or `patch -p1` can apply. Like git, directories have no entries of their
+    // own: the files in them have theirs, and a file replaced by a directory (or the other way
+    // around) is a deleted file or a new file
+
+    for diff_fragment in dir_diff {
+        // stop between two files, so a hunk is not cut in the middle
+        crate::interrupt::check()?;
+
+        match diff_fragment {
+            DirDiff::Removed(path) => {
+                let source_path = source_dir.join(path);
+
+                if source_path.is_dir() {
+                    continue;
+                }
+
+                print_git_header(out, path, path)?;
+                writeln!(out, "deleted file mode {}", file_mode(&source_path)?)?;
+                print_file_patch(
+                    out,
+                    (path, path),
+                    Some(&source_path),
+                    None,
+                    context,
+                    algorithm,
+                )?;
+            }
+            DirDiff::Added(path) => {
+                let target_path = find_target_dir(target_dirs, path).join(path);
+
+                if target_path.is_dir() {
+                    continue;
+                }
+
+                print_git_header(out, path, path)?;
+                writeln!(out, "new file mode {}", file_mode(&target_path)?)?;
+                print_file_patch(
+                    out,
+                    (path, path),
+                    None,
+                    Some(&target_path),
+                    context,
+                    algorithm,
+                )?;
+            }
+            DirDiff::Similar(path, Some(DirDiffFileContent::Changed)) => {
+                let source_path = source_dir.join(path);
+                let target_path = find_target_dir(target_dirs, path).join(path);
+
+                match (source_path.is_dir(), target_path.is_dir()) {
+                    // the children of the directory changed, they have their own entries
+                    (true, true) => {}
+                    // a directory was replaced by a file, its children have their own entries
+                    (true, false) => {
+                        print_git_header(out, path, path)?;
+                        writeln!(out, "new file mode {}", file_mode(&target_path)?)?;
+                        print_file_patch(
+                            out,
+                            (path, path),
+                            None,
+                            Some(&target_path),
+                            context,
+                            algorithm,
+                        )?;
+                    }
+                    // a file was replaced by a directory, its children have their own entries
+                    (false, true) => {
+                        print_git_header(out, path, path)?;
+                        writeln!(out, "deleted file mode {}", file_mode(&source_path)?)?;
+                        print_file_patch(
+                            out,
+                            (path, path),
+                            Some(&source_path),
+                            None,
+                            context,
+                            algorithm,
+                        )?;
+                    }
+                    (false, false) => {
+                        print_git_header(out, path, path)?;
+
+                        let source_mode = file_mode(&source_path)?;
+                        let target_mode = file_mode(&target_path)?;
+
+                        if source_mode != target_mode {
+                            writeln!(out, "old mode {}", source_mode)?;
+                            writeln!(out, "new mode {}", target_mode)?;
+                        }
+
+                        print_file_patch(
+                            out,
+                            (path, path),
+                            Some(&source_path),
+                            Some(&target_path),
+                            context,
+                            algorithm,
+                        )?;
+                    }
+                }
+            }
+            DirDiff::Renamed(from, to)
+            | DirDiff::RenamedChanged(from, to, _)
+            | DirDiff::CaseChanged(from, to, _) => {
+                let source_path = source_dir.join(from);
+                let target_path = find_target_dir(target_dirs, to).join(to);
+
+                // only the case of a directory can change, and it has no entry of its own
+                if source_path.is_dir() {
+                    continue;
+                }
+
+                print_git_header(out, from, to)?;
+
+                let changed = match diff_fragment {
+                    DirDiff::RenamedChanged(_, _, similarity) => {
+                        writeln!(out, "similarity index {}%", similarity)?;
+                        true
+                    }
+                    DirDiff::CaseChanged(_, _, content) => {
+                        matches!(content, Some(DirDiffFileContent::Changed))
+                    }
+                    _ => {
+                        writeln!(out, "similarity index 100%")?;
+                        false
+                    }
+                };
+
+                writeln!(out, "rename from {}", from.display())?;
+                writeln!(out, "rename to {}", to.display())?;
+
+                if changed {
+                    print_file_patch(
+                        out,
+                        (from, to),
+                        Some(&source_path),
+                        Some(&target_path),
+                        context,
+                        algorithm,
+                    )?;
+                }
+            }
+            // nothing to change
+            DirDiff::Similar(_, _) => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn count_changed_lines(
+    source_file_path: Option<&std::path::Path>,
+    target_file_path: Option<&std::path::Path>,
+    algorithm: DiffAlgorithm,
+) -> Result<Option<(usize, usize)>, DirDiffError> {
+    // Return the number of inserted and deleted lines between the files, or None if one of them is
+    // binary. A missing file counts as empty
+
+    let source_content = match source_file_path {
+        Some(p) => read_text_file(p)?,
+        None => Some(String::new()),
+    };
+
+    let target_content = match target_file_path {
+        Some(p) => read_text_file(p)?,
+        None => Some(String::new()),
+    };
+
+    let (source_content, target_content) = match (source_content, target_content) {
+        (Some(source_content), Some(target_content)) => (source_content, target_content),
+        _ => return Ok(None),
+    };
+
+    let text_diff = text_diff(&source_content, &target_content, algorithm);
+
+    let mut insertions = 0;
+    let mut deletions = 0;
+
+    for change in text_diff.iter_all_changes() {
+        match change.tag() {
+            similar::ChangeTag::Insert => insertions += 1,
+            similar::ChangeTag::Delete => deletions += 1,
+            similar::ChangeTag::Equal => {}
+        }
+    }
+
+    Ok(Some((insertions, deletions)))
+}
+
+pub fn print_diffstat(
+    out: &mut impl Write,
+    dir_diff: &[DirDiff<std::path::PathBuf>],
+    source_dir: &std::path::Path,
+    target_dirs: &[std::path::PathBuf],
+    algorithm: DiffAlgorithm,
+) -> Result<(), DirDiffError> {
+    // Print the number of inserted and deleted lines of every removed, added, changed or renamed
+    // file, like `git diff --stat`, followed by the totals. Directories have no lines, so they are
+    // left out
+
+    // path and line counts (None for binary files)
+    let mut file_stats = Vec::new();
+
+    for diff_fragment in dir_diff {
+        let (path, source_file_path, target_file_path) = match diff_fragment {
+            DirDiff::Removed(path) => (
+                path.display().to_string(),
+                Some(source_dir.join(path)),
+                None,
+            ),
+            DirDiff::Added(path) => (
+                path.display().to_string(),
+                None,
+                Some(find_target_dir(target_dirs, path).join(path)),
+            ),
+            DirDiff::Similar(path, Some(DirDiffFileContent::Changed)) => (
+                path.display().to_string(),
+                Some(source_dir.join(path)),
+                Some(find_target_dir(target_dirs, path).join(path)),
+            ),
+            // like git, renamed files are shown even without changed lines
+            DirDiff::Renamed(from, to)
+            | DirDiff::RenamedChanged(from, to, _)
+            | DirDiff::CaseChanged(from, to, _) => (
+                format!("{} => {}", from.display(), to.display()),
+                Some(source_dir.join(from)),
+                Some(find_target_dir(target_dirs, to).join(to)),
+            ),
+            DirDiff::Similar(_, _) => continue,
+        };
+
+        if source_file_path
+            .iter()
+            .chain(&target_file_path)
+            .any(|p| p.is_dir())
+        {
+            continue;
+        }
+
+        let line_counts = count_changed_lines(
+            source_file_path.as_deref(),
+            target_file_path.as_deref(),
+            algorithm,
+        )?;
+
+        file_stats.push((path, line_counts));
+    }
+
+    let path_width = file_stats
+        .iter()
+        .map(|(path, _)| path.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    let mut total_insertions = 0;
+    let mut total_deletions = 0;
+
+    for (path, line_counts) in &file_stats {
+        match line_counts {
+            Some((insertions, deletions)) => {
+                writeln!(
+                    out,
+                    " {:width$} | +{} -{}",
+                    path,
+                    insertions,
+                    deletions,
+                    width = path_width
+                )?;
+
+                total_insertions += insertions;
+                total_deletions += deletions;
+            }
+            None => writeln!(out, " {:width$} | Bin", path, width = path_width)?,
+        }
+    }
+
+    writeln!(
+        out,
+        " {} files changed, {} insertions(+), {} deletions(-)",
+        file_stats.len(),
+        total_insertions,
+        total_deletions
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_git_hunks_for_removed_added_and_changed_files() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        std::fs::write(source_dir.path().join("changed.txt"), "one\ntwo\n").unwrap();
+        std::fs::write(source_dir.path().join("removed.txt"), "gone\n").unwrap();
+        std::fs::write(target_dir.path().join("added.txt"), "new\n").unwrap();
+        std::fs::write(target_dir.path().join("changed.txt"), "one\nthree\n").unwrap();
+
+        let dir_diff = [
+            DirDiff::Added("added.txt".into()),
+            DirDiff::Similar("changed.txt".into(), Some(DirDiffFileContent::Changed)),
+            DirDiff::Removed("removed.txt".into()),
+        ];
+
+        let mut out = Vec::new();
+
+        print_patch(
+            &mut out,
+            &dir_diff,
+            source_dir.path(),
+            &[target_dir.path().to_path_buf()],
+            3,
+            DiffAlgorithm::Myers,
+        )
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "diff --git a/added.txt b/added.txt\n\
+             new file mode 100644\n\
+             --- /dev/null\n\
+             +++ b/added.txt\n\
+             @@ -0,0 +1 @@\n\
+             +new\n\
+             diff --git a/changed.txt b/changed.txt\n\
+             --- a/changed.txt\n\
+             +++ b/changed.txt\n\
+             @@ -1,2 +1,2 @@\n \
+             one\n\
+             -two\n\
+             +three\n\
+             diff --git a/removed.txt b/removed.txt\n\
+             deleted file mode 100644\n\
+             --- a/removed.txt\n\
+             +++ /dev/null\n\
+             @@ -1 +0,0 @@\n\
+             -gone\n"
+        );
+    }
+
+    #[test]
+    fn hunks_have_the_context_lines_asked_for() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        let source_content: String = (1..=20).map(|i| format!("{}\n", i)).collect();
+        let target_content = source_content.replace("\n10\n", "\nten\n");
+
+        std::fs::write(source_dir.path().join("lines"), source_content).unwrap();
+        std::fs::write(target_dir.path().join("lines"), target_content).unwrap();
+
+        let hunk = |context: usize| {
+            let mut out = Vec::new();
+
+            print_file_patch(
+                &mut out,
+                (std::path::Path::new("lines"), std::path::Path::new("lines")),
+                Some(&source_dir.path().join("lines")),
+                Some(&target_dir.path().join("lines")),
+                context,
+                DiffAlgorithm::Myers,
+            )
+            .unwrap();
+
+            // the lines after the header
+            String::from_utf8(out)
+                .unwrap()
+                .lines()
+                .skip(2)
+                .map(String::from)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(hunk(0), ["@@ -10 +10 @@", "-10", "+ten"]);
+        assert_eq!(
+            hunk(2),
+            ["@@ -8,5 +8,5 @@", " 8", " 9", "-10", "+ten", " 11", " 12"]
+        );
+
+        // 3 lines by default, like diff -u
+        let args = <crate::CliArgs as clap::Parser>::try_parse_from(["dirdiff", "a", "b"]).unwrap();
+        let context_lines = hunk(args.context)
+            .iter()
+            .filter(|line| line.starts_with(' '))
+            .count();
+        assert_eq!(context_lines, 6);
+    }
+
+    #[test]
+    fn diffstat_counts_inserted_and_deleted_lines() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        std::fs::write(source_dir.path().join("text"), "a\nb\nc\nd\n").unwrap();
+        std::fs::write(target_dir.path().join("text"), "a\nB\nc\nd\ne\nf\n").unwrap();
+        std::fs::write(source_dir.path().join("binary"), b"\0a").unwrap();
+        std::fs::write(target_dir.path().join("binary"), b"\0b").unwrap();
+        std::fs::write(source_dir.path().join("removed"), "x\ny\n").unwrap();
+
+        let dir_diff = [
+            DirDiff::Similar("binary".into(), Some(DirDiffFileContent::Changed)),
+            DirDiff::Removed("removed".into()),
+            DirDiff::Similar("text".into(), Some(DirDiffFileContent::Changed)),
+        ];
+
+        let mut out = Vec::new();
+
+        print_diffstat(
+            &mut out,
+            &dir_diff,
+            source_dir.path(),
+            &[target_dir.path().to_path_buf()],
+            DiffAlgorithm::Myers,
+        )
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            " binary  | Bin\n \
+             removed | +0 -2\n \
+             text    | +3 -1\n \
+             3 files changed, 3 insertions(+), 3 deletions(-)\n"
+        );
+    }
+    #[test]
+    fn hunks_follow_the_chosen_algorithm() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        // two blocks swapped, which each algorithm matches differently
+        std::fs::write(
+            source_dir.path().join("blocks"),
+            "one\n{\ntwo\n}\nthree\n{\nfour\n}\n",
+        )
+        .unwrap();
+        std::fs::write(
+            target_dir.path().join("blocks"),
+            "three\n{\nfour\n}\none\n{\ntwo\n}\n",
+        )
+        .unwrap();
+
+        let hunks = |algorithm: DiffAlgorithm| {
+            let mut out = Vec::new();
+
+            print_file_patch(
+                &mut out,
+                (
+                    std::path::Path::new("blocks"),
+                    std::path::Path::new("blocks"),
+                ),
+                Some(&source_dir.path().join("blocks")),
+                Some(&target_dir.path().join("blocks")),
+                0,
+                algorithm,
+            )
+            .unwrap();
+
+            // the lines after the header
+            String::from_utf8(out)
+                .unwrap()
+                .lines()
+                .skip(2)
+                .map(String::from)
+                .collect::<Vec<_>>()
+        };
+
+        // the shortest diff cuts through both blocks
+        assert_eq!(
+            hunks(DiffAlgorithm::Myers),
+            [
+                "@@ -0,0 +1,4 @@",
+                "+three",
+                "+{",
+                "+four",
+                "+}",
+                "@@ -4,4 +7,0 @@",
+                "-}",
+                "-three",
+                "-{",
+                "-four",
+            ]
+        );
+
+        // the moved block is removed and added whole
+        assert_eq!(
+            hunks(DiffAlgorithm::Patience),
+            [
+                "@@ -0,0 +1,4 @@",
+                "+three",
+                "+{",
+                "+four",
+                "+}",
+                "@@ -5,4 +8,0 @@",
+                "-three",
+                "-{",
+                "-four",
+                "-}",
+            ]
+        );
+
+        assert_eq!(
+            hunks(DiffAlgorithm::Lcs),
+            [
+                "@@ -1,4 +0,0 @@",
+                "-one",
+                "-{",
+                "-two",
+                "-}",
+                "@@ -8,0 +5,4 @@",
+                "+one",
+                "+{",
+                "+two",
+                "+}",
+            ]
+        );
+    }
+}
@@ -0,0 +1,399 @@
+use crate::compare::ContentComparator;
+use crate::{find_target_dir, hash, patch, DirDiff, DirDiffError};
+
+fn similarity(source_text: &str, target_text: &str) -> u8 {
+    // Return how similar two texts are, as the percentage of their lines that are in both. Only
+    // identical content is 100% similar
+
+    let ratio = similar::TextDiff::from_lines(source_text, target_text).ratio();
+
+    ((ratio * 100.0) as u8).min(99)
+}
+
+pub fn detect_renames(
+    dir_diff: Vec<DirDiff<std::path::PathBuf>>,
+    source_dir: &std::path::Path,
+    target_dirs: &[std::path::PathBuf],
+    threshold: u8,
+    buffer_size: usize,
+) -> Result<Vec<DirDiff<std::path::PathBuf>>, DirDiffError> {
+    // Pair the removed files with the added files they were moved to, like git does. A removed
+    // file with the same content as an added one is renamed, otherwise it is renamed and changed
+    // with the most similar added text file, if they are at least threshold percent similar. The
+    // renamed file replaces the removed one in the diff, and the added one is dropped
+
+    let read_error = |path: &std::path::Path| {
+        let path = path.to_path_buf();
+        move |source| DirDiffError::Read { path, source }
+    };
+
+    // index in the diff and absolute path of the files only on one side
+    let mut removed_files = Vec::new();
+    let mut added_files = Vec::new();
+
+    for (index, diff_fragment) in dir_diff.iter().enumerate() {
+        match diff_fragment {
+            DirDiff::Removed(path) if source_dir.join(path).is_file() => {
+                removed_files.push((index, source_dir.join(path)));
+            }
+            DirDiff::Added(path) if find_target_dir(target_dirs, path).join(path).is_file() => {
+                added_files.push((index, find_target_dir(target_dirs, path).join(path)));
+            }
+            _ => {}
+        }
+    }
+
+    // index of the added file and similarity (None if identical), by index of the removed file
+    let mut renames = std::collections::HashMap::new();
+    let mut renamed_added_indexes = std::collections::HashSet::new();
+
+    if !removed_files.is_empty() && !added_files.is_empty() {
+        // exact renames first, so a changed copy doesn't take the place of an identical one
+        let mut added_hashes = Vec::new();
+
+        for (index, file_path) in &added_files {
+            added_hashes.push((
+                *index,
+                hash::hash_file(file_path, buffer_size).map_err(read_error(file_path))?,
+            ));
+        }
+
+        for (removed_index, file_path) in &removed_files {
+            crate::interrupt::check()?;
+
+            let file_hash =
+                hash::hash_file(file_path, buffer_size).map_err(read_error(file_path))?;
+
+            if let Some((added_index, _)) = added_hashes.iter().find(|(added_index, added_hash)| {
+                *added_hash == file_hash && !renamed_added_indexes.contains(added_index)
+            }) {
+                renames.insert(*removed_index, (*added_index, None));
+                renamed_added_indexes.insert(*added_index);
+            }
+        }
+
+        // binary files can only be renamed without changes
+        let mut added_texts = Vec::new();
+
+        for (index, file_path) in &added_files {
+            if !renamed_added_indexes.contains(index) {
+                if let Some(text) = patch::read_text_file(file_path)? {
+                    added_texts.push((*index, text));
+                }
+            }
+        }
+
+        for (removed_index, file_path) in &removed_files {
+            crate::interrupt::check()?;
+
+            if renames.contains_key(removed_index) {
+                continue;
+            }
+
+            let text = match patch::read_text_file(file_path)? {
+                Some(text) => text,
+                None => continue,
+            };
+
+            // the first of the most similar added files
+            let mut best_match: Option<(usize, u8)> = None;
+
+            for (added_index, added_text) in &added_texts {
+                if renamed_added_indexes.contains(added_index) {
+                    continue;
+                }
+
+                let file_similarity = similarity(&text, added_text);
+
+                if file_similarity >= threshold
+                    && best_match
+                        .is_none_or(|(_, best_similarity)| file_similarity > best_similarity)
+                {
+                    best_match = Some((*added_index, file_similarity));
+                }
+            }
+
+            if let Some((added_index, file_similarity)) = best_match {
+                renames.insert(*removed_index, (added_index, Some(file_similarity)));
+                renamed_added_indexes.insert(added_index);
+            }
+        }
+    }
+
+    let added_paths: std::collections::HashMap<usize, std::path::PathBuf> = dir_diff
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| renamed_added_indexes.contains(index))
+        .map(|(index, diff_fragment)| (index, diff_fragment.path().clone()))
+        .collect();
+
+    Ok(dir_diff
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| !renamed_added_indexes.contains(index))
+        .map(
+            |(index, diff_fragment)| match (renames.get(&index), diff_fragment) {
+                (Some((added_index, None)), DirDiff::Removed(path)) => {
+                    DirDiff::Renamed(path, added_paths[added_index].clone())
+                }
+                (Some((added_index, Some(file_similarity))), DirDiff::Removed(path)) => {
+                    DirDiff::RenamedChanged(
+                        path,
+                        added_paths[added_index].clone(),
+                        *file_similarity,
+                    )
+                }
+                (_, diff_fragment) => diff_fragment,
+            },
+        )
+        .collect())
+}
+
+pub fn detect_case_renames(
+    dir_diff: Vec<DirDiff<std::path::PathBuf>>,
+    source_dir: &std::path::Path,
+    target_dirs: &[std::path::PathBuf],
+    comparator: Option<&dyn ContentComparator>,
+) -> Result<Vec<DirDiff<std::path::PathBuf>>, DirDiffError> {
+    // Pair the removed paths with the added paths that only differ from them in case. The pair
+    // replaces the removed path in the diff, and the added one is dropped. With a comparator,
+    // the content of the files is compared too
+
+    // index in the diff of the added paths, by case-folded path
+    let mut added_indexes: std::collections::HashMap<String, Vec<usize>> =
+        std::collections::HashMap::new();
+
+    for (index, diff_fragment) in dir_diff.iter().enumerate() {
+        if let DirDiff::Added(path) = diff_fragment {
+            added_indexes
+                .entry(path.to_string_lossy().to_lowercase())
+                .or_default()
+                .push(index);
+        }
+    }
+
+    // index of the added path, by index of the removed path
+    let mut case_renames = std::collections::HashMap::new();
+    let mut renamed_added_indexes = std::collections::HashSet::new();
+
+    for (index, diff_fragment) in dir_diff.iter().enumerate() {
+        if let DirDiff::Removed(path) = diff_fragment {
+            let candidates = match added_indexes.get(&path.to_string_lossy().to_lowercase()) {
+                Some(candidates) => candidates,
+                None => continue,
+            };
+
+            if let Some(added_index) = candidates
+                .iter()
+                .find(|added_index| !renamed_added_indexes.contains(*added_index))
+            {
+                case_renames.insert(index, *added_index);
+                renamed_added_indexes.insert(*added_index);
+            }
+        }
+    }
+
+    let added_paths: std::collections::HashMap<usize, std::path::PathBuf> = dir_diff
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| renamed_added_indexes.contains(index))
+        .map(|(index, diff_fragment)| (index, diff_fragment.path().clone()))
+        .collect();
+
+    let mut case_changed_diff = Vec::with_capacity(dir_diff.len() - renamed_added_indexes.len());
+
+    for (index, diff_fragment) in dir_diff.into_iter().enumerate() {
+        if renamed_added_indexes.contains(&index) {
+            continue;
+        }
+
+        let (from, to) = match (case_renames.get(&index), diff_fragment) {
+            (Some(added_index), DirDiff::Removed(from)) => (from, added_paths[added_index].clone()),
+            (_, diff_fragment) => {
+                case_changed_diff.push(diff_fragment);
+                continue;
+            }
+        };
+
+        let source_file_path = source_dir.join(&from);
+        let target_file_path = find_target_dir(target_dirs, &to).join(&to);
+
+        // only files have a content to compare
+        let file_content_similarity = match comparator {
+            Some(comparator) if source_file_path.is_file() && target_file_path.is_file() => {
+                crate::interrupt::check()?;
+
+                Some(
+                    comparator
+                        .compare(&source_file_path, &target_file_path)
+                        .map_err(|source| DirDiffError::Compare {
+                            path: from.clone(),
+                            source,
+                        })?,
+                )
+            }
+            _ => None,
+        };
+
+        case_changed_diff.push(DirDiff::CaseChanged(from, to, file_content_similarity));
+    }
+
+    Ok(case_changed_diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DirDiffFileContent;
+
+    fn case_renames(
+        dir_diff: Vec<DirDiff<std::path::PathBuf>>,
+        compare_content: bool,
+    ) -> Vec<String> {
+        // Return the diff after case renames were detected, with the entries written like
+        // `CaseChanged("Readme.md", "readme.md", None)`
+
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        std::fs::write(source_dir.path().join("Readme.md"), "readme").unwrap();
+        std::fs::write(target_dir.path().join("readme.md"), "README").unwrap();
+        std::fs::write(source_dir.path().join("LICENSE"), "license").unwrap();
+        std::fs::write(target_dir.path().join("License"), "license").unwrap();
+
+        let comparator = crate::compare::ByteComparator { buffer_size: 4096 };
+
+        detect_case_renames(
+            dir_diff,
+            source_dir.path(),
+            &[target_dir.path().to_path_buf()],
+            compare_content.then_some(&comparator as &dyn ContentComparator),
+        )
+        .unwrap()
+        .iter()
+        .map(|diff_fragment| format!("{:?}", diff_fragment))
+        .collect()
+    }
+
+    #[test]
+    fn pairs_paths_differing_only_in_case() {
+        let dir_diff = vec![
+            DirDiff::Removed("LICENSE".into()),
+            DirDiff::Removed("Readme.md".into()),
+            DirDiff::Removed("other".into()),
+            DirDiff::Added("License".into()),
+            DirDiff::Added("readme.md".into()),
+            DirDiff::Added("readme.txt".into()),
+        ];
+
+        assert_eq!(
+            case_renames(dir_diff, false),
+            [
+                r#"CaseChanged("LICENSE", "License", None)"#,
+                r#"CaseChanged("Readme.md", "readme.md", None)"#,
+                r#"Removed("other")"#,
+                r#"Added("readme.txt")"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn compares_the_content_of_paired_files() {
+        let dir_diff = vec![
+            DirDiff::Removed("LICENSE".into()),
+            DirDiff::Removed("Readme.md".into()),
+            DirDiff::Added("License".into()),
+            DirDiff::Added("readme.md".into()),
+        ];
+
+        assert_eq!(
+            case_renames(dir_diff, true),
+            [
+                format!(
+                    "CaseChanged(\"LICENSE\", \"License\", Some({:?}))",
+                    DirDiffFileContent::Unchanged
+                ),
+                format!(
+                    "CaseChanged(\"Readme.md\", \"readme.md\", Some({:?}))",
+                    DirDiffFileContent::Changed
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_paths_with_the_same_case_alone() {
+        let dir_diff = vec![
+            DirDiff::Removed("Readme.md".into()),
+            DirDiff::Similar("readme.md".into(), None),
+        ];
+
+        assert_eq!(
+            case_renames(dir_diff, false),
+            [r#"Removed("Readme.md")"#, r#"Similar("readme.md", None)"#]
+        );
+    }
+
+    #[test]
+    fn moved_and_edited_files_are_renamed_and_changed() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        let lines: Vec<String> = (1..=10).map(|line| format!("line {}\n", line)).collect();
+        let mut edited_lines = lines.clone();
+        edited_lines[4] = String::from("edited line\n");
+
+        std::fs::write(source_dir.path().join("old.txt"), lines.concat()).unwrap();
+        std::fs::write(target_dir.path().join("moved.txt"), edited_lines.concat()).unwrap();
+        std::fs::write(source_dir.path().join("same"), "same").unwrap();
+        std::fs::write(target_dir.path().join("renamed"), "same").unwrap();
+        std::fs::write(source_dir.path().join("unrelated"), "one\ntwo\n").unwrap();
+        std::fs::write(target_dir.path().join("other"), "three\nfour\n").unwrap();
+
+        let renames = |threshold| -> Vec<String> {
+            let dir_diff = vec![
+                DirDiff::Added("moved.txt".into()),
+                DirDiff::Removed("old.txt".into()),
+                DirDiff::Added("other".into()),
+                DirDiff::Added("renamed".into()),
+                DirDiff::Removed("same".into()),
+                DirDiff::Removed("unrelated".into()),
+            ];
+
+            detect_renames(
+                dir_diff,
+                source_dir.path(),
+                &[target_dir.path().to_path_buf()],
+                threshold,
+                4096,
+            )
+            .unwrap()
+            .iter()
+            .map(|diff_fragment| format!("{:?}", diff_fragment))
+            .collect()
+        };
+
+        // 9 of the 10 lines are in both files
+        assert_eq!(
+            renames(50),
+            [
+                r#"RenamedChanged("old.txt", "moved.txt", 90)"#,
+                r#"Added("other")"#,
+                r#"Renamed("same", "renamed")"#,
+                r#"Removed("unrelated")"#,
+            ]
+        );
+
+        // below the threshold, a changed file is removed and added again
+        assert_eq!(
+            renames(95),
+            [
+                r#"Added("moved.txt")"#,
+                r#"Removed("old.txt")"#,
+                r#"Added("other")"#,
+                r#"Renamed("same", "renamed")"#,
+                r#"Removed("unrelated")"#,
+            ]
+        );
+    }
+}
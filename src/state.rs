@@ -0,0 +1,303 @@
+use std::io::Write;
+
+use crate::compare::ContentComparator;
+use crate::{DirDiffError, DirDiffFileContent};
+
+// size and modification time of a file, in nanoseconds since the epoch
+type FileStamp = (u64, u64);
+
+// canonical source and target path of a compared pair of files
+type FilePaths = (String, String);
+
+// stamps of both files and whether their content differed
+type StoredResult = (FileStamp, FileStamp, bool);
+
+type StoredResults = std::collections::HashMap<FilePaths, StoredResult>;
+
+pub struct StateComparator {
+    pub comparator: Box<dyn ContentComparator>, // the comparator used for files not in the state
+    stored_results: StoredResults,              // results of previous runs with the same options
+    state_file: std::sync::Mutex<std::fs::File>, // results are appended to it as they are found
+    fingerprint: String, // the content options of the comparator, stored with each result
+}
+
+fn file_stamp(path: &std::path::Path) -> std::io::Result<FileStamp> {
+    let metadata = std::fs::metadata(path)?;
+
+    let modified = metadata
+        .modified()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(std::io::Error::other)?;
+
+    Ok((metadata.len(), modified.as_nanos() as u64))
+}
+
+fn parse_stamp(value: &serde_json::Value) -> Option<FileStamp> {
+    Some((value.get(0)?.as_u64()?, value.get(1)?.as_u64()?))
+}
+
+fn parse_result(line: &str, fingerprint: &str) -> Option<(FilePaths, StoredResult)> {
+    // Return the stored result of a line, unless it was found with other content options
+    let record: serde_json::Value = serde_json::from_str(line).ok()?;
+
+    if record["comparator"].as_str()? != fingerprint {
+        return None;
+    }
+
+    Some((
+        (
+            record["source"].as_str()?.to_string(),
+            record["target"].as_str()?.to_string(),
+        ),
+        (
+            parse_stamp(&record["source_stamp"])?,
+            parse_stamp(&record["target_stamp"])?,
+            record["changed"].as_bool()?,
+        ),
+    ))
+}
+
+impl StateComparator {
+    pub fn open(
+        comparator: Box<dyn ContentComparator>,
+        state_file_path: &std::path::Path,
+        fingerprint: &str,
+    ) -> Result<Self, DirDiffError> {
+        // Read the results stored by previous runs, then keep the state file open to append the
+        // new ones. Results are written one line at a time, so an interrupted run only loses the
+        // comparisons in progress. A line cut short by the interruption is ignored. The
+        // fingerprint identifies the content options of the comparator: results found with other
+        // options (ignoring case, only the first lines...) are ignored
+
+        let mut stored_results = StoredResults::new();
+
+        let content = match std::fs::read_to_string(state_file_path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(source) => {
+                return Err(DirDiffError::Read {
+                    path: state_file_path.to_path_buf(),
+                    source,
+                })
+            }
+        };
+
+        // the last result of a pair is the most recent one
+        for line in content.lines() {
+            if let Some((paths, result)) = parse_result(line, fingerprint) {
+                stored_results.insert(paths, result);
+            }
+        }
+
+        let mut state_file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(state_file_path)
+            .map_err(|source| DirDiffError::Write {
+                path: state_file_path.to_path_buf(),
+                source,
+            })?;
+
+        // end the line cut short, so it doesn't swallow the next result
+        if !content.is_empty() && !content.ends_with('\n') {
+            state_file
+                .write_all(b"\n")
+                .map_err(|source| DirDiffError::Write {
+                    path: state_file_path.to_path_buf(),
+                    source,
+                })?;
+        }
+
+        Ok(StateComparator {
+            comparator,
+            stored_results,
+            state_file: std::sync::Mutex::new(state_file),
+            fingerprint: fingerprint.to_string(),
+        })
+    }
+}
+
+impl ContentComparator for StateComparator {
+    fn compare(
+        &self,
+        source_file_path: &std::path::Path,
+        target_file_path: &std::path::Path,
+    ) -> std::io::Result<DirDiffFileContent> {
+        // Reuse the stored result of the pair if neither file changed size or modification time
+        // since, and compare and store it otherwise. Pairs are stored by canonical path, so runs
+        // from other directories or through symlinks find them
+
+        let source_stamp = file_stamp(source_file_path)?;
+        let target_stamp = file_stamp(target_file_path)?;
+
+        let paths = (
+            std::fs::canonicalize(source_file_path)?
+                .to_string_lossy()
+                .into_owned(),
+            std::fs::canonicalize(target_file_path)?
+                .to_string_lossy()
+                .into_owned(),
+        );
+
+        if let Some(&(stored_source_stamp, stored_target_stamp, changed)) =
+            self.stored_results.get(&paths)
+        {
+            if stored_source_stamp == source_stamp && stored_target_stamp == target_stamp {
+                return Ok(match changed {
+                    true => DirDiffFileContent::Changed,
+                    false => DirDiffFileContent::Unchanged,
+                });
+            }
+        }
+
+        let result = self
+            .comparator
+            .compare(source_file_path, target_file_path)?;
+
+        let changed = match result {
+            DirDiffFileContent::Changed => true,
+            DirDiffFileContent::Unchanged => false,
+            DirDiffFileContent::Special | DirDiffFileContent::Inaccessible => return Ok(result),
+        };
+
+        let record = serde_json::json!({
+            "source": paths.0,
+            "target": paths.1,
+            "source_stamp": [source_stamp.0, source_stamp.1],
+            "target_stamp": [target_stamp.0, target_stamp.1],
+            "changed": changed,
+            "comparator": self.fingerprint,
+        });
+
+        // write the whole line at once so concurrent comparisons don't interleave
+        let mut state_file = self.state_file.lock().unwrap();
+        state_file.write_all(format!("{}\n", record).as_bytes())?;
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // finds every pair changed, counting the comparisons
+    struct CountingComparator {
+        calls: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl ContentComparator for CountingComparator {
+        fn compare(
+            &self,
+            _source_file_path: &std::path::Path,
+            _target_file_path: &std::path::Path,
+        ) -> std::io::Result<DirDiffFileContent> {
+            self.calls
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
+            Ok(DirDiffFileContent::Changed)
+        }
+    }
+
+    fn compare_all(
+        state_file_path: &std::path::Path,
+        fingerprint: &str,
+        pairs: &[(std::path::PathBuf, std::path::PathBuf)],
+    ) -> usize {
+        // Compare the pairs with the state file, returning how many were actually compared
+
+        let calls = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+
+        let state_comparator = StateComparator::open(
+            Box::new(CountingComparator {
+                calls: calls.clone(),
+            }),
+            state_file_path,
+            fingerprint,
+        )
+        .unwrap();
+
+        for (source_file_path, target_file_path) in pairs {
+            assert!(matches!(
+                state_comparator.compare(source_file_path, target_file_path),
+                Ok(DirDiffFileContent::Changed)
+            ));
+        }
+
+        calls.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    fn file_pairs(
+        dir: &std::path::Path,
+        count: usize,
+    ) -> Vec<(std::path::PathBuf, std::path::PathBuf)> {
+        (0..count)
+            .map(|i| {
+                let source_file_path = dir.join(format!("source{}", i));
+                let target_file_path = dir.join(format!("target{}", i));
+
+                std::fs::write(&source_file_path, "source").unwrap();
+                std::fs::write(&target_file_path, "target").unwrap();
+
+                (source_file_path, target_file_path)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn resumes_an_interrupted_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_file_path = dir.path().join("state");
+        let pairs = file_pairs(dir.path(), 3);
+
+        assert_eq!(compare_all(&state_file_path, "options", &pairs), 3);
+
+        // the run was interrupted while writing the last result
+        let content = std::fs::read_to_string(&state_file_path).unwrap();
+        std::fs::write(&state_file_path, &content[..content.len() - 10]).unwrap();
+
+        assert_eq!(compare_all(&state_file_path, "options", &pairs), 1);
+        assert_eq!(compare_all(&state_file_path, "options", &pairs), 0);
+    }
+
+    #[test]
+    fn compares_again_files_that_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_file_path = dir.path().join("state");
+        let pairs = file_pairs(dir.path(), 2);
+
+        assert_eq!(compare_all(&state_file_path, "options", &pairs), 2);
+
+        std::fs::write(&pairs[0].1, "longer target").unwrap();
+
+        assert_eq!(compare_all(&state_file_path, "options", &pairs), 1);
+    }
+
+    #[test]
+    fn ignores_results_found_with_other_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_file_path = dir.path().join("state");
+        let pairs = file_pairs(dir.path(), 2);
+
+        assert_eq!(compare_all(&state_file_path, "ignore case", &pairs), 2);
+        assert_eq!(compare_all(&state_file_path, "plain", &pairs), 2);
+        assert_eq!(compare_all(&state_file_path, "ignore case", &pairs), 0);
+    }
+
+    #[test]
+    fn finds_pairs_by_canonical_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_file_path = dir.path().join("state");
+        let pairs = file_pairs(dir.path(), 1);
+
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+
+        let other_spelling = [(
+            dir.path().join("sub/../source0"),
+            dir.path().join("./target0"),
+        )];
+
+        assert_eq!(compare_all(&state_file_path, "options", &pairs), 1);
+        assert_eq!(compare_all(&state_file_path, "options", &other_spelling), 0);
+    }
+}
@@ -0,0 +1,455 @@
+use std::io::Write;
+
+use crate::{DirDiff, DirDiffError, DirDiffFileContent};
+
+fn confirm_apply(
+    input: &mut impl std::io::BufRead,
+    target_dir: &std::path::Path,
+) -> Result<(), DirDiffError> {
+    // Ask the user to type "yes" on input before touching the target directory
+
+    eprint!(
+        "This will copy and DELETE files in {} so that it mirrors the source. Type 'yes' to continue: ",
+        target_dir.display()
+    );
+
+    // an unreadable input counts as not confirming
+    let mut answer = String::new();
+    let _ = input.read_line(&mut answer);
+
+    if answer.trim() != "yes" {
+        return Err(DirDiffError::Aborted);
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_symlink(
+    source_path: &std::path::Path,
+    target_path: &std::path::Path,
+) -> Result<(), DirDiffError> {
+    // Create a symlink at target_path pointing where the source symlink points, so relative links
+    // stay relative
+
+    let link_target = std::fs::read_link(source_path).map_err(|source| DirDiffError::Read {
+        path: source_path.to_path_buf(),
+        source,
+    })?;
+
+    std::os::unix::fs::symlink(link_target, target_path).map_err(|source| DirDiffError::Write {
+        path: target_path.to_path_buf(),
+        source,
+    })
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(
+    source_path: &std::path::Path,
+    target_path: &std::path::Path,
+) -> Result<(), DirDiffError> {
+    // Creating symlinks needs a privilege outside of unix, so the file it points to is copied
+
+    std::fs::copy(source_path, target_path)
+        .map(|_| ())
+        .map_err(|source| DirDiffError::Write {
+            path: target_path.to_path_buf(),
+            source,
+        })
+}
+
+fn copy_path(
+    source_path: &std::path::Path,
+    target_path: &std::path::Path,
+) -> Result<(), DirDiffError> {
+    // Copy a file, symlink or directory at target_path, replacing whatever was there before. A
+    // directory already there is kept with its content, only its permissions are copied. Symlinks
+    // are never followed, on either side: they are copied as symlinks, and replaced instead of
+    // writing where they point
+
+    let write_error = |source| DirDiffError::Write {
+        path: target_path.to_path_buf(),
+        source,
+    };
+
+    let read_error = |source| DirDiffError::Read {
+        path: source_path.to_path_buf(),
+        source,
+    };
+
+    let source_type = source_path
+        .symlink_metadata()
+        .map_err(read_error)?
+        .file_type();
+
+    match target_path.symlink_metadata() {
+        Ok(target_metadata) if target_metadata.is_dir() && !source_type.is_dir() => {
+            std::fs::remove_dir_all(target_path).map_err(write_error)?;
+        }
+        Ok(target_metadata)
+            if !target_metadata.is_dir()
+                && (target_metadata.file_type().is_symlink() || !source_type.is_file()) =>
+        {
+            std::fs::remove_file(target_path).map_err(write_error)?;
+        }
+        _ => {}
+    }
+
+    if source_type.is_dir() {
+        std::fs::create_dir_all(target_path).map_err(write_error)?;
+
+        let permissions = std::fs::metadata(source_path)
+            .map_err(read_error)?
+            .permissions();
+
+        std::fs::set_permissions(target_path, permissions).map_err(write_error)?;
+    } else {
+        if let Some(parent) = target_path.parent() {
+            std::fs::create_dir_all(parent).map_err(write_error)?;
+        }
+
+        if source_type.is_symlink() {
+            copy_symlink(source_path, target_path)?;
+        } else {
+            std::fs::copy(source_path, target_path).map_err(write_error)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn delete_path(path: &std::path::Path) -> Result<(), DirDiffError> {
+    // Delete a file, a symlink (not what it points to) or a whole directory. The path may already
+    // be gone if one of its parent directories was deleted before
+
+    let write_error = |source| DirDiffError::Write {
+        path: path.to_path_buf(),
+        source,
+    };
+
+    match path.symlink_metadata() {
+        Ok(metadata) if metadata.is_dir() => {
+            std::fs::remove_dir_all(path).map_err(write_error)?;
+        }
+        Ok(_) => std::fs::remove_file(path).map_err(write_error)?,
+        Err(_) => {}
+    }
+
+    Ok(())
+}
+
+fn is_inside_symlink(dir: &std::path::Path, path: &std::path::Path) -> bool {
+    // Return whether one of the parent directories of the relative path is a symlink in dir
+
+    path.ancestors()
+        .skip(1)
+        .filter(|ancestor| !ancestor.as_os_str().is_empty())
+        .any(|ancestor| dir.join(ancestor).is_symlink())
+}
+
+pub fn apply_dir_diff(
+    out: &mut impl Write,
+    dir_diff: &[DirDiff<std::path::PathBuf>],
+    source_dir: &std::path::Path,
+    target_dir: &std::path::Path,
+    skip_confirmation: bool,
+    dry_run: bool,
+) -> Result<(), DirDiffError> {
+    // Make the target directory mirror the source directory: copy paths that are only in the
+    // source, overwrite changed files and delete paths that are only in the target
+    // NOTE: this function assumes the diff is sorted, so parent directories come before their
+    // children
+
+    if !dry_run && !skip_confirmation {
+        confirm_apply(&mut std::io::stdin().lock(), target_dir)?;
+    }
+
+    let action_prefix = if dry_run { "would " } else { "" };
+
+    for diff_fragment in dir_diff {
+        match diff_fragment {
+            DirDiff::Removed(path) | DirDiff::Similar(path, Some(DirDiffFileContent::Changed)) => {
+                // never write through a symlink to a directory, the content of a copied one comes
+                // with it
+                if is_inside_symlink(source_dir, path) || is_inside_symlink(target_dir, path) {
+                    continue;
+                }
+
+                writeln!(out, "{}copy {}", action_prefix, path.display())?;
+
+                if !dry_run {
+                    copy_path(&source_dir.join(path), &target_dir.join(path))?;
+                }
+            }
+            DirDiff::Added(path) => {
+                writeln!(out, "{}delete {}", action_prefix, path.display())?;
+
+                if !dry_run {
+                    delete_path(&target_dir.join(path))?;
+                }
+            }
+            // the file is copied to its old path, and deleted from its new one
+            DirDiff::Renamed(from, to) | DirDiff::RenamedChanged(from, to, _) => {
+                writeln!(out, "{}copy {}", action_prefix, from.display())?;
+                writeln!(out, "{}delete {}", action_prefix, to.display())?;
+
+                if !dry_run {
+                    copy_path(&source_dir.join(from), &target_dir.join(from))?;
+                    delete_path(&target_dir.join(to))?;
+                }
+            }
+            // the new path is deleted first, since it is the same path on case-insensitive file
+            // systems
+            DirDiff::CaseChanged(from, to, _) => {
+                writeln!(out, "{}delete {}", action_prefix, to.display())?;
+                writeln!(out, "{}copy {}", action_prefix, from.display())?;
+
+                if !dry_run {
+                    delete_path(&target_dir.join(to))?;
+                    copy_path(&source_dir.join(from), &target_dir.join(from))?;
+                }
+            }
+            // unchanged, special, or not compared
+            DirDiff::Similar(_, _) => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(
+        dir_diff: &[DirDiff<std::path::PathBuf>],
+        source_dir: &std::path::Path,
+        target_dir: &std::path::Path,
+    ) -> String {
+        let mut out = Vec::new();
+
+        apply_dir_diff(&mut out, dir_diff, source_dir, target_dir, true, false).unwrap();
+
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn copies_changed_and_removed_files_and_deletes_added_ones() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        std::fs::create_dir(source_dir.path().join("d")).unwrap();
+        std::fs::write(source_dir.path().join("d/new"), "new").unwrap();
+        std::fs::write(source_dir.path().join("changed"), "source").unwrap();
+        std::fs::write(target_dir.path().join("changed"), "target").unwrap();
+        std::fs::write(target_dir.path().join("extra"), "extra").unwrap();
+
+        let dir_diff = vec![
+            DirDiff::Similar("changed".into(), Some(DirDiffFileContent::Changed)),
+            DirDiff::Removed("d".into()),
+            DirDiff::Removed("d/new".into()),
+            DirDiff::Added("extra".into()),
+        ];
+
+        let out = apply(&dir_diff, source_dir.path(), target_dir.path());
+
+        assert_eq!(out, "copy changed\ncopy d\ncopy d/new\ndelete extra\n");
+        assert_eq!(
+            std::fs::read_to_string(target_dir.path().join("changed")).unwrap(),
+            "source"
+        );
+        assert_eq!(
+            std::fs::read_to_string(target_dir.path().join("d/new")).unwrap(),
+            "new"
+        );
+        assert!(!target_dir.path().join("extra").exists());
+    }
+
+    #[test]
+    fn replaces_a_file_with_a_directory_and_back() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        std::fs::create_dir(source_dir.path().join("was_file")).unwrap();
+        std::fs::write(target_dir.path().join("was_file"), "file").unwrap();
+        std::fs::write(source_dir.path().join("was_dir"), "file").unwrap();
+        std::fs::create_dir(target_dir.path().join("was_dir")).unwrap();
+        std::fs::write(target_dir.path().join("was_dir/child"), "child").unwrap();
+
+        let dir_diff = vec![
+            DirDiff::Similar("was_dir".into(), Some(DirDiffFileContent::Changed)),
+            DirDiff::Similar("was_file".into(), Some(DirDiffFileContent::Changed)),
+        ];
+
+        apply(&dir_diff, source_dir.path(), target_dir.path());
+
+        assert!(target_dir.path().join("was_file").is_dir());
+        assert_eq!(
+            std::fs::read_to_string(target_dir.path().join("was_dir")).unwrap(),
+            "file"
+        );
+    }
+
+    #[test]
+    fn copies_a_directory_over_a_directory() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        std::fs::create_dir(source_dir.path().join("d")).unwrap();
+        std::fs::create_dir(target_dir.path().join("d")).unwrap();
+        std::fs::write(target_dir.path().join("d/kept"), "kept").unwrap();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            std::fs::set_permissions(
+                source_dir.path().join("d"),
+                std::fs::Permissions::from_mode(0o750),
+            )
+            .unwrap();
+        }
+
+        let dir_diff = vec![DirDiff::Similar(
+            "d".into(),
+            Some(DirDiffFileContent::Changed),
+        )];
+
+        apply(&dir_diff, source_dir.path(), target_dir.path());
+
+        // the children are compared on their own, so they are not touched
+        assert!(target_dir.path().join("d/kept").is_file());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let metadata = std::fs::metadata(target_dir.path().join("d")).unwrap();
+
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o750);
+        }
+    }
+
+    #[test]
+    fn dry_run_changes_nothing() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        std::fs::write(source_dir.path().join("f"), "f").unwrap();
+        std::fs::write(target_dir.path().join("extra"), "extra").unwrap();
+
+        let dir_diff = vec![DirDiff::Removed("f".into()), DirDiff::Added("extra".into())];
+        let mut out = Vec::new();
+
+        apply_dir_diff(
+            &mut out,
+            &dir_diff,
+            source_dir.path(),
+            target_dir.path(),
+            false,
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "would copy f\nwould delete extra\n"
+        );
+        assert!(!target_dir.path().join("f").exists());
+        assert!(target_dir.path().join("extra").exists());
+    }
+
+    #[test]
+    fn only_yes_confirms() {
+        let target_dir = std::path::Path::new("target");
+
+        assert!(confirm_apply(&mut "yes\n".as_bytes(), target_dir).is_ok());
+        assert!(matches!(
+            confirm_apply(&mut "y\n".as_bytes(), target_dir),
+            Err(DirDiffError::Aborted)
+        ));
+        assert!(matches!(
+            confirm_apply(&mut "".as_bytes(), target_dir),
+            Err(DirDiffError::Aborted)
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn copies_symlinks_as_symlinks() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+
+        std::fs::create_dir(source_dir.path().join("d")).unwrap();
+        std::fs::write(source_dir.path().join("d/f"), "f").unwrap();
+        std::os::unix::fs::symlink("d/f", source_dir.path().join("file_link")).unwrap();
+        std::os::unix::fs::symlink("d", source_dir.path().join("dir_link")).unwrap();
+
+        // the listing follows the symlink to the directory
+        let dir_diff = vec![
+            DirDiff::Removed("d".into()),
+            DirDiff::Removed("d/f".into()),
+            DirDiff::Removed("dir_link".into()),
+            DirDiff::Removed("dir_link/f".into()),
+            DirDiff::Removed("file_link".into()),
+        ];
+
+        let out = apply(&dir_diff, source_dir.path(), target_dir.path());
+
+        assert_eq!(out, "copy d\ncopy d/f\ncopy dir_link\ncopy file_link\n");
+
+        for (link, link_target) in [("file_link", "d/f"), ("dir_link", "d")] {
+            assert_eq!(
+                std::fs::read_link(target_dir.path().join(link)).unwrap(),
+                std::path::Path::new(link_target)
+            );
+        }
+
+        assert_eq!(
+            std::fs::read_to_string(target_dir.path().join("dir_link/f")).unwrap(),
+            "f"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn replaces_target_symlinks_instead_of_writing_through_them() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+        let outside_dir = tempfile::tempdir().unwrap();
+
+        std::fs::write(source_dir.path().join("f"), "source").unwrap();
+        std::fs::write(outside_dir.path().join("f"), "outside").unwrap();
+        std::os::unix::fs::symlink(outside_dir.path().join("f"), target_dir.path().join("f"))
+            .unwrap();
+
+        std::fs::create_dir(source_dir.path().join("d")).unwrap();
+        std::fs::write(source_dir.path().join("d/g"), "source").unwrap();
+        std::os::unix::fs::symlink(outside_dir.path(), target_dir.path().join("d")).unwrap();
+
+        let dir_diff = vec![
+            DirDiff::Similar("d".into(), None),
+            DirDiff::Removed("d/g".into()),
+            DirDiff::Similar("f".into(), Some(DirDiffFileContent::Changed)),
+            DirDiff::Added("link".into()),
+        ];
+
+        std::os::unix::fs::symlink(outside_dir.path(), target_dir.path().join("link")).unwrap();
+
+        apply(&dir_diff, source_dir.path(), target_dir.path());
+
+        assert!(!target_dir.path().join("f").is_symlink());
+        assert_eq!(
+            std::fs::read_to_string(target_dir.path().join("f")).unwrap(),
+            "source"
+        );
+        assert!(!target_dir.path().join("link").exists());
+
+        // nothing outside of the target directory is touched
+        assert_eq!(
+            std::fs::read_to_string(outside_dir.path().join("f")).unwrap(),
+            "outside"
+        );
+        assert!(!outside_dir.path().join("g").exists());
+    }
+}
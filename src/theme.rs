@@ -0,0 +1,96 @@
+use colored::{Color, Colorize};
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum ColorTheme {
+    Default,           // red removals, green additions, yellow changes, cyan renames
+    ColorblindSafe,    // orange removals, blue additions, magenta changes, with distinct symbols
+    MonochromeSymbols, // no colors, only bracketed symbols
+}
+
+pub struct StatusStyle {
+    pub symbol: &'static str,
+    pub color: Option<Color>, // None for themes without colors
+}
+
+impl StatusStyle {
+    pub fn paint(&self, text: &str, color: bool) -> String {
+        // Return the text in this style's color, or unchanged if colors are disabled
+
+        match self.color {
+            Some(c) if color => text.color(c).to_string(),
+            _ => text.to_string(),
+        }
+    }
+}
+
+const ORANGE: Color = Color::TrueColor {
+    r: 255,
+    g: 140,
+    b: 0,
+};
+
+impl ColorTheme {
+    pub fn removed(&self) -> StatusStyle {
+        match self {
+            ColorTheme::Default => StatusStyle {
+                symbol: "-",
+                color: Some(Color::Red),
+            },
+            ColorTheme::ColorblindSafe => StatusStyle {
+                symbol: "<",
+                color: Some(ORANGE),
+            },
+            ColorTheme::MonochromeSymbols => StatusStyle {
+                symbol: "[-]",
+                color: None,
+            },
+        }
+    }
+
+    pub fn added(&self) -> StatusStyle {
+        match self {
+            ColorTheme::Default => StatusStyle {
+                symbol: "+",
+                color: Some(Color::Green),
+            },
+            ColorTheme::ColorblindSafe => StatusStyle {
+                symbol: ">",
+                color: Some(Color::Blue),
+            },
+            ColorTheme::MonochromeSymbols => StatusStyle {
+                symbol: "[+]",
+                color: None,
+            },
+        }
+    }
+
+    pub fn changed(&self) -> StatusStyle {
+        match self {
+            ColorTheme::Default => StatusStyle {
+                symbol: "~",
+                color: Some(Color::Yellow),
+            },
+            ColorTheme::ColorblindSafe => StatusStyle {
+                symbol: "!",
+                color: Some(Color::Magenta),
+            },
+            ColorTheme::MonochromeSymbols => StatusStyle {
+                symbol: "[~]",
+                color: None,
+            },
+        }
+    }
+
+    pub fn renamed(&self) -> StatusStyle {
+        match self {
+            ColorTheme::Default | ColorTheme::ColorblindSafe => StatusStyle {
+                symbol: "R",
+                color: Some(Color::Cyan),
+            },
+            ColorTheme::MonochromeSymbols => StatusStyle {
+                symbol: "[R]",
+                color: None,
+            },
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use std::io::Write;
+
+use crate::theme::ColorTheme;
+use crate::{DirDiff, DirDiffFileContent};
+
+// a path component, with the diff of its path if it is in the diff, and its children in order
+#[derive(Default)]
+struct TreeNode<'a> {
+    name: String,
+    diff_fragment: Option<&'a DirDiff<std::path::PathBuf>>,
+    children: Vec<TreeNode<'a>>,
+    child_indexes: std::collections::HashMap<String, usize>, // index in children, by name
+}
+
+impl<'a> TreeNode<'a> {
+    fn insert(&mut self, diff_fragment: &'a DirDiff<std::path::PathBuf>) {
+        // Add the diff of a path under its parent, adding the parents missing from the diff (the
+        // similar ones, if they are hidden)
+
+        let mut node = self;
+
+        for component in diff_fragment.path().components() {
+            let name = component.as_os_str().to_string_lossy().to_string();
+
+            let index = match node.child_indexes.get(&name) {
+                Some(&index) => index,
+                None => {
+                    node.child_indexes.insert(name.clone(), node.children.len());
+                    node.children.push(TreeNode {
+                        name,
+                        ..TreeNode::default()
+                    });
+
+                    node.children.len() - 1
+                }
+            };
+
+            node = &mut node.children[index];
+        }
+
+        node.diff_fragment = Some(diff_fragment);
+    }
+}
+
+// the characters drawing the branches, for a given indentation width
+struct Branches {
+    child: String,      // before an entry followed by a sibling
+    last_child: String, // before the last entry of a directory
+    line: String,       // under an entry followed by a sibling, for the entries of its subtree
+    blank: String,      // under the last entry of a directory
+}
+
+impl Branches {
+    fn new(ascii: bool, indent_width: usize) -> Branches {
+        let (tee, corner, horizontal, vertical) = match ascii {
+            true => ("|", "`", "-", "|"),
+            false => ("├", "└", "─", "│"),
+        };
+
+        let horizontal_line = horizontal.repeat(indent_width - 2);
+
+        Branches {
+            child: format!("{}{} ", tee, horizontal_line),
+            last_child: format!("{}{} ", corner, horizontal_line),
+            line: format!("{}{}", vertical, " ".repeat(indent_width - 1)),
+            blank: " ".repeat(indent_width),
+        }
+    }
+}
+
+fn entry_label(node: &TreeNode, color: bool, theme: ColorTheme) -> String {
+    // Return the name of an entry with the symbol and color of its status, like the text format
+
+    let name = &node.name;
+
+    let diff_fragment = match node.diff_fragment {
+        Some(diff_fragment) => diff_fragment,
+        None => return name.to_string(),
+    };
+
+    let (style, label) = match diff_fragment {
+        DirDiff::Removed(_) => (theme.removed(), name.to_string()),
+        DirDiff::Added(_) => (theme.added(), name.to_string()),
+        DirDiff::Similar(_, Some(DirDiffFileContent::Changed)) => {
+            (theme.changed(), name.to_string())
+        }
+        DirDiff::Similar(_, Some(DirDiffFileContent::Special)) => {
+            return format!("{} (special)", name)
+        }
+        DirDiff::Similar(_, Some(DirDiffFileContent::Inaccessible)) => {
+            return format!("{} (inaccessible)", name)
+        }
+        DirDiff::Similar(_, _) => return name.to_string(),
+        DirDiff::Renamed(_, to) => (theme.renamed(), format!("{} -> {}", name, to.display())),
+        DirDiff::RenamedChanged(_, to, similarity) => (
+            theme.renamed(),
+            format!("{} -> {} ({}% similar)", name, to.display(), similarity),
+        ),
+        DirDiff::CaseChanged(_, to, Some(DirDiffFileContent::Changed)) => (
+            theme.renamed(),
+            format!("{} -> {} (case, changed)", name, to.display()),
+        ),
+        DirDiff::CaseChanged(_, to, _) => (
+            theme.renamed(),
+            format!("{} -> {} (case)", name, to.display()),
+        ),
+    };
+
+    format!(
+        "{} {}",
+        style.paint(style.symbol, color),
+        style.paint(&label, color)
+    )
+}
+
+fn print_children(
+    out: &mut impl Write,
+    node: &TreeNode,
+    prefix: &str,
+    branches: &Branches,
+    color: bool,
+    theme: ColorTheme,
+) -> std::io::Result<()> {
+    for (index, child) in node.children.iter().enumerate() {
+        let is_last = index + 1 == node.children.len();
+
+        let (branch, child_prefix) = match is_last {
+            true => (&branches.last_child, &branches.blank),
+            false => (&branches.child, &branches.line),
+        };
+
+        writeln!(
+            out,
+            "{}{}{}",
+            prefix,
+            branch,
+            entry_label(child, color, theme)
+        )?;
+
+        print_children(
+            out,
+            child,
+            &format!("{}{}", prefix, child_prefix),
+            branches,
+            color,
+            theme,
+        )?;
+    }
+
+    Ok(())
+}
+
+pub fn print_tree(
+    out: &mut impl Write,
+    dir_diff: &[DirDiff<std::path::PathBuf>],
+    hide_similarities: bool,
+    ascii: bool,
+    indent_width: usize,
+    color: bool,
+    theme: ColorTheme,
+) -> std::io::Result<()> {
+    // Print the diff as a tree of the paths under the compared directories, like the tree command,
+    // with each entry marked like in the text format. The branches are drawn with box-drawing
+    // characters, or ASCII ones for terminals that can't show them
+
+    let mut root = TreeNode::default();
+
+    for diff_fragment in dir_diff {
+        if hide_similarities && matches!(diff_fragment, DirDiff::Similar(..)) {
+            continue;
+        }
+
+        root.insert(diff_fragment);
+    }
+
+    writeln!(out, ".")?;
+
+    print_children(
+        out,
+        &root,
+        "",
+        &Branches::new(ascii, indent_width),
+        color,
+        theme,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(ascii: bool, indent_width: usize) -> String {
+        // Return the tree of a small diff, without colors
+
+        let dir_diff = [
+            DirDiff::Similar("dir".into(), None),
+            DirDiff::Added("dir/added".into()),
+            DirDiff::Similar("dir/sub".into(), None),
+            DirDiff::Removed("dir/sub/removed".into()),
+            DirDiff::Similar("file".into(), Some(DirDiffFileContent::Changed)),
+        ];
+
+        let mut out = Vec::new();
+        print_tree(
+            &mut out,
+            &dir_diff,
+            false,
+            ascii,
+            indent_width,
+            false,
+            ColorTheme::Default,
+        )
+        .unwrap();
+
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn draws_ascii_branches_with_ascii_tree() {
+        assert_eq!(
+            tree(false, 4),
+            ".\n\
+             ├── dir\n\
+             │   ├── + added\n\
+             │   └── sub\n\
+             │       └── - removed\n\
+             └── ~ file\n"
+        );
+
+        assert_eq!(
+            tree(true, 4),
+            ".\n\
+             |-- dir\n\
+             |   |-- + added\n\
+             |   `-- sub\n\
+             |       `-- - removed\n\
+             `-- ~ file\n"
+        );
+        assert!(tree(true, 4).is_ascii());
+
+        assert_eq!(
+            tree(true, 2),
+            ".\n\
+             | dir\n\
+             | | + added\n\
+             | ` sub\n\
+             |   ` - removed\n\
+             ` ~ file\n"
+        );
+    }
+}
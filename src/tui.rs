@@ -0,0 +1,526 @@
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+
+use crate::patch::DiffAlgorithm;
+use crate::theme::ColorTheme;
+use crate::{find_target_dir, DiffStatus, DirDiff, DirDiffError, DirDiffFileContent};
+
+// where the files of the diff are, to show their content diffs
+pub struct DiffSources<'a> {
+    pub source_dir: &'a std::path::Path,
+    pub target_dirs: &'a [std::path::PathBuf],
+    pub context: usize,           // unchanged lines around each hunk
+    pub algorithm: DiffAlgorithm, // how the lines are matched
+    pub theme: ColorTheme,        // symbols and colors of the statuses
+    pub color: bool,              // whether anything is colored
+}
+
+// what typed keys do
+#[derive(PartialEq)]
+enum Mode {
+    Browse, // move in the list, or in the content diff if it is open
+    Filter, // typed characters are added to the filter
+}
+
+struct TuiState<'a> {
+    dir_diff: Vec<&'a DirDiff<std::path::PathBuf>>, // the browsable entries
+    filter: String,                                 // only paths containing it are listed
+    visible: Vec<usize>,                            // indexes in dir_diff of the listed entries
+    list_state: ListState,
+    mode: Mode,
+    content_diff: Option<Vec<String>>, // lines of the open content diff of the selected entry
+    content_scroll: u16,               // first line of the content diff shown
+}
+
+impl<'a> TuiState<'a> {
+    fn new(dir_diff: &'a [DirDiff<std::path::PathBuf>], hide_similarities: bool) -> Self {
+        // like the other formats, hiding similarities hides changed files too
+        let dir_diff: Vec<_> = dir_diff
+            .iter()
+            .filter(|diff_fragment| {
+                !hide_similarities || !matches!(diff_fragment, DirDiff::Similar(..))
+            })
+            .collect();
+
+        let mut state = TuiState {
+            visible: (0..dir_diff.len()).collect(),
+            dir_diff,
+            filter: String::new(),
+            list_state: ListState::default(),
+            mode: Mode::Browse,
+            content_diff: None,
+            content_scroll: 0,
+        };
+
+        state
+            .list_state
+            .select((!state.visible.is_empty()).then_some(0));
+
+        state
+    }
+
+    fn selected_entry(&self) -> Option<&'a DirDiff<std::path::PathBuf>> {
+        let index = *self.visible.get(self.list_state.selected()?)?;
+        Some(self.dir_diff[index])
+    }
+
+    fn apply_filter(&mut self) {
+        // List the entries whose path contains the filter, keeping the selected one selected if
+        // it is still listed
+
+        let selected_index = self
+            .list_state
+            .selected()
+            .and_then(|position| self.visible.get(position).copied());
+
+        self.visible = (0..self.dir_diff.len())
+            .filter(|&index| {
+                self.dir_diff[index]
+                    .path()
+                    .to_string_lossy()
+                    .contains(&self.filter)
+            })
+            .collect();
+
+        let position = selected_index
+            .and_then(|selected_index| self.visible.iter().position(|&i| i == selected_index))
+            .or((!self.visible.is_empty()).then_some(0));
+
+        self.list_state.select(position);
+    }
+
+    fn move_selection(&mut self, offset: isize) {
+        if self.visible.is_empty() {
+            return;
+        }
+
+        let position = self.list_state.selected().unwrap_or(0) as isize + offset;
+        let last_position = self.visible.len() as isize - 1;
+
+        self.list_state
+            .select(Some(position.clamp(0, last_position) as usize));
+    }
+
+    fn jump_to_status(&mut self, statuses: &[DiffStatus]) {
+        // Select the next listed entry with one of the statuses, going back to the top after the
+        // last entry
+
+        let start = self
+            .list_state
+            .selected()
+            .map_or(0, |position| position + 1);
+        let count = self.visible.len();
+
+        let next_position = (0..count)
+            .map(|offset| (start + offset) % count)
+            .find(|&position| statuses.contains(&self.dir_diff[self.visible[position]].status()));
+
+        if next_position.is_some() {
+            self.list_state.select(next_position);
+        }
+    }
+
+    fn open_content_diff(&mut self, sources: &DiffSources) {
+        // Show the content diff of the selected entry, like the patch format prints it
+
+        let diff_fragment = match self.selected_entry() {
+            Some(diff_fragment) => diff_fragment,
+            None => return,
+        };
+
+        let (from, to) = match diff_fragment {
+            DirDiff::Renamed(from, to)
+            | DirDiff::RenamedChanged(from, to, _)
+            | DirDiff::CaseChanged(from, to, _) => (from, to),
+            _ => (diff_fragment.path(), diff_fragment.path()),
+        };
+
+        let source_file_path = sources.source_dir.join(from);
+        let target_file_path = find_target_dir(sources.target_dirs, to).join(to);
+
+        let (source_file_path, target_file_path) = match diff_fragment {
+            DirDiff::Removed(_) => (Some(source_file_path), None),
+            DirDiff::Added(_) => (None, Some(target_file_path)),
+            _ => (Some(source_file_path), Some(target_file_path)),
+        };
+
+        let is_dir = source_file_path
+            .iter()
+            .chain(&target_file_path)
+            .any(|path| path.is_dir());
+
+        let content_diff = if is_dir {
+            String::from("directory")
+        } else {
+            let mut patch = Vec::new();
+
+            match crate::patch::print_file_patch(
+                &mut patch,
+                (from, to),
+                source_file_path.as_deref(),
+                target_file_path.as_deref(),
+                sources.context,
+                sources.algorithm,
+            ) {
+                Ok(()) if patch.is_empty() => String::from("no differences"),
+                Ok(()) => String::from_utf8_lossy(&patch).into_owned(),
+                Err(e) => e.to_string(),
+            }
+        };
+
+        self.content_diff = Some(content_diff.lines().map(String::from).collect());
+        self.content_scroll = 0;
+    }
+
+    fn handle_key(&mut self, key: KeyEvent, sources: &DiffSources) -> bool {
+        // Update the state for a pressed key, returning whether to quit
+
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            return true;
+        }
+
+        if self.mode == Mode::Filter {
+            match key.code {
+                KeyCode::Char(c) => self.filter.push(c),
+                KeyCode::Backspace => {
+                    self.filter.pop();
+                }
+                KeyCode::Enter => self.mode = Mode::Browse,
+                KeyCode::Esc => {
+                    self.filter.clear();
+                    self.mode = Mode::Browse;
+                }
+                _ => {}
+            }
+
+            self.apply_filter();
+
+            return false;
+        }
+
+        // the content diff scrolls instead of the list while it is open
+        if self.content_diff.is_some() {
+            match key.code {
+                KeyCode::Up | KeyCode::Char('k') => {
+                    self.content_scroll = self.content_scroll.saturating_sub(1)
+                }
+                KeyCode::Down | KeyCode::Char('j') => {
+                    self.content_scroll = self.content_scroll.saturating_add(1)
+                }
+                KeyCode::PageUp => self.content_scroll = self.content_scroll.saturating_sub(20),
+                KeyCode::PageDown => self.content_scroll = self.content_scroll.saturating_add(20),
+                KeyCode::Enter | KeyCode::Esc => self.content_diff = None,
+                KeyCode::Char('q') => return true,
+                _ => {}
+            }
+
+            return false;
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return true,
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::PageUp => self.move_selection(-20),
+            KeyCode::PageDown => self.move_selection(20),
+            KeyCode::Home | KeyCode::Char('g') => self.move_selection(isize::MIN / 2),
+            KeyCode::End | KeyCode::Char('G') => self.move_selection(isize::MAX / 2),
+            KeyCode::Char('/') => self.mode = Mode::Filter,
+            KeyCode::Char('-') => self.jump_to_status(&[DiffStatus::Removed]),
+            KeyCode::Char('+') => self.jump_to_status(&[DiffStatus::Added]),
+            KeyCode::Char('~') => self.jump_to_status(&[DiffStatus::Changed]),
+            KeyCode::Char('r') => self.jump_to_status(&[
+                DiffStatus::Renamed,
+                DiffStatus::RenamedChanged,
+                DiffStatus::CaseChanged,
+            ]),
+            KeyCode::Enter => self.open_content_diff(sources),
+            _ => {}
+        }
+
+        false
+    }
+}
+
+fn terminal_color(color: Option<colored::Color>, enabled: bool) -> Color {
+    // Return the terminal color of a theme color, or the default color if colors are disabled
+
+    match color {
+        _ if !enabled => Color::Reset,
+        None => Color::Reset,
+        Some(colored::Color::Black) => Color::Black,
+        Some(colored::Color::Red) => Color::Red,
+        Some(colored::Color::Green) => Color::Green,
+        Some(colored::Color::Yellow) => Color::Yellow,
+        Some(colored::Color::Blue) => Color::Blue,
+        Some(colored::Color::Magenta) => Color::Magenta,
+        Some(colored::Color::Cyan) => Color::Cyan,
+        Some(colored::Color::White) => Color::Gray,
+        Some(colored::Color::BrightBlack) => Color::DarkGray,
+        Some(colored::Color::BrightRed) => Color::LightRed,
+        Some(colored::Color::BrightGreen) => Color::LightGreen,
+        Some(colored::Color::BrightYellow) => Color::LightYellow,
+        Some(colored::Color::BrightBlue) => Color::LightBlue,
+        Some(colored::Color::BrightMagenta) => Color::LightMagenta,
+        Some(colored::Color::BrightCyan) => Color::LightCyan,
+        Some(colored::Color::BrightWhite) => Color::White,
+        Some(colored::Color::TrueColor { r, g, b }) => Color::Rgb(r, g, b),
+    }
+}
+
+fn entry_line(
+    diff_fragment: &DirDiff<std::path::PathBuf>,
+    theme: ColorTheme,
+    color: bool,
+) -> Line<'static> {
+    // Return the line of an entry in the list, with the symbol and color of its status
+
+    let (style, text) = match diff_fragment {
+        DirDiff::Removed(path) => (Some(theme.removed()), path.display().to_string()),
+        DirDiff::Added(path) => (Some(theme.added()), path.display().to_string()),
+        DirDiff::Similar(path, Some(DirDiffFileContent::Changed)) => {
+            (Some(theme.changed()), path.display().to_string())
+        }
+        DirDiff::Similar(path, Some(DirDiffFileContent::Special)) => {
+            (None, format!("{} (special)", path.display()))
+        }
+        DirDiff::Similar(path, Some(DirDiffFileContent::Inaccessible)) => {
+            (None, format!("{} (inaccessible)", path.display()))
+        }
+        DirDiff::Similar(path, _) => (None, path.display().to_string()),
+        DirDiff::Renamed(from, to) => (
+            Some(theme.renamed()),
+            format!("{} -> {}", from.display(), to.display()),
+        ),
+        DirDiff::RenamedChanged(from, to, similarity) => (
+            Some(theme.renamed()),
+            format!(
+                "{} -> {} ({}% similar)",
+                from.display(),
+                to.display(),
+                similarity
+            ),
+        ),
+        DirDiff::CaseChanged(from, to, _) => (
+            Some(theme.renamed()),
+            format!("{} -> {} (case)", from.display(), to.display()),
+        ),
+    };
+
+    let (symbol, color) = match style {
+        Some(style) => (style.symbol, terminal_color(style.color, color)),
+        None => (" ", Color::Reset),
+    };
+
+    let style = Style::default().fg(color);
+
+    Line::from(vec![
+        Span::styled(symbol, style),
+        Span::raw(" "),
+        Span::styled(text, style),
+    ])
+}
+
+fn content_line(line: &str, theme: ColorTheme, color: bool) -> Line<'_> {
+    // Return a line of a content diff, colored like the added and removed entries
+
+    let color = match line.chars().next() {
+        Some('+') => terminal_color(theme.added().color, color),
+        Some('-') => terminal_color(theme.removed().color, color),
+        Some('@') => terminal_color(theme.renamed().color, color),
+        _ => Color::Reset,
+    };
+
+    Line::styled(line, Style::default().fg(color))
+}
+
+fn draw(frame: &mut ratatui::Frame, state: &mut TuiState, theme: ColorTheme, color: bool) {
+    let [main_area, status_area] =
+        Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+
+    match &state.content_diff {
+        Some(content_diff) => {
+            let title = state
+                .selected_entry()
+                .map(|diff_fragment| diff_fragment.path().display().to_string())
+                .unwrap_or_default();
+
+            let content = Paragraph::new(
+                content_diff
+                    .iter()
+                    .map(|line| content_line(line, theme, color))
+                    .collect::<Vec<_>>(),
+            )
+            .block(Block::bordered().title(title))
+            .scroll((state.content_scroll, 0));
+
+            frame.render_widget(content, main_area);
+        }
+        None => {
+            let items: Vec<ListItem> = state
+                .visible
+                .iter()
+                .map(|&index| ListItem::new(entry_line(state.dir_diff[index], theme, color)))
+                .collect();
+
+            let title = format!(
+                "{} of {} entries",
+                state.visible.len(),
+                state.dir_diff.len()
+            );
+
+            let list = List::new(items)
+                .block(Block::bordered().title(title))
+                .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+
+            frame.render_stateful_widget(list, main_area, &mut state.list_state);
+        }
+    }
+
+    let status = match (&state.mode, &state.content_diff) {
+        (Mode::Filter, _) => format!("/{}", state.filter),
+        (Mode::Browse, Some(_)) => String::from("up/down scroll  enter/esc back  q quit"),
+        (Mode::Browse, None) => String::from(
+            "up/down move  enter content diff  / filter  - + ~ r next of a status  q quit",
+        ),
+    };
+
+    frame.render_widget(Paragraph::new(status), status_area);
+}
+
+pub fn browse(
+    dir_diff: &[DirDiff<std::path::PathBuf>],
+    hide_similarities: bool,
+    sources: &DiffSources,
+) -> Result<(), DirDiffError> {
+    // Show the diff as a list in the terminal until the user quits. The entries can be filtered
+    // by path, and the content diff of the selected one opened
+
+    let mut state = TuiState::new(dir_diff, hide_similarities);
+
+    let mut terminal = ratatui::try_init()?;
+
+    let result = (|| -> std::io::Result<()> {
+        loop {
+            terminal.draw(|frame| draw(frame, &mut state, sources.theme, sources.color))?;
+
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && state.handle_key(key, sources) {
+                    return Ok(());
+                }
+            }
+        }
+    })();
+
+    // the terminal must be restored even if drawing failed
+    ratatui::try_restore()?;
+
+    Ok(result?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir_diff() -> Vec<DirDiff<std::path::PathBuf>> {
+        vec![
+            DirDiff::Removed("docs/old.md".into()),
+            DirDiff::Added("docs/new.md".into()),
+            DirDiff::Similar("src/main.rs".into(), Some(DirDiffFileContent::Changed)),
+            DirDiff::Similar("src/lib.rs".into(), Some(DirDiffFileContent::Unchanged)),
+            DirDiff::Similar("src".into(), None),
+            DirDiff::Renamed("a.txt".into(), "b.txt".into()),
+        ]
+    }
+
+    fn press(state: &mut TuiState, keys: &str) {
+        let sources = DiffSources {
+            source_dir: std::path::Path::new("source"),
+            target_dirs: &[],
+            context: 3,
+            algorithm: DiffAlgorithm::Myers,
+            theme: ColorTheme::Default,
+            color: true,
+        };
+
+        for c in keys.chars() {
+            let key = match c {
+                '\n' => KeyCode::Enter,
+                c => KeyCode::Char(c),
+            };
+
+            assert!(!state.handle_key(KeyEvent::from(key), &sources));
+        }
+    }
+
+    fn selected_path(state: &TuiState) -> String {
+        state.selected_entry().unwrap().path().display().to_string()
+    }
+
+    #[test]
+    fn lists_every_entry() {
+        let dir_diff = dir_diff();
+        let state = TuiState::new(&dir_diff, false);
+
+        assert_eq!(state.visible.len(), 6);
+        assert_eq!(selected_path(&state), "docs/old.md");
+    }
+
+    #[test]
+    fn hiding_similarities_hides_every_similar_entry() {
+        let dir_diff = dir_diff();
+        let state = TuiState::new(&dir_diff, true);
+
+        let paths: Vec<_> = state
+            .dir_diff
+            .iter()
+            .map(|diff_fragment| diff_fragment.path().display().to_string())
+            .collect();
+
+        assert_eq!(paths, ["docs/old.md", "docs/new.md", "a.txt"]);
+    }
+
+    #[test]
+    fn filters_and_jumps_to_statuses() {
+        let dir_diff = dir_diff();
+        let mut state = TuiState::new(&dir_diff, false);
+
+        press(&mut state, "~");
+        assert_eq!(selected_path(&state), "src/main.rs");
+
+        press(&mut state, "r");
+        assert_eq!(selected_path(&state), "a.txt");
+
+        press(&mut state, "/docs\n");
+        assert_eq!(state.visible.len(), 2);
+        assert_eq!(selected_path(&state), "docs/old.md");
+
+        press(&mut state, "+");
+        assert_eq!(selected_path(&state), "docs/new.md");
+    }
+
+    #[test]
+    fn entries_use_the_theme_colors() {
+        let removed = DirDiff::Removed("a".into());
+
+        let color_of = |theme, color| entry_line(&removed, theme, color).spans[0].style.fg;
+
+        assert_eq!(color_of(ColorTheme::Default, true), Some(Color::Red));
+        assert_eq!(
+            color_of(ColorTheme::ColorblindSafe, true),
+            Some(Color::Rgb(255, 140, 0))
+        );
+        assert_eq!(
+            color_of(ColorTheme::MonochromeSymbols, true),
+            Some(Color::Reset)
+        );
+        assert_eq!(color_of(ColorTheme::Default, false), Some(Color::Reset));
+        assert_eq!(
+            entry_line(&removed, ColorTheme::ColorblindSafe, false).spans[0].content,
+            "<"
+        );
+    }
+}
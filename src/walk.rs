@@ -0,0 +1,530 @@
+use crate::DirDiffError;
+
+#[derive(Clone)]
+pub struct WalkOptions {
+    pub depth: Option<u8>,           // only list the paths at exactly this depth
+    pub include: Vec<glob::Pattern>, // if not empty, only list the paths matching one of these
+    pub exclude: Vec<glob::Pattern>, // don't list (or enter) the paths matching one of these
+    pub exclude_dirs: Vec<glob::Pattern>, // don't list or enter directories whose name matches
+    pub hidden: bool,                // list hidden paths (starting with a dot)
+    pub follow_symlinks: bool,       // enter symlinks to directories
+    pub one_file_system: bool,       // don't enter mount points
+    pub deadline: Option<std::time::Instant>, // give up walking at this time
+}
+
+fn is_hidden(relative_path: &std::path::Path) -> bool {
+    relative_path
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+}
+
+fn skip_reason(
+    relative_path: &std::path::Path,
+    is_dir: bool,
+    options: &WalkOptions,
+) -> Option<String> {
+    // Return why the path is not listed (nor entered), if it is hidden or excluded. Directory
+    // patterns match the name of directories, wherever they are, or their whole path
+
+    if !options.hidden && is_hidden(relative_path) {
+        return Some(String::from("hidden"));
+    }
+
+    if is_dir {
+        let dir_name = std::path::Path::new(relative_path.file_name().unwrap_or_default());
+
+        if let Some(pattern) = options
+            .exclude_dirs
+            .iter()
+            .find(|pattern| pattern.matches_path(dir_name) || pattern.matches_path(relative_path))
+        {
+            return Some(format!("exclude-dir pattern {}", pattern));
+        }
+    }
+
+    options
+        .exclude
+        .iter()
+        .find(|pattern| pattern.matches_path(relative_path))
+        .map(|pattern| format!("exclude pattern {}", pattern))
+}
+
+fn is_skipped(relative_path: &std::path::Path, is_dir: bool, options: &WalkOptions) -> bool {
+    skip_reason(relative_path, is_dir, options).is_some()
+}
+
+fn is_included(relative_path: &std::path::Path, options: &WalkOptions) -> bool {
+    options.include.is_empty()
+        || options
+            .include
+            .iter()
+            .any(|pattern| pattern.matches_path(relative_path))
+}
+
+pub fn walk_dir<'a>(
+    root: &'a std::path::Path,
+    options: &'a WalkOptions,
+) -> Box<dyn Iterator<Item = Result<std::path::PathBuf, DirDiffError>> + 'a> {
+    // Return an iterator over every path inside root (relative to it), recursively, sorted by file
+    // name in each directory. Errors listing a directory are returned in the middle of the paths,
+    // and the walk continues with the other directories. With a deadline, the last item is a
+    // WalkTimeout error if the walk didn't finish in time
+
+    match options.deadline {
+        Some(deadline) => Box::new(walk_dir_until(root, options, deadline)),
+        None => Box::new(walk_entries(root, options)),
+    }
+}
+
+fn walk_dir_until(
+    root: &std::path::Path,
+    options: &WalkOptions,
+    deadline: std::time::Instant,
+) -> impl Iterator<Item = Result<std::path::PathBuf, DirDiffError>> {
+    // Walk in another thread, so the deadline is kept even if reading a directory hangs (on a
+    // network file system that went away). The thread is left behind when the deadline passes
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+
+    let thread_root = root.to_path_buf();
+    let thread_options = options.clone();
+
+    std::thread::spawn(move || {
+        for item in walk_entries(&thread_root, &thread_options) {
+            // the receiver is gone after a timeout
+            if sender.send(item).is_err() {
+                break;
+            }
+        }
+    });
+
+    receive_until(root.to_path_buf(), receiver, deadline)
+}
+
+fn receive_until(
+    root: std::path::PathBuf,
+    receiver: std::sync::mpsc::Receiver<Result<std::path::PathBuf, DirDiffError>>,
+    deadline: std::time::Instant,
+) -> impl Iterator<Item = Result<std::path::PathBuf, DirDiffError>> {
+    // Return the items of the walk of root as they are received, and a WalkTimeout error instead
+    // of the rest if they are not all received at the deadline
+
+    let mut timed_out = false;
+
+    std::iter::from_fn(move || {
+        if timed_out {
+            return None;
+        }
+
+        let timeout = deadline.saturating_duration_since(std::time::Instant::now());
+
+        match receiver.recv_timeout(timeout) {
+            Ok(item) => Some(item),
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
+                timed_out = true;
+                Some(Err(DirDiffError::WalkTimeout(root.clone())))
+            }
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => None,
+        }
+    })
+}
+
+fn walk_entries<'a>(
+    root: &'a std::path::Path,
+    options: &'a WalkOptions,
+) -> impl Iterator<Item = Result<std::path::PathBuf, DirDiffError>> + 'a {
+    let mut walk = walkdir::WalkDir::new(root)
+        .min_depth(1)
+        .follow_links(options.follow_symlinks)
+        .same_file_system(options.one_file_system)
+        .sort_by_file_name();
+
+    // with a depth, only the paths at that depth are listed. The root is depth 0 and its children
+    // depth 1. The root is never listed, so --depth 0 lists nothing
+    if let Some(depth) = options.depth {
+        walk = walk
+            .min_depth(usize::from(depth).max(1))
+            .max_depth(usize::from(depth).max(1));
+    }
+
+    let relative_path = move |path: &std::path::Path| {
+        path.strip_prefix(root)
+            .map(|relative_path| relative_path.to_path_buf())
+            .unwrap_or_else(|_| path.to_path_buf())
+    };
+
+    walk.into_iter()
+        // excluded and hidden directories are not entered either
+        .filter_entry(move |entry| {
+            !is_skipped(
+                &relative_path(entry.path()),
+                entry.file_type().is_dir(),
+                options,
+            )
+        })
+        .filter_map(move |maybe_entry| match maybe_entry {
+            Ok(entry) => {
+                let relative_path = relative_path(entry.path());
+
+                (is_included(&relative_path, options) && options.depth != Some(0))
+                    .then_some(Ok(relative_path))
+            }
+            // following a broken symlink fails, but the symlink itself is still a path to compare
+            Err(e)
+                if e.io_error()
+                    .is_some_and(|io_error| io_error.kind() == std::io::ErrorKind::NotFound)
+                    && e.path().is_some_and(|path| path.is_symlink()) =>
+            {
+                let relative_path = relative_path(e.path().unwrap());
+
+                (!is_skipped(&relative_path, false, options)
+                    && is_included(&relative_path, options))
+                .then_some(Ok(relative_path))
+            }
+            Err(e) => Some(Err(DirDiffError::Walk {
+                path: e.path().unwrap_or(root).to_path_buf(),
+                source: e.into(),
+            })),
+        })
+        // errors about the root (a broken symlink) have its path, which is empty once relative
+        .filter(|maybe_path| !matches!(maybe_path, Ok(path) if path.as_os_str().is_empty()))
+}
+
+pub fn explain_walk<'a>(
+    root: &'a std::path::Path,
+    options: &'a WalkOptions,
+) -> impl Iterator<Item = Result<(std::path::PathBuf, Option<String>), DirDiffError>> + 'a {
+    // Return every path the walk considers inside root (relative to it), with the reason it is
+    // not listed, if it isn't. The content of hidden and excluded directories is not considered
+
+    let mut walk = walkdir::WalkDir::new(root)
+        .min_depth(1)
+        .follow_links(options.follow_symlinks)
+        .same_file_system(options.one_file_system)
+        .sort_by_file_name();
+
+    // deeper paths are never listed, so they are not considered either
+    if let Some(depth) = options.depth {
+        walk = walk.max_depth(usize::from(depth).max(1));
+    }
+
+    let mut entries = walk.into_iter();
+
+    std::iter::from_fn(move || {
+        let entry = match entries.next()? {
+            Ok(entry) => entry,
+            Err(e) => {
+                return Some(Err(DirDiffError::Walk {
+                    path: e.path().unwrap_or(root).to_path_buf(),
+                    source: e.into(),
+                }))
+            }
+        };
+
+        let relative_path = entry
+            .path()
+            .strip_prefix(root)
+            .unwrap_or(entry.path())
+            .to_path_buf();
+
+        let reason = match skip_reason(&relative_path, entry.file_type().is_dir(), options) {
+            Some(reason) => {
+                if entry.file_type().is_dir() {
+                    entries.skip_current_dir();
+                }
+
+                Some(reason)
+            }
+            None if !is_included(&relative_path, options) => {
+                Some(String::from("not matching any include pattern"))
+            }
+            None => match options.depth {
+                Some(depth) if entry.depth() != usize::from(depth) => {
+                    Some(format!("not at depth {}", depth))
+                }
+                _ => None,
+            },
+        };
+
+        Some(Ok((relative_path, reason)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> WalkOptions {
+        WalkOptions {
+            depth: None,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            exclude_dirs: Vec::new(),
+            hidden: true,
+            follow_symlinks: false,
+            one_file_system: false,
+            deadline: None,
+        }
+    }
+
+    fn patterns(patterns: &[&str]) -> Vec<glob::Pattern> {
+        patterns
+            .iter()
+            .map(|pattern| glob::Pattern::new(pattern).unwrap())
+            .collect()
+    }
+
+    fn tree() -> tempfile::TempDir {
+        // Return a small tree with hidden, excluded and included paths
+
+        let root = tempfile::tempdir().unwrap();
+
+        for path in [
+            ".hidden/file",
+            "a.txt",
+            "b.log",
+            "src/lib.rs",
+            "src/target/out.txt",
+            "target/out.txt",
+        ] {
+            let file_path = root.path().join(path);
+
+            std::fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+            std::fs::write(file_path, "").unwrap();
+        }
+
+        root
+    }
+
+    fn walk(options: &WalkOptions) -> Vec<String> {
+        // Return the paths walked in the small tree with the options
+
+        walk_dir(tree().path(), options)
+            .map(|path| path.unwrap().display().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn walks_every_path_without_filters() {
+        assert_eq!(
+            walk(&options()),
+            [
+                ".hidden",
+                ".hidden/file",
+                "a.txt",
+                "b.log",
+                "src",
+                "src/lib.rs",
+                "src/target",
+                "src/target/out.txt",
+                "target",
+                "target/out.txt",
+            ]
+        );
+    }
+
+    #[test]
+    fn hidden_and_excluded_directories_are_not_entered() {
+        let options = WalkOptions {
+            hidden: false,
+            exclude_dirs: patterns(&["target"]),
+            exclude: patterns(&["*.log"]),
+            ..options()
+        };
+
+        assert_eq!(walk(&options), ["a.txt", "src", "src/lib.rs"]);
+    }
+
+    #[test]
+    fn included_paths_are_listed_at_a_depth() {
+        let options = WalkOptions {
+            include: patterns(&["*.txt", "src/*"]),
+            ..options()
+        };
+
+        assert_eq!(
+            walk(&options),
+            [
+                "a.txt",
+                "src/lib.rs",
+                "src/target",
+                "src/target/out.txt",
+                "target/out.txt"
+            ]
+        );
+
+        let options = WalkOptions {
+            depth: Some(2),
+            ..options
+        };
+
+        assert_eq!(
+            walk(&options),
+            ["src/lib.rs", "src/target", "target/out.txt"]
+        );
+
+        let options = WalkOptions {
+            depth: Some(0),
+            ..options
+        };
+
+        assert!(walk(&options).is_empty());
+    }
+
+    #[test]
+    fn names_with_glob_characters_are_walked() {
+        // the listing used to be built from glob patterns, which these names broke
+        let root = tempfile::tempdir().unwrap();
+
+        for path in ["[ab]/x", "a/x", "b/x", "star*/x", "what?", ".dotfile"] {
+            let file_path = root.path().join(path);
+
+            std::fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+            std::fs::write(file_path, "").unwrap();
+        }
+
+        let paths: Vec<String> = walk_dir(root.path(), &options())
+            .map(|path| path.unwrap().display().to_string())
+            .collect();
+
+        assert_eq!(
+            paths,
+            [".dotfile", "[ab]", "[ab]/x", "a", "a/x", "b", "b/x", "star*", "star*/x", "what?",]
+        );
+    }
+
+    #[test]
+    fn slow_walks_stop_at_the_deadline() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+
+        // a walk that hangs after its first path, like a file system that went away
+        std::thread::spawn(move || {
+            sender.send(Ok(std::path::PathBuf::from("a"))).unwrap();
+            std::thread::sleep(std::time::Duration::from_secs(10));
+            let _ = sender.send(Ok(std::path::PathBuf::from("b")));
+        });
+
+        let start = std::time::Instant::now();
+        let deadline = start + std::time::Duration::from_millis(100);
+
+        let items: Vec<String> = receive_until("root".into(), receiver, deadline)
+            .map(|item| match item {
+                Ok(path) => path.display().to_string(),
+                Err(e) => e.to_string(),
+            })
+            .collect();
+
+        assert_eq!(
+            items,
+            [
+                "a".to_string(),
+                DirDiffError::WalkTimeout("root".into()).to_string()
+            ]
+        );
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn explains_which_filter_drops_each_path() {
+        let root = tree();
+
+        let options = WalkOptions {
+            hidden: false,
+            exclude_dirs: patterns(&["target"]),
+            exclude: patterns(&["*.log", "src/*.rs"]),
+            ..options()
+        };
+
+        let explained: Vec<String> = explain_walk(root.path(), &options)
+            .map(|explained_path| match explained_path.unwrap() {
+                (path, None) => format!("kept {}", path.display()),
+                (path, Some(reason)) => format!("dropped {} ({})", path.display(), reason),
+            })
+            .collect();
+
+        // the content of dropped directories is not considered
+        assert_eq!(
+            explained,
+            [
+                "dropped .hidden (hidden)",
+                "kept a.txt",
+                "dropped b.log (exclude pattern *.log)",
+                "kept src",
+                "dropped src/lib.rs (exclude pattern src/*.rs)",
+                "dropped src/target (exclude-dir pattern target)",
+                "dropped target (exclude-dir pattern target)",
+            ]
+        );
+
+        // the kept paths are the ones the walk lists
+        assert_eq!(
+            explained
+                .iter()
+                .filter_map(|line| line.strip_prefix("kept "))
+                .collect::<Vec<_>>(),
+            walk(&options)
+        );
+    }
+
+    #[test]
+    fn depth_1_lists_the_children_of_the_root_but_not_the_root() {
+        let expected = [".hidden", "a.txt", "b.log", "src", "target"];
+
+        // patterns matching anything would also match the empty path of the root
+        for include in [&[][..], &["*"], &["**"]] {
+            let options = WalkOptions {
+                depth: Some(1),
+                include: patterns(include),
+                ..options()
+            };
+
+            assert_eq!(walk(&options), expected, "{:?}", include);
+        }
+
+        let root = tree();
+        let options = WalkOptions {
+            depth: Some(1),
+            ..options()
+        };
+
+        assert_eq!(
+            explain_walk(root.path(), &options)
+                .map(|explained_path| explained_path.unwrap().0.display().to_string())
+                .collect::<Vec<_>>(),
+            expected
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn excluded_directories_are_never_entered() {
+        let root = tempfile::tempdir().unwrap();
+
+        std::fs::create_dir_all(root.path().join("node_modules/package")).unwrap();
+        std::fs::write(root.path().join("node_modules/package/index.js"), "").unwrap();
+        std::fs::write(root.path().join("main.js"), "").unwrap();
+
+        // a symlink loop, which is an error as soon as the walk enters its directory
+        std::os::unix::fs::symlink("..", root.path().join("node_modules/package/loop")).unwrap();
+
+        let walk = |options: &WalkOptions| -> Result<Vec<String>, DirDiffError> {
+            walk_dir(root.path(), options)
+                .map(|path| Ok(path?.display().to_string()))
+                .collect()
+        };
+
+        let options = WalkOptions {
+            follow_symlinks: true,
+            ..options()
+        };
+
+        assert!(matches!(walk(&options), Err(DirDiffError::Walk { .. })));
+
+        let options = WalkOptions {
+            exclude_dirs: patterns(&["node_modules"]),
+            ..options
+        };
+
+        assert_eq!(walk(&options).unwrap(), ["main.js"]);
+    }
+}
@@ -0,0 +1,597 @@
+// end-to-end tests of the behavior only visible from the command line (stdin, stdout, exit codes)
+
+fn dirdiff(args: &[&std::ffi::OsStr], stdin: &[u8]) -> (String, String, i32) {
+    // Run dirdiff with the arguments and stdin, and return its stdout, stderr and exit code
+
+    use std::io::Write;
+
+    let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_dirdiff"))
+        .args(args)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+
+    let output = child.wait_with_output().unwrap();
+
+    (
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+        output.status.code().unwrap(),
+    )
+}
+
+fn write_files(dir: &std::path::Path, files: &[&str]) {
+    for path in files {
+        std::fs::write(dir.join(path), path).unwrap();
+    }
+}
+
+#[test]
+fn batch_labels_the_diff_of_each_pair() {
+    let dirs = [(); 4].map(|_| tempfile::tempdir().unwrap());
+    let [source_1, target_1, source_2, target_2] = dirs.each_ref().map(|dir| dir.path());
+
+    write_files(source_1, &["removed"]);
+    write_files(target_2, &["added"]);
+
+    let stdin = format!(
+        "{}\t{}\n{}\t{}\n",
+        source_1.display(),
+        target_1.display(),
+        source_2.display(),
+        target_2.display()
+    );
+
+    let (stdout, _, exit_code) = dirdiff(&["--batch".as_ref()], stdin.as_bytes());
+
+    assert_eq!(
+        stdout,
+        format!(
+            "==> {} {} <==\n- removed\n1 removed, 0 added, 0 similar\n\
+             ==> {} {} <==\n+ added\n0 removed, 1 added, 0 similar\n",
+            source_1.display(),
+            target_1.display(),
+            source_2.display(),
+            target_2.display()
+        )
+    );
+    assert_eq!(exit_code, 0);
+
+    // the exit code is the highest of the pairs
+    let (_, _, exit_code) = dirdiff(
+        &["--batch".as_ref(), "--fail-on".as_ref(), "added".as_ref()],
+        stdin.as_bytes(),
+    );
+
+    assert_eq!(exit_code, 1);
+}
+
+#[test]
+fn missing_target_is_empty_with_ignore_missing_dir() {
+    let source_dir = tempfile::tempdir().unwrap();
+    let parent_dir = tempfile::tempdir().unwrap();
+    let missing_dir = parent_dir.path().join("missing");
+
+    std::fs::create_dir(source_dir.path().join("dir")).unwrap();
+    write_files(source_dir.path(), &["a", "dir/b"]);
+
+    let args = [source_dir.path().as_os_str(), missing_dir.as_os_str()];
+
+    // an error without the option
+    let (stdout, _, exit_code) = dirdiff(&args, b"");
+
+    assert_eq!(stdout, "");
+    assert_ne!(exit_code, 0);
+
+    let (stdout, _, exit_code) = dirdiff(
+        &[&args[..], &["--ignore-missing-dir".as_ref()]].concat(),
+        b"",
+    );
+
+    assert_eq!(
+        stdout,
+        "- a\n- dir\n- dir/b\n3 removed, 0 added, 0 similar\n"
+    );
+    assert_eq!(exit_code, 0);
+}
+
+#[test]
+fn timing_prints_each_phase_on_stderr() {
+    let source_dir = tempfile::tempdir().unwrap();
+    let target_dir = tempfile::tempdir().unwrap();
+
+    write_files(source_dir.path(), &["a"]);
+    write_files(target_dir.path(), &["a"]);
+
+    let args = [source_dir.path().as_os_str(), target_dir.path().as_os_str()];
+
+    let (stdout, stderr, _) = dirdiff(
+        &[&args[..], &["-f".as_ref(), "--timing".as_ref()]].concat(),
+        b"",
+    );
+
+    // the diff on stdout is left alone
+    assert_eq!(
+        stdout,
+        " a\n0 removed, 0 added, 0 similar, 0 files changed, 1 files unchanged, 0 special files\n"
+    );
+
+    let phases: Vec<&str> = stderr
+        .lines()
+        .map(|line| {
+            let (phase, duration) = line.split_once(": ").unwrap();
+            assert!(duration.ends_with("ms"), "{}", line);
+            phase
+        })
+        .collect();
+
+    assert_eq!(phases, ["walk", "sort", "compare", "output", "total"]);
+
+    let (_, stderr, _) = dirdiff(&args, b"");
+
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn no_summary_only_prints_the_entries() {
+    let source_dir = tempfile::tempdir().unwrap();
+    let target_dir = tempfile::tempdir().unwrap();
+
+    write_files(source_dir.path(), &["removed"]);
+    write_files(target_dir.path(), &["added"]);
+
+    let args = [source_dir.path().as_os_str(), target_dir.path().as_os_str()];
+
+    let (stdout, _, _) = dirdiff(&args, b"");
+
+    assert_eq!(
+        stdout,
+        "+ added\n- removed\n1 removed, 1 added, 0 similar\n"
+    );
+
+    let (stdout, _, _) = dirdiff(&[&args[..], &["--no-summary".as_ref()]].concat(), b"");
+
+    assert_eq!(stdout, "+ added\n- removed\n");
+}
+
+#[test]
+fn same_directory_twice_is_all_similar() {
+    let dir = tempfile::tempdir().unwrap();
+
+    std::fs::create_dir(dir.path().join("dir")).unwrap();
+    write_files(dir.path(), &["a", "dir/b"]);
+
+    // the same directory, spelled differently
+    let other_path = dir.path().join("dir").join("..");
+
+    let (stdout, stderr, exit_code) = dirdiff(
+        &[
+            dir.path().as_os_str(),
+            other_path.as_os_str(),
+            "-f".as_ref(),
+        ],
+        b"",
+    );
+
+    assert_eq!(
+        stdout,
+        " a\n  dir\n dir/b\n0 removed, 0 added, 1 similar, 0 files changed, 2 files unchanged, \
+         0 special files\n"
+    );
+    // the paths are printed once normalized
+    assert_eq!(
+        stderr,
+        format!(
+            "note: {} and {} are the same directory\n",
+            dir.path().display(),
+            dir.path().display()
+        )
+    );
+    assert_eq!(exit_code, 0);
+
+    // two missing directories are both empty, not the same directory
+    let missing_dir = dir.path().join("missing");
+
+    let (stdout, stderr, exit_code) = dirdiff(
+        &[
+            missing_dir.as_os_str(),
+            missing_dir.as_os_str(),
+            "--ignore-missing-dir".as_ref(),
+        ],
+        b"",
+    );
+
+    assert_eq!(stdout, "0 removed, 0 added, 0 similar\n");
+    assert_eq!(stderr, "");
+    assert_eq!(exit_code, 0);
+}
+
+#[test]
+fn show_hashes_prints_the_hashes_of_the_files() {
+    use sha2::Digest;
+
+    let source_dir = tempfile::tempdir().unwrap();
+    let target_dir = tempfile::tempdir().unwrap();
+
+    std::fs::write(source_dir.path().join("changed"), "old").unwrap();
+    std::fs::write(target_dir.path().join("changed"), "new").unwrap();
+    write_files(source_dir.path(), &["same"]);
+    write_files(target_dir.path(), &["same"]);
+
+    let hash = |content: &str| {
+        sha2::Sha256::digest(content)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>()
+    };
+
+    let args = [
+        source_dir.path().as_os_str(),
+        target_dir.path().as_os_str(),
+        "-f".as_ref(),
+        "--show-hashes".as_ref(),
+    ];
+
+    let (stdout, _, _) = dirdiff(&args, b"");
+
+    assert_eq!(
+        stdout.lines().take(2).collect::<Vec<_>>(),
+        [
+            format!("~ changed ({} -> {})", hash("old"), hash("new")),
+            format!(" same ({})", hash("same")),
+        ]
+    );
+
+    let (stdout, _, _) = dirdiff(
+        &[&args[..], &["--format".as_ref(), "json".as_ref()]].concat(),
+        b"",
+    );
+    let output: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+
+    assert_eq!(
+        output["entries"],
+        serde_json::json!([
+            {
+                "path": "changed",
+                "status": "changed",
+                "source_hash": hash("old"),
+                "target_hash": hash("new"),
+            },
+            {"path": "same", "status": "unchanged", "hash": hash("same")},
+        ])
+    );
+}
+
+#[test]
+fn final_newline_differences_are_noted_or_ignored() {
+    let source_dir = tempfile::tempdir().unwrap();
+    let target_dir = tempfile::tempdir().unwrap();
+
+    for (path, source_content, target_content) in [
+        ("crlf", "x", "x\r\n"),
+        ("edited", "x", "y\n"),
+        ("newline", "x", "x\n"),
+    ] {
+        std::fs::write(source_dir.path().join(path), source_content).unwrap();
+        std::fs::write(target_dir.path().join(path), target_content).unwrap();
+    }
+
+    let args = [
+        source_dir.path().as_os_str(),
+        target_dir.path().as_os_str(),
+        "-f".as_ref(),
+        "--no-summary".as_ref(),
+    ];
+
+    let (stdout, _, _) = dirdiff(&args, b"");
+
+    assert_eq!(
+        stdout,
+        "~ crlf (final newline)\n~ edited\n~ newline (final newline)\n"
+    );
+
+    let (stdout, _, _) = dirdiff(
+        &[&args[..], &["--ignore-final-newline".as_ref()]].concat(),
+        b"",
+    );
+
+    assert_eq!(stdout, " crlf\n~ edited\n newline\n");
+}
+
+#[test]
+fn changed_files_only_prints_the_added_and_changed_paths() {
+    let source_dir = tempfile::tempdir().unwrap();
+    let target_dir = tempfile::tempdir().unwrap();
+
+    for dir in [source_dir.path(), target_dir.path()] {
+        std::fs::create_dir(dir.join("dir")).unwrap();
+        write_files(dir, &["dir/same", "same"]);
+        std::fs::write(dir.join("changed"), dir.as_os_str().as_encoded_bytes()).unwrap();
+    }
+
+    write_files(source_dir.path(), &["removed"]);
+    std::fs::create_dir(target_dir.path().join("new dir")).unwrap();
+    write_files(target_dir.path(), &["added", "new dir/added"]);
+
+    let args = [
+        source_dir.path().as_os_str(),
+        target_dir.path().as_os_str(),
+        "--changed-files-only".as_ref(),
+    ];
+
+    // the added directory is left out, its files are listed
+    let (stdout, stderr, exit_code) = dirdiff(&args, b"");
+
+    assert_eq!(stdout, "added\nchanged\nnew dir/added\n");
+    assert_eq!(stderr, "");
+    assert_eq!(exit_code, 0);
+
+    let (stdout, _, _) = dirdiff(&[&args[..], &["--print0".as_ref()]].concat(), b"");
+
+    assert_eq!(stdout, "added\0changed\0new dir/added\0");
+}
+
+#[test]
+fn errors_are_json_objects_with_the_json_formats() {
+    let parent_dir = tempfile::tempdir().unwrap();
+    let missing_dir = parent_dir.path().join("missing");
+
+    let args = [missing_dir.as_os_str(), parent_dir.path().as_os_str()];
+    let message = format!("{} does not exist", missing_dir.display());
+
+    for format in ["json", "json-tree"] {
+        let (stdout, stderr, exit_code) = dirdiff(
+            &[&args[..], &["--format".as_ref(), format.as_ref()]].concat(),
+            b"",
+        );
+
+        let error: serde_json::Value = serde_json::from_str(&stderr).unwrap();
+
+        assert_eq!(error, serde_json::json!({ "error": message }));
+        assert_eq!(stdout, "");
+        assert_ne!(exit_code, 0);
+    }
+
+    // the other formats print the message as text
+    let (_, stderr, _) = dirdiff(&args, b"");
+
+    assert_eq!(stderr, format!("Error: {}\n", message));
+}
+
+#[cfg(unix)]
+#[test]
+fn show_perms_prints_the_modes_of_similar_paths() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let source_dir = tempfile::tempdir().unwrap();
+    let target_dir = tempfile::tempdir().unwrap();
+
+    for dir in [source_dir.path(), target_dir.path()] {
+        std::fs::create_dir(dir.join("dir")).unwrap();
+        write_files(dir, &["executable", "same"]);
+    }
+    write_files(source_dir.path(), &["removed"]);
+
+    let set_mode = |path: std::path::PathBuf, mode: u32| {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).unwrap()
+    };
+
+    set_mode(source_dir.path().join("dir"), 0o750);
+    set_mode(target_dir.path().join("dir"), 0o750);
+    set_mode(source_dir.path().join("executable"), 0o644);
+    set_mode(target_dir.path().join("executable"), 0o755);
+    set_mode(source_dir.path().join("same"), 0o600);
+    set_mode(target_dir.path().join("same"), 0o600);
+
+    let args = [
+        source_dir.path().as_os_str(),
+        target_dir.path().as_os_str(),
+        "-f".as_ref(),
+        "--show-perms".as_ref(),
+        "--no-summary".as_ref(),
+    ];
+
+    // a mode change is not a change of the content
+    let (stdout, _, _) = dirdiff(&args, b"");
+
+    assert_eq!(
+        stdout,
+        "  dir (mode 750)\n executable (mode 644 -> 755)\n- removed\n same (mode 600)\n"
+    );
+
+    // without the option
+    let (stdout, _, _) = dirdiff(&args[..3], b"");
+
+    assert!(!stdout.contains("mode"), "{}", stdout);
+}
+
+#[cfg(unix)]
+#[test]
+fn symlink_to_a_file_is_not_a_source_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    let other_dir = tempfile::tempdir().unwrap();
+
+    write_files(dir.path(), &["file"]);
+    std::os::unix::fs::symlink("file", dir.path().join("link")).unwrap();
+    std::os::unix::fs::symlink(other_dir.path(), dir.path().join("dir_link")).unwrap();
+
+    let link = dir.path().join("link");
+
+    let (stdout, stderr, exit_code) = dirdiff(&[link.as_os_str(), dir.path().as_os_str()], b"");
+
+    assert_eq!(stdout, "");
+    assert_eq!(
+        stderr,
+        format!(
+            "Error: {} is a symlink to file, which is not a directory\n",
+            link.display()
+        )
+    );
+    assert_ne!(exit_code, 0);
+
+    // a symlink to a directory is followed, to the same directory as the target
+    let (_, stderr, exit_code) = dirdiff(
+        &[
+            dir.path().join("dir_link").as_os_str(),
+            other_dir.path().as_os_str(),
+        ],
+        b"",
+    );
+
+    assert!(stderr.ends_with("are the same directory\n"), "{}", stderr);
+    assert_eq!(exit_code, 0);
+}
+
+#[test]
+fn paths_from_only_compares_the_listed_paths() {
+    let source_dir = tempfile::tempdir().unwrap();
+    let target_dir = tempfile::tempdir().unwrap();
+    let list_dir = tempfile::tempdir().unwrap();
+
+    for dir in [source_dir.path(), target_dir.path()] {
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        write_files(dir, &["sub/same"]);
+        std::fs::write(dir.join("changed"), dir.as_os_str().as_encoded_bytes()).unwrap();
+        std::fs::write(dir.join("unlisted"), dir.as_os_str().as_encoded_bytes()).unwrap();
+    }
+
+    write_files(source_dir.path(), &["sub/removed", "unlisted_removed"]);
+    write_files(target_dir.path(), &["unlisted_added"]);
+
+    // as an SCM would print them
+    let list_path = list_dir.path().join("paths");
+    std::fs::write(&list_path, "./changed\nsub/removed\n").unwrap();
+
+    let (stdout, stderr, _) = dirdiff(
+        &[
+            source_dir.path().as_os_str(),
+            target_dir.path().as_os_str(),
+            "-f".as_ref(),
+            "--paths-from".as_ref(),
+            list_path.as_os_str(),
+        ],
+        b"",
+    );
+
+    assert_eq!(
+        stdout,
+        "~ changed\n- sub/removed\n1 removed, 0 added, 0 similar, 1 files changed, \
+         0 files unchanged, 0 special files\n"
+    );
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn stable_output_is_the_same_bytes_on_every_run() {
+    let source_dir = tempfile::tempdir().unwrap();
+    let target_dir = tempfile::tempdir().unwrap();
+
+    // enough files for the listings and the comparison threads to come back in any order
+    for i in 0..200 {
+        let name = format!("file_{}", i);
+        let content = if i % 3 == 0 { "changed" } else { "same" };
+
+        std::fs::write(source_dir.path().join(&name), "same").unwrap();
+        std::fs::write(target_dir.path().join(&name), content).unwrap();
+    }
+
+    write_files(source_dir.path(), &["Removed", "é_removed"]);
+    write_files(target_dir.path(), &["added", "e_added"]);
+
+    let args = [
+        source_dir.path().as_os_str(),
+        target_dir.path().as_os_str(),
+        "-f".as_ref(),
+        "--threads-io".as_ref(),
+        "8".as_ref(),
+        "--stable-output".as_ref(),
+    ];
+
+    let (first_stdout, first_stderr, _) = dirdiff(&args, b"");
+    let (second_stdout, second_stderr, _) = dirdiff(&args, b"");
+
+    assert!(first_stdout.contains("~ file_0\n"));
+    assert!(!first_stdout.contains('\x1b'));
+    assert_eq!(first_stdout, second_stdout);
+    assert_eq!(first_stderr, second_stderr);
+}
+
+#[test]
+fn counts_prints_the_files_and_directories_of_each_side() {
+    let source_dir = tempfile::tempdir().unwrap();
+    let target_dir = tempfile::tempdir().unwrap();
+
+    std::fs::create_dir_all(source_dir.path().join("sub/empty")).unwrap();
+    write_files(source_dir.path(), &["same", "sub/removed", "sub/other"]);
+
+    std::fs::create_dir(target_dir.path().join("sub")).unwrap();
+    write_files(target_dir.path(), &["same"]);
+
+    let (stdout, stderr, _) = dirdiff(
+        &[
+            source_dir.path().as_os_str(),
+            target_dir.path().as_os_str(),
+            "--counts".as_ref(),
+        ],
+        b"",
+    );
+
+    assert_eq!(
+        stdout.lines().next(),
+        Some("source: 3 files, 2 dirs; target: 1 files, 1 dirs")
+    );
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn rules_compare_each_file_type_with_its_own_mode() {
+    let source_dir = tempfile::tempdir().unwrap();
+    let target_dir = tempfile::tempdir().unwrap();
+
+    let files = [
+        // the same values, reformatted
+        (
+            "config.json",
+            "{\"a\": 1, \"b\": [1, 2]}",
+            "{\n  \"b\": [1, 2],\n  \"a\": 1\n}\n",
+        ),
+        // only the timestamped lines differ
+        (
+            "app.log",
+            "2024-01-01 started\nstatus ok\n",
+            "2025-06-30 started\nstatus ok\n",
+        ),
+        ("error.log", "status ok\n", "status failed\n"),
+        // no rule for text files
+        ("notes.txt", "2024-01-01\n", "2025-06-30\n"),
+    ];
+
+    for (path, source_content, target_content) in files {
+        std::fs::write(source_dir.path().join(path), source_content).unwrap();
+        std::fs::write(target_dir.path().join(path), target_content).unwrap();
+    }
+
+    let (stdout, stderr, _) = dirdiff(
+        &[
+            source_dir.path().as_os_str(),
+            target_dir.path().as_os_str(),
+            "-f".as_ref(),
+            "--rule".as_ref(),
+            "*.json:json-aware".as_ref(),
+            "--rule".as_ref(),
+            r"*.log:ignore-lines-matching=\d{4}-\d\d-\d\d".as_ref(),
+        ],
+        b"",
+    );
+
+    assert_eq!(
+        stdout,
+        " app.log\n config.json\n~ error.log\n~ notes.txt\n0 removed, 0 added, 0 similar, \
+         2 files changed, 2 files unchanged, 0 special files\n"
+    );
+    assert_eq!(stderr, "");
+}